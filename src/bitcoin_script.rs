@@ -1,12 +1,67 @@
 use crate::structures::tagged_hash::{HashTag, TaggedHashGadget};
 use crate::treepp::*;
-use crate::utils::pseudo::{OP_CAT2, OP_CAT3, OP_CAT4, OP_HINT};
+use crate::utils::hint_schema::{HintSchema, HintSize};
+use crate::utils::pseudo::{OP_CAT2, OP_CAT3, OP_CAT4};
+use crate::utils::stack_builder::StackBuilder;
 use crate::wizards::{tap_csv_preimage, tx};
 use crate::DUST_AMOUNT;
 use crate::SECP256K1_GENERATOR;
+use anyhow::{ensure, Result};
 use bitcoin::absolute::LockTime;
 use bitcoin::transaction::Version;
-use bitcoin::{Amount, Sequence, TapSighashType};
+use bitcoin::{Amount, Sequence, TapSighashType, TxOut};
+
+/// The hints in the witness of a covenant leaf, in the order of [`CovenantHintSchema`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CovenantHint {
+    /// The new balance (8 bytes).
+    NewBalance,
+    /// This script's script pub key (34 bytes).
+    ScriptPubKey,
+    /// The new state hash (32 bytes).
+    NewStateHash,
+    /// The old state hash (32 bytes).
+    OldStateHash,
+    /// The randomizer for this transaction (4 bytes).
+    Randomizer,
+    /// The annex without the 0x50 prefix (an empty string if the annex is not present).
+    Annex,
+    /// The previous transaction's txid (32 bytes).
+    OldTxid,
+    /// The previous transaction's amount (8 bytes).
+    OldAmount,
+    /// The deposit outpoint (an empty string if there is no deposit input).
+    DepositOutPoint,
+    /// The deposit amount (8 bytes, only if there is a deposit input).
+    DepositAmount,
+    /// The deposit script pub key (only if there is a deposit input).
+    DepositScriptPubKey,
+    /// The deposit sequence (4 bytes, only if there is a deposit input).
+    DepositSequence,
+    /// The tap leaf hash (32 bytes).
+    TapLeafHash,
+    /// The SHA256 BIP-340 challenge hash without the last byte (31 bytes).
+    Challenge,
+    /// The first outpoint of the previous transaction (36 bytes).
+    FirstOutPoint,
+    /// The second outpoint of the previous transaction (an empty string if not present).
+    SecondOutPoint,
+    /// The randomizer for the previous transaction (4 bytes).
+    OldRandomizer,
+    /// The old state of the application (a group of elements, which the application pulls).
+    OldState,
+    /// The new state of the application (a group of elements, which the application pulls).
+    NewState,
+    /// The input of the application (a group of elements, which the application pulls).
+    Input,
+}
+
+/// The schema of the hints that the covenant consumes.
+pub type CovenantHintSchema = HintSchema<CovenantHint>;
+
+/// The outputs of the covenants in this module are fixed, and they fit in 520 bytes, so hashing
+/// them does not fail (see [`step3_sha_outputs`]).
+const FIXED_OUTPUTS_FIT: &str = "the fixed outputs of the covenant fit in 520 bytes";
 
 /// Step 1: Create the beginning part of the preimage.
 ///
@@ -14,13 +69,17 @@ use bitcoin::{Amount, Sequence, TapSighashType};
 /// - preimage_head
 ///
 pub fn step1() -> Script {
+    step1_with_version(&Version::TWO, &TapSighashType::AllPlusAnyoneCanPay)
+}
+
+fn step1_with_version(version: &Version, hash_type: &TapSighashType) -> Script {
     script! {
         // For more information about the construction of the Tap CheckSigVerify Preimage, please
         // check out the `covenants-gadgets` repository.
 
         { tap_csv_preimage::Step1EpochGadget::default() }
-        { tap_csv_preimage::Step2HashTypeGadget::from_constant(&TapSighashType::AllPlusAnyoneCanPay) }
-        { tap_csv_preimage::Step3VersionGadget::from_constant(&Version::TWO) }
+        { tap_csv_preimage::Step2HashTypeGadget::from_constant(hash_type) }
+        { tap_csv_preimage::Step3VersionGadget::from_constant(version) }
         { tap_csv_preimage::Step4LockTimeGadget::from_constant_absolute(&LockTime::ZERO) }
         OP_CAT4
     }
@@ -42,14 +101,16 @@ pub fn step1() -> Script {
 /// - dust for second_output
 ///
 pub fn step2() -> Script {
+    step2_with_schema(&mut CovenantHintSchema::new())
+}
+
+fn step2_with_schema(schema: &mut CovenantHintSchema) -> Script {
     script! {
         // get a hint: new balance (8 bytes)
-        OP_HINT
-        OP_SIZE 8 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::NewBalance, HintSize::Exact(8)) }
 
         // get a hint: this script's scriptpubkey (34 bytes)
-        OP_HINT
-        OP_SIZE 34 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::ScriptPubKey, HintSize::Exact(34)) }
 
         // save pubkey to the altstack
         OP_DUP OP_TOALTSTACK
@@ -88,26 +149,61 @@ pub fn step2() -> Script {
 /// - old_state_hash
 ///
 pub fn step3() -> Script {
-    script! {
+    step3_with_extra_outputs(&[], &mut CovenantHintSchema::new()).expect(FIXED_OUTPUTS_FIT)
+}
+
+fn step3_with_extra_outputs(
+    extra_outputs: &[TxOut],
+    schema: &mut CovenantHintSchema,
+) -> Result<Script> {
+    let sha_outputs = step3_sha_outputs(extra_outputs, schema)?;
+    Ok(script! {
+        { sha_outputs }
+        OP_ROT OP_SWAP OP_CAT2
+
+        OP_FROMALTSTACK OP_SWAP
+    })
+}
+
+/// Compute the hash of the outputs, which leaves the stack as follows:
+/// - preimage_head
+/// - pubkey
+/// - Hash(first output | second_output)
+///
+/// The outputs are hashed with a single `OP_SHA256`, so it returns an error if the outputs with
+/// the extra outputs exceed 520 bytes. Hashing in chunks with
+/// [`crate::utils::sha256::Sha256StreamBuilder`] is not available on-chain, since it exceeds the
+/// size of a standard leaf.
+fn step3_sha_outputs(extra_outputs: &[TxOut], schema: &mut CovenantHintSchema) -> Result<Script> {
+    // the first output and the caboose take 43 bytes each
+    let outputs_len = 43
+        + 43
+        + extra_outputs
+            .iter()
+            .map(|extra_output| bitcoin::consensus::serialize(extra_output).len())
+            .sum::<usize>();
+    ensure!(
+        outputs_len <= 520,
+        "the outputs of the covenant transaction exceed 520 bytes"
+    );
+
+    Ok(script! {
         // script hash header
         OP_PUSHBYTES_2 OP_RETURN OP_PUSHBYTES_36
 
         // get a hint: the new state hash
-        OP_HINT
-        OP_SIZE 32 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::NewStateHash, HintSize::Exact(32)) }
         // save the new state hash to the altstack
         OP_DUP OP_TOALTSTACK
 
         // get a hint: the old state hash
-        OP_HINT
-        OP_SIZE 32 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::OldStateHash, HintSize::Exact(32)) }
         // save the old state hash in the altstack for later use
         OP_DUP OP_TOALTSTACK
         OP_TOALTSTACK
 
         // get a hint: the randomizer for this transaction (4 bytes)
-        OP_HINT
-        OP_SIZE 4 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::Randomizer, HintSize::Exact(4)) }
         OP_CAT3
 
         OP_SHA256
@@ -115,11 +211,13 @@ pub fn step3() -> Script {
         OP_PUSHBYTES_3 OP_PUSHBYTES_34 OP_PUSHBYTES_0 OP_PUSHBYTES_32
         OP_SWAP OP_CAT3
 
-        OP_SHA256
-        OP_ROT OP_SWAP OP_CAT2
+        for extra_output in extra_outputs.iter() {
+            { tx::Step5OutputGadget::from_constant(extra_output) }
+            OP_CAT
+        }
 
-        OP_FROMALTSTACK OP_SWAP
-    }
+        OP_SHA256
+    })
 }
 
 /// Step 4: provide the original data of the input.
@@ -145,12 +243,20 @@ pub fn step3() -> Script {
 /// - old_state_hash
 ///
 pub fn step4() -> Script {
+    step4_with_schema(&mut CovenantHintSchema::new())
+}
+
+fn step4_with_schema(schema: &mut CovenantHintSchema) -> Script {
     script! {
         { tap_csv_preimage::Step7SpendTypeGadget::from_constant(1, false) } OP_CAT2
+        { step4_this_input(schema) }
+    }
+}
 
+fn step4_this_input(schema: &mut CovenantHintSchema) -> Script {
+    script! {
         // get a hint: previous tx's txid
-        OP_HINT
-        OP_SIZE 32 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::OldTxid, HintSize::Exact(32)) }
 
         // save a copy to altstack
         OP_DUP OP_TOALTSTACK
@@ -160,8 +266,7 @@ pub fn step4() -> Script {
         OP_CAT3
 
         // get a hint: previous tx's amount
-        OP_HINT
-        OP_SIZE 8 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::OldAmount, HintSize::Exact(8)) }
         OP_DUP OP_TOALTSTACK
         OP_CAT2
 
@@ -179,7 +284,7 @@ pub fn step4() -> Script {
     }
 }
 
-/// Step 5: provide the extension data.
+/// Step 5: provide the extension data, where no OP_CODESEPARATOR has been executed.
 ///
 /// Hint:
 /// - tap leaf hash
@@ -203,13 +308,26 @@ pub fn step4() -> Script {
 /// - old_state_hash
 ///
 pub fn step5() -> Script {
+    step5_with_code_sep_pos(None)
+}
+
+/// Step 5: same as [`step5`], but with the position of the last OP_CODESEPARATOR executed before
+/// the covenant, if any.
+pub fn step5_with_code_sep_pos(code_sep_pos: Option<u32>) -> Script {
+    step5_with_schema(code_sep_pos, &mut CovenantHintSchema::new())
+}
+
+fn step5_with_schema(code_sep_pos: Option<u32>, schema: &mut CovenantHintSchema) -> Script {
     script! {
         // get a hint: tap leaf hash
-        OP_HINT
-        OP_SIZE 32 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::TapLeafHash, HintSize::Exact(32)) }
 
         { tap_csv_preimage::step12_ext::Step2KeyVersionGadget::from_constant(0) }
-        { tap_csv_preimage::step12_ext::Step3CodeSepPosGadget::no_code_sep_executed() }
+        if code_sep_pos.is_some() {
+            { tap_csv_preimage::step12_ext::Step3CodeSepPosGadget::from_constant(code_sep_pos.unwrap()) }
+        } else {
+            { tap_csv_preimage::step12_ext::Step3CodeSepPosGadget::no_code_sep_executed() }
+        }
         OP_CAT4
     }
 }
@@ -239,6 +357,13 @@ pub fn step5() -> Script {
 /// The script fails if the preimage doesn't match the transaction.
 ///
 pub fn step6() -> Script {
+    step6_with_hash_type(
+        &TapSighashType::AllPlusAnyoneCanPay,
+        &mut CovenantHintSchema::new(),
+    )
+}
+
+fn step6_with_hash_type(hash_type: &TapSighashType, schema: &mut CovenantHintSchema) -> Script {
     // Obtain the secp256k1 dummy generator, which would be point R in the signature, as well as
     // the public key.
     let secp256k1_generator = SECP256K1_GENERATOR.clone();
@@ -255,15 +380,21 @@ pub fn step6() -> Script {
         { TaggedHashGadget::from_provided(&HashTag::BIP340Challenge) }
 
         // get a hint: the sha256 without the last byte
-        OP_HINT
-        OP_SIZE 31 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::Challenge, HintSize::Exact(31)) }
 
         OP_DUP { 1 } OP_CAT
         OP_ROT OP_EQUALVERIFY
 
         OP_FROMALTSTACK OP_SWAP
 
-        OP_PUSHBYTES_2 OP_PUSHBYTES_2 OP_RIGHT
+        // the last byte of the signature, followed by the hash type unless it is the default
+        if *hash_type == TapSighashType::AllPlusAnyoneCanPay {
+            OP_PUSHBYTES_2 OP_PUSHBYTES_2 OP_RIGHT
+        } else if *hash_type == TapSighashType::Default {
+            2
+        } else {
+            { vec![0x02, *hash_type as u8] }
+        }
         OP_CAT3
 
         OP_FROMALTSTACK
@@ -297,19 +428,20 @@ pub fn step6() -> Script {
 /// - old_state_hash
 ///
 pub fn step7() -> Script {
+    step7_with_version(&Version::TWO, &mut CovenantHintSchema::new())
+}
+
+fn step7_with_version(version: &Version, schema: &mut CovenantHintSchema) -> Script {
     script! {
-        { tx::Step1VersionGadget::from_constant(&Version::TWO) }
+        { tx::Step1VersionGadget::from_constant(version) }
 
         // Below all are related to the old transaction.
 
         // get a hint: first input's outpoint
-        OP_HINT
-        OP_SIZE 36 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::FirstOutPoint, HintSize::Exact(36)) }
 
         // get a hint: second input's outpoint (an empty string if the second input is not present)
-        OP_HINT
-        OP_SIZE 0 OP_EQUAL OP_TOALTSTACK
-        OP_SIZE 36 OP_EQUAL OP_FROMALTSTACK OP_BOOLOR OP_VERIFY
+        { schema.hint(CovenantHint::SecondOutPoint, HintSize::EmptyOr(36)) }
 
         OP_SIZE 0 OP_EQUAL
         OP_IF
@@ -351,8 +483,12 @@ pub fn step7() -> Script {
 /// - old_state_hash
 ///
 pub fn step8() -> Script {
+    step8_with_extra_outputs(&[], &mut CovenantHintSchema::new())
+}
+
+fn step8_with_extra_outputs(extra_outputs: &[TxOut], schema: &mut CovenantHintSchema) -> Script {
     script! {
-        { tx::Step4OutCounterGadget::from_constant(2) }
+        { tx::Step4OutCounterGadget::from_constant(2 + extra_outputs.len()) }
         OP_CAT2
 
         // get the previous amount
@@ -372,14 +508,18 @@ pub fn step8() -> Script {
         3 OP_ROLL
 
         // get a hint: the randomizer for previous transaction (4 bytes)
-        OP_HINT
-        OP_SIZE 4 OP_EQUALVERIFY
+        { schema.hint(CovenantHint::OldRandomizer, HintSize::Exact(4)) }
         OP_CAT3
         OP_SHA256
 
         OP_PUSHBYTES_3 OP_PUSHBYTES_34 OP_PUSHBYTES_0 OP_PUSHBYTES_32
         OP_SWAP OP_CAT3
 
+        for extra_output in extra_outputs.iter() {
+            { tx::Step5OutputGadget::from_constant(extra_output) }
+            OP_CAT
+        }
+
         { tx::Step6LockTimeGadget::from_constant_absolute(&LockTime::ZERO) }
         OP_CAT2
     }
@@ -411,33 +551,652 @@ pub fn step9() -> Script {
 
 /// Implementation of a standard covenant.
 pub fn covenant() -> Script {
-    script! {
-        step1
-        // [..., preimage_head ]
+    covenant_with_code_sep_pos(None)
+}
 
-        step2
-        // [..., preimage_head, pubkey, first_output | dust ]
+/// Same as [`covenant`], but with the position of the last OP_CODESEPARATOR in the leaf before
+/// the covenant, if any (see [`step5_with_code_sep_pos`]).
+pub fn covenant_with_code_sep_pos(code_sep_pos: Option<u32>) -> Script {
+    covenant_with_hint_schema(code_sep_pos)
+        .expect(FIXED_OUTPUTS_FIT)
+        .0
+}
 
-        step3
-        // [..., pubkey, old_state_hash, preimage_head | Hash(first_output | second_output) ]
+/// Same as [`covenant`], but also return the schema of the hints that the covenant consumes.
+pub fn covenant_with_hint_schema(
+    code_sep_pos: Option<u32>,
+) -> Result<(Script, CovenantHintSchema)> {
+    build_covenant(
+        &Version::TWO,
+        &TapSighashType::AllPlusAnyoneCanPay,
+        &[],
+        false,
+        code_sep_pos,
+    )
+}
 
-        step4
-        // [..., pubkey, old_state_hash, old_amount, old_txid, preimage_head | Hash(first_output | second_output) | this_input ]
+/// Build a covenant from the steps, tracking the stack with a [`StackBuilder`].
+///
+/// The covenant signs all the inputs unless the hash type is `SIGHASH_ALL | ANYONECANPAY` (see
+/// [`all_inputs`]), and the outputs of the transaction end with the extra outputs. The annex is
+/// only supported with `SIGHASH_ALL | ANYONECANPAY` (see [`annex`]).
+fn build_covenant(
+    version: &Version,
+    hash_type: &TapSighashType,
+    extra_outputs: &[TxOut],
+    allow_annex: bool,
+    code_sep_pos: Option<u32>,
+) -> Result<(Script, CovenantHintSchema)> {
+    let sign_all_inputs = *hash_type != TapSighashType::AllPlusAnyoneCanPay;
+    ensure!(
+        !(sign_all_inputs && allow_annex),
+        "the annex is only supported with AllPlusAnyoneCanPay"
+    );
 
-        step5
-        // [..., pubkey, old_state_hash, old_amount, old_txid, preimage_head | Hash(first_output | second_output) | this_input | ext ]
+    let mut schema = CovenantHintSchema::new();
+    let mut builder = StackBuilder::new();
 
-        step6
-        // checksigverify done
-        // [..., pubkey, old_state_hash, old_amount, old_txid ]
+    let preimage_head = builder.push("preimage_head", step1_with_version(version, hash_type));
 
-        step7
-        // [..., pubkey, old_state_hash, old_amount, old_txid, version | inputs ]
+    let [preimage_head, pubkey, first_output] = builder.apply(
+        step2_with_schema(&mut schema),
+        &[preimage_head],
+        ["preimage_head", "pubkey", "first_output | dust"],
+    );
+
+    let [pubkey, old_state_hash, old_amount, old_txid, preimage] = if sign_all_inputs {
+        let step3 = all_inputs::step3_with_extra_outputs(extra_outputs, &mut schema)?;
+        let [pubkey, old_state_hash, preimage_head, hash_outputs] = builder.apply(
+            step3,
+            &[preimage_head, pubkey, first_output],
+            ["pubkey", "old_state_hash", "preimage_head", "Hash(outputs)"],
+        );
+        builder.apply(
+            all_inputs::step4_with_schema(&mut schema),
+            &[pubkey, old_state_hash, preimage_head, hash_outputs],
+            [
+                "pubkey",
+                "old_state_hash",
+                "old_amount",
+                "old_txid",
+                "preimage",
+            ],
+        )
+    } else {
+        let step3 = step3_with_extra_outputs(extra_outputs, &mut schema)?;
+        let [pubkey, old_state_hash, preimage] = builder.apply(
+            step3,
+            &[preimage_head, pubkey, first_output],
+            ["pubkey", "old_state_hash", "preimage"],
+        );
+        let step4 = if allow_annex {
+            annex::step4_with_schema(&mut schema)
+        } else {
+            step4_with_schema(&mut schema)
+        };
+        builder.apply(
+            step4,
+            &[pubkey, old_state_hash, preimage],
+            [
+                "pubkey",
+                "old_state_hash",
+                "old_amount",
+                "old_txid",
+                "preimage",
+            ],
+        )
+    };
+
+    let [preimage] = builder.apply(
+        step5_with_schema(code_sep_pos, &mut schema),
+        &[preimage],
+        ["preimage"],
+    );
+
+    // the signature check consumes the preimage
+    builder.apply(
+        step6_with_hash_type(hash_type, &mut schema),
+        &[preimage],
+        [],
+    );
+
+    let tx_data = builder.push("tx_data", step7_with_version(version, &mut schema));
+    let [old_txid, tx_data] = builder.apply(
+        step8_with_extra_outputs(extra_outputs, &mut schema),
+        &[pubkey, old_state_hash, old_amount, old_txid, tx_data],
+        ["old_txid", "tx_data"],
+    );
+
+    // the state hashes come from the altstack
+    let [old_state_hash, new_state_hash] = builder.apply(
+        step9(),
+        &[old_txid, tx_data],
+        ["old_state_hash", "new_state_hash"],
+    );
+
+    let script = if sign_all_inputs {
+        let outputs = builder.apply(
+            all_inputs::step10(),
+            &[old_state_hash, new_state_hash],
+            [
+                "deposit_amount",
+                "deposit_script_pub_key",
+                "old_state_hash",
+                "new_state_hash",
+            ],
+        );
+        builder.build(&outputs)
+    } else {
+        builder.build(&[old_state_hash, new_state_hash])
+    };
+    Ok((script, schema))
+}
+
+/// Module for the covenant over TRUC (version 3) transactions.
+///
+/// The transaction has a third output after the caboose, which is a zero-value pay-to-anchor
+/// (P2A) output. The covenant transaction pays no fee by itself, and a child transaction that
+/// spends the anchor pays the fee for both (CPFP).
+pub mod truc {
+    use crate::bitcoin_script::CovenantHintSchema;
+    use crate::treepp::*;
+    use crate::P2A_SCRIPT_PUB_KEY;
+    use anyhow::Result;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, TapSighashType, TxOut};
 
-        step8
-        // [..., pubkey, old_state_hash, old_amount, old_txid, version | inputs | output | locktime ]
+    /// The zero-value pay-to-anchor output.
+    pub fn anchor_output() -> TxOut {
+        TxOut {
+            value: Amount::ZERO,
+            script_pubkey: P2A_SCRIPT_PUB_KEY.clone(),
+        }
+    }
+
+    /// Step 1: Create the beginning part of the preimage, with version 3.
+    pub fn step1() -> Script {
+        super::step1_with_version(&Version(3), &TapSighashType::AllPlusAnyoneCanPay)
+    }
+
+    /// Step 3: same as [`super::step3`], but the outputs end with the anchor output.
+    pub fn step3() -> Script {
+        super::step3_with_extra_outputs(&[anchor_output()], &mut CovenantHintSchema::new())
+            .expect(super::FIXED_OUTPUTS_FIT)
+    }
+
+    /// Step 7: same as [`super::step7`], but the old transaction has version 3.
+    pub fn step7() -> Script {
+        super::step7_with_version(&Version(3), &mut CovenantHintSchema::new())
+    }
+
+    /// Step 8: same as [`super::step8`], but the old transaction's outputs end with the anchor
+    /// output.
+    pub fn step8() -> Script {
+        super::step8_with_extra_outputs(&[anchor_output()], &mut CovenantHintSchema::new())
+    }
+
+    /// Implementation of a covenant over TRUC transactions.
+    ///
+    /// Note: the transaction that creates the first program must follow the same layout, namely
+    /// version 3 and the outputs being the program, the caboose, and the anchor.
+    pub fn covenant() -> Script {
+        covenant_with_code_sep_pos(None)
+    }
+
+    /// Same as [`covenant`], but with the position of the last OP_CODESEPARATOR in the leaf before
+    /// the covenant, if any.
+    pub fn covenant_with_code_sep_pos(code_sep_pos: Option<u32>) -> Script {
+        covenant_with_hint_schema(code_sep_pos)
+            .expect(super::FIXED_OUTPUTS_FIT)
+            .0
+    }
+
+    /// Same as [`covenant`], but also return the schema of the hints that the covenant consumes.
+    pub fn covenant_with_hint_schema(
+        code_sep_pos: Option<u32>,
+    ) -> Result<(Script, CovenantHintSchema)> {
+        super::build_covenant(
+            &Version(3),
+            &TapSighashType::AllPlusAnyoneCanPay,
+            &[anchor_output()],
+            false,
+            code_sep_pos,
+        )
+    }
+}
+
+/// Module for the covenant that allows the input to carry an annex.
+///
+/// The annex is not standard on the mainnet, so this is only useful on networks that relay
+/// transactions with an annex. The covenant accepts the transaction with or without the annex.
+pub mod annex {
+    use crate::bitcoin_script::{CovenantHint, CovenantHintSchema};
+    use crate::treepp::*;
+    use crate::utils::hint_schema::HintSize;
+    use crate::utils::pseudo::OP_CAT2;
+    use crate::wizards::tap_csv_preimage;
+    use anyhow::Result;
+    use bitcoin::transaction::Version;
+    use bitcoin::TapSighashType;
+
+    /// Step 4: same as [`super::step4`], but the spend type depends on whether the annex is
+    /// present, and the hash of the annex follows the input.
+    ///
+    /// Hint:
+    /// - the annex without the 0x50 prefix (an empty string if the annex is not present)
+    /// - old_txid
+    /// - old_amount
+    ///
+    /// Note: since an empty hint means no annex, the annex needs to have at least one byte after
+    /// the 0x50 prefix.
+    pub fn step4() -> Script {
+        step4_with_schema(&mut CovenantHintSchema::new())
+    }
+
+    pub(super) fn step4_with_schema(schema: &mut CovenantHintSchema) -> Script {
+        script! {
+            // get a hint: the annex without the prefix
+            { schema.hint(CovenantHint::Annex, HintSize::Any) }
+            OP_SIZE 0 OP_EQUAL
+            OP_IF
+                // keep the empty string as the placeholder of the annex hash
+                { tap_csv_preimage::Step7SpendTypeGadget::from_constant(1, false) }
+            OP_ELSE
+                { tap_csv_preimage::Step10AnnexGadgetIfPresent::from_provided() }
+                { tap_csv_preimage::Step7SpendTypeGadget::from_constant(1, true) }
+            OP_ENDIF
+
+            // save the annex hash to the altstack
+            OP_SWAP OP_TOALTSTACK
+            OP_CAT2
+
+            { super::step4_this_input(schema) }
+
+            OP_FROMALTSTACK OP_CAT2
+        }
+    }
+
+    /// Implementation of a covenant that allows the annex.
+    pub fn covenant() -> Script {
+        covenant_with_code_sep_pos(None)
+    }
+
+    /// Same as [`covenant`], but with the position of the last OP_CODESEPARATOR in the leaf before
+    /// the covenant, if any.
+    pub fn covenant_with_code_sep_pos(code_sep_pos: Option<u32>) -> Script {
+        covenant_with_hint_schema(code_sep_pos)
+            .expect(super::FIXED_OUTPUTS_FIT)
+            .0
+    }
+
+    /// Same as [`covenant`], but also return the schema of the hints that the covenant consumes.
+    pub fn covenant_with_hint_schema(
+        code_sep_pos: Option<u32>,
+    ) -> Result<(Script, CovenantHintSchema)> {
+        super::build_covenant(
+            &Version::TWO,
+            &TapSighashType::AllPlusAnyoneCanPay,
+            &[],
+            true,
+            code_sep_pos,
+        )
+    }
+
+    /// Implementation of a covenant over TRUC transactions that allows the annex.
+    pub fn truc_covenant() -> Script {
+        truc_covenant_with_code_sep_pos(None)
+    }
+
+    /// Same as [`truc_covenant`], but with the position of the last OP_CODESEPARATOR in the leaf
+    /// before the covenant, if any.
+    pub fn truc_covenant_with_code_sep_pos(code_sep_pos: Option<u32>) -> Script {
+        truc_covenant_with_hint_schema(code_sep_pos)
+            .expect(super::FIXED_OUTPUTS_FIT)
+            .0
+    }
+
+    /// Same as [`truc_covenant`], but also return the schema of the hints that the covenant
+    /// consumes.
+    pub fn truc_covenant_with_hint_schema(
+        code_sep_pos: Option<u32>,
+    ) -> Result<(Script, CovenantHintSchema)> {
+        super::build_covenant(
+            &Version(3),
+            &TapSighashType::AllPlusAnyoneCanPay,
+            &[super::truc::anchor_output()],
+            true,
+            code_sep_pos,
+        )
+    }
+}
+
+/// Module for the covenant that signs all the inputs, i.e., without `SIGHASH_ANYONECANPAY`.
+///
+/// The signature commits to the deposit input, which is therefore visible to the application.
+/// The transaction has at most one deposit input, after the program input.
+pub mod all_inputs {
+    use crate::bitcoin_script::{CovenantHint, CovenantHintSchema};
+    use crate::internal_structures::variable_length_integer::VariableLengthIntegerGadget;
+    use crate::treepp::*;
+    use crate::utils::hint_schema::HintSize;
+    use crate::utils::pseudo::{OP_CAT2, OP_CAT3, OP_CAT4};
+    use crate::wizards::tap_csv_preimage;
+    use anyhow::Result;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Sequence, TapSighashType, TxOut};
+
+    fn check_hash_type(hash_type: &TapSighashType) {
+        assert!(
+            [TapSighashType::All, TapSighashType::Default].contains(hash_type),
+            "the covenant only supports SIGHASH_ALL or SIGHASH_DEFAULT when signing all the inputs"
+        );
+    }
+
+    /// Step 1: Create the beginning part of the preimage with the given hash type, which is either
+    /// `SIGHASH_ALL` or `SIGHASH_DEFAULT`.
+    pub fn step1(hash_type: &TapSighashType) -> Script {
+        check_hash_type(hash_type);
+        super::step1_with_version(&Version::TWO, hash_type)
+    }
+
+    /// Step 3: same as [`super::step3`], but the hash of the outputs is kept apart from the
+    /// preimage head, since the hashes of the inputs go in between.
+    ///
+    /// Output:
+    /// - pubkey
+    /// - old_state_hash
+    /// - preimage_head
+    /// - Hash(first output | second_output)
+    ///
+    pub fn step3() -> Script {
+        step3_with_extra_outputs(&[], &mut CovenantHintSchema::new())
+            .expect(super::FIXED_OUTPUTS_FIT)
+    }
+
+    pub(super) fn step3_with_extra_outputs(
+        extra_outputs: &[TxOut],
+        schema: &mut CovenantHintSchema,
+    ) -> Result<Script> {
+        let sha_outputs = super::step3_sha_outputs(extra_outputs, schema)?;
+        Ok(script! {
+            { sha_outputs }
+            OP_ROT OP_SWAP
+            OP_FROMALTSTACK
+            OP_ROT OP_ROT
+        })
+    }
+
+    /// Step 4: provide the original data of the program input and the deposit input, and compute
+    /// the hashes of all the inputs.
+    ///
+    /// Hint:
+    /// - old_txid
+    /// - old_amount
+    /// - deposit outpoint (an empty string if there is no deposit input)
+    /// - deposit amount (only if there is a deposit input)
+    /// - deposit script pub key (only if there is a deposit input)
+    /// - deposit sequence (only if there is a deposit input)
+    ///
+    /// Input:
+    /// - pubkey
+    /// - old_state_hash
+    /// - preimage_head
+    /// - Hash(first output | second_output)
+    ///
+    /// Output:
+    /// - pubkey
+    /// - old_state_hash
+    /// - old_amount
+    /// - old_txid
+    /// - preimage_head | Hash(inputs) | Hash(first output | second_output) | this_input
+    ///
+    /// Altstack:
+    /// - deposit amount (an empty string if there is no deposit input)
+    /// - deposit script pub key (an empty string if there is no deposit input)
+    /// - new_state_hash
+    /// - old_state_hash
+    ///
+    pub fn step4() -> Script {
+        step4_with_schema(&mut CovenantHintSchema::new())
+    }
+
+    pub(super) fn step4_with_schema(schema: &mut CovenantHintSchema) -> Script {
+        script! {
+            // get a hint: previous tx's txid
+            { schema.hint(CovenantHint::OldTxid, HintSize::Exact(32)) }
+
+            // get a hint: previous tx's amount
+            { schema.hint(CovenantHint::OldAmount, HintSize::Exact(8)) }
+
+            // get a hint: the deposit outpoint
+            { schema.hint_unchecked(CovenantHint::DepositOutPoint, HintSize::EmptyOr(36)) }
+            OP_SIZE 0 OP_EQUAL
+            OP_IF
+                // the deposit amount, script pub key, and sequence are all empty strings
+                OP_PUSHBYTES_0 OP_PUSHBYTES_0 OP_PUSHBYTES_0
+            OP_ELSE
+                OP_SIZE 36 OP_EQUALVERIFY
+
+                // get a hint: the deposit amount
+                { schema.hint_if_present(CovenantHint::DepositAmount, HintSize::Exact(8), CovenantHint::DepositOutPoint) }
+
+                // get a hint: the deposit script pub key
+                { schema.hint_if_present(CovenantHint::DepositScriptPubKey, HintSize::NonEmpty, CovenantHint::DepositOutPoint) }
+
+                // get a hint: the deposit sequence
+                { schema.hint_if_present(CovenantHint::DepositSequence, HintSize::Exact(4), CovenantHint::DepositOutPoint) }
+            OP_ENDIF
+
+            // save a copy of the deposit amount and script pub key to the altstack for the
+            // application, below the state hashes
+            OP_FROMALTSTACK OP_FROMALTSTACK
+            4 OP_PICK OP_TOALTSTACK
+            3 OP_PICK OP_TOALTSTACK
+            OP_TOALTSTACK OP_TOALTSTACK
+
+            // serialize the deposit script pub key with its length
+            OP_SWAP
+            OP_SIZE 0 OP_EQUAL
+            OP_NOTIF
+                OP_SIZE { VariableLengthIntegerGadget::from_stack_element_size() }
+                OP_SWAP OP_CAT
+            OP_ENDIF
+            OP_SWAP
+
+            // [..., old_txid, old_amount, deposit outpoint, deposit amount, deposit script pub key, deposit sequence ]
+
+            // require the input sequence number be 0xfffffffd
+            { tap_csv_preimage::step5_tx_data_part1_if_not_anyonecanpay::Step4SequenceGadget::from_constant(&Sequence::ENABLE_RBF_NO_LOCKTIME) }
+            OP_SWAP OP_CAT2
+            OP_SHA256 OP_TOALTSTACK
+
+            // the program's script pub key
+            OP_PUSHBYTES_1 OP_PUSHBYTES_34
+            9 OP_PICK OP_CAT2
+            OP_SWAP OP_CAT2
+            OP_SHA256 OP_TOALTSTACK
+
+            2 OP_PICK
+            OP_SWAP OP_CAT2
+            OP_SHA256 OP_TOALTSTACK
+
+            // require the output index be 0
+            2 OP_PICK
+            { tap_csv_preimage::step5_tx_data_part1_if_not_anyonecanpay::step1_outpoint::Step2IndexGadget::from_constant(0) }
+            OP_CAT2
+            OP_SWAP OP_CAT2
+            OP_SHA256
+
+            OP_FROMALTSTACK OP_FROMALTSTACK OP_FROMALTSTACK
+            OP_CAT4
+
+            // [..., preimage_head, Hash(outputs), old_txid, old_amount, Hash(inputs) ]
+            4 OP_ROLL OP_SWAP OP_CAT2
+            3 OP_ROLL OP_CAT2
+
+            { tap_csv_preimage::Step7SpendTypeGadget::from_constant(1, false) }
+            // require the program input be the first input
+            { tap_csv_preimage::Step9InputIndexGadgetIfNotAnyOneCanPay::from_constant(0) }
+            OP_CAT3
+
+            OP_SWAP OP_ROT OP_ROT
+        }
+    }
+
+    /// Step 6: same as [`super::step6`], with the given hash type.
+    pub fn step6(hash_type: &TapSighashType) -> Script {
+        super::step6_with_hash_type(hash_type, &mut CovenantHintSchema::new())
+    }
+
+    /// Step 10: take the deposit amount and the deposit script pub key from the altstack.
+    ///
+    /// Input:
+    /// - old_state_hash
+    /// - new_state_hash
+    ///
+    /// Output:
+    /// - deposit amount (an empty string if there is no deposit input)
+    /// - deposit script pub key (an empty string if there is no deposit input)
+    /// - old_state_hash
+    /// - new_state_hash
+    ///
+    pub fn step10() -> Script {
+        script! {
+            OP_FROMALTSTACK OP_FROMALTSTACK
+            OP_SWAP OP_2SWAP
+        }
+    }
+
+    /// Implementation of a covenant that signs all the inputs.
+    ///
+    /// Different from [`super::covenant`], it leaves the deposit amount and the deposit script
+    /// pub key below the state hashes, which the application needs to consume.
+    pub fn covenant(hash_type: &TapSighashType) -> Script {
+        covenant_with_code_sep_pos(hash_type, None)
+    }
+
+    /// Same as [`covenant`], but with the position of the last OP_CODESEPARATOR in the leaf before
+    /// the covenant, if any.
+    pub fn covenant_with_code_sep_pos(
+        hash_type: &TapSighashType,
+        code_sep_pos: Option<u32>,
+    ) -> Script {
+        covenant_with_hint_schema(hash_type, code_sep_pos)
+            .expect(super::FIXED_OUTPUTS_FIT)
+            .0
+    }
+
+    /// Same as [`covenant`], but also return the schema of the hints that the covenant consumes.
+    pub fn covenant_with_hint_schema(
+        hash_type: &TapSighashType,
+        code_sep_pos: Option<u32>,
+    ) -> Result<(Script, CovenantHintSchema)> {
+        check_hash_type(hash_type);
+        super::build_covenant(&Version::TWO, hash_type, &[], false, code_sep_pos)
+    }
+
+    /// Implementation of a covenant over TRUC transactions that signs all the inputs.
+    pub fn truc_covenant(hash_type: &TapSighashType) -> Script {
+        truc_covenant_with_code_sep_pos(hash_type, None)
+    }
+
+    /// Same as [`truc_covenant`], but with the position of the last OP_CODESEPARATOR in the leaf
+    /// before the covenant, if any.
+    pub fn truc_covenant_with_code_sep_pos(
+        hash_type: &TapSighashType,
+        code_sep_pos: Option<u32>,
+    ) -> Script {
+        truc_covenant_with_hint_schema(hash_type, code_sep_pos)
+            .expect(super::FIXED_OUTPUTS_FIT)
+            .0
+    }
+
+    /// Same as [`truc_covenant`], but also return the schema of the hints that the covenant
+    /// consumes.
+    pub fn truc_covenant_with_hint_schema(
+        hash_type: &TapSighashType,
+        code_sep_pos: Option<u32>,
+    ) -> Result<(Script, CovenantHintSchema)> {
+        check_hash_type(hash_type);
+        super::build_covenant(
+            &Version(3),
+            hash_type,
+            &[super::truc::anchor_output()],
+            false,
+            code_sep_pos,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bitcoin_script::{
+        all_inputs, annex, build_covenant, covenant, covenant_with_code_sep_pos, truc,
+    };
+    use crate::treepp::*;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::transaction::Version;
+    use bitcoin::TapSighashType;
+
+    fn hash(script: Script) -> String {
+        sha256::Hash::hash(script.as_bytes()).to_string()
+    }
+
+    #[test]
+    fn test_covenant_scripts_unchanged() {
+        // The covenants are built from the same steps as before the hints were declared through
+        // the schema and the stack was tracked by the builder, which emits no stack moves since
+        // every step finds its inputs in place, so the scripts, and therefore the program
+        // addresses, stay the same.
+        assert_eq!(
+            hash(covenant()),
+            "1637da5b6e5acd91370cf47454c34b7289708bd9fa244f841ea09061951b82a1"
+        );
+        assert_eq!(
+            hash(truc::covenant()),
+            "f4f1c3cfe6675b283ebfea18703fa7260ba521e0dcf0821059e34aeff4317df8"
+        );
+        assert_eq!(
+            hash(annex::covenant()),
+            "5556a27b363d3361886ca73072788c53aca87e8331867d8724c9ffcf82024cb3"
+        );
+        assert_eq!(
+            hash(annex::truc_covenant()),
+            "dd72a563f7356f898a18329229d9eb4598e38277060f78576463451af05c675f"
+        );
+        assert_eq!(
+            hash(all_inputs::covenant(&TapSighashType::All)),
+            "337a5fec8e5c819fb760b6bb27a6051a1381d3188245475c6105a3ef989f58f2"
+        );
+        assert_eq!(
+            hash(all_inputs::truc_covenant(&TapSighashType::Default)),
+            "a6e1b9aa66b62150844865b5530e6fc19ca53bf8ab8cbfcf83d1d68a0b747682"
+        );
+
+        assert_eq!(
+            hash(covenant_with_code_sep_pos(Some(3))),
+            "48e4129c495669d5a31d98b6ad0a37cb93f1bc4537fc2f6da6b7f97c12f3f064"
+        );
+        assert_eq!(
+            hash(all_inputs::truc_covenant_with_code_sep_pos(
+                &TapSighashType::Default,
+                Some(3)
+            )),
+            "8b5bfdd83a95ee4de4909b3299a07842884dd3fd7a7b42ee434d8e1e97aab9fc"
+        );
+    }
 
-        step9
-        // [old_state_hash, new_state_hash]
+    #[test]
+    fn test_covenant_outputs_too_long() {
+        // The outputs are hashed with a single OP_SHA256, which takes at most 520 bytes.
+        let extra_outputs = vec![truc::anchor_output(); 40];
+        assert!(build_covenant(
+            &Version::TWO,
+            &TapSighashType::AllPlusAnyoneCanPay,
+            &extra_outputs,
+            false,
+            None
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,439 @@
+use crate::DUST_AMOUNT;
+use anyhow::{anyhow, bail, ensure, Result};
+use bitcoin::{Amount, FeeRate, OutPoint, TxOut, Weight};
+
+/// The maximum number of branches that the branch-and-bound search explores.
+pub const BNB_MAX_TRIES: usize = 100_000;
+
+/// A UTXO of the wallet, which can be used as a deposit input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Utxo {
+    /// The outpoint of the UTXO.
+    pub outpoint: OutPoint,
+    /// The output itself.
+    pub tx_out: TxOut,
+    /// The weight that spending this UTXO adds to the transaction, including the witness.
+    pub input_weight: Weight,
+}
+
+/// The strategy to select the deposit inputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoinSelectionStrategy {
+    /// Search for the combination that exceeds the target by the smallest amount.
+    BranchAndBound,
+    /// Take the UTXOs with the largest values first.
+    LargestFirst,
+}
+
+/// Parameters for selecting the deposit inputs of a covenant transaction.
+#[derive(Clone, Debug)]
+pub struct CoinSelectionParams {
+    /// The predicted weight of the covenant transaction without any deposit input.
+    pub base_weight: Weight,
+    /// The target fee rate.
+    pub fee_rate: FeeRate,
+    /// The maximum fee that the transaction is allowed to pay.
+    pub fee_cap: Option<Amount>,
+    /// The maximum number of deposit inputs.
+    ///
+    /// Note: the covenant only supports at most one deposit input at this moment, so this must be
+    /// either 0 or 1.
+    pub max_inputs: usize,
+    /// The balance of the program before the transaction.
+    pub old_balance: u64,
+    /// The balance that the program must keep at least. A deposit is only made when the program
+    /// would fall below this balance.
+    pub min_balance: u64,
+    /// The balance that a deposit should bring the program up to.
+    pub target_balance: u64,
+}
+
+/// The result of the coin selection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinSelection {
+    /// The UTXOs selected as the deposit inputs, which can be empty if no deposit is needed.
+    pub selected: Vec<Utxo>,
+    /// The fee that the transaction pays.
+    pub fee: Amount,
+    /// The balance of the program after the transaction.
+    pub new_balance: u64,
+    /// The part of the new balance above the target balance.
+    ///
+    /// The covenant transaction cannot have a change output, so the excess stays in the program.
+    pub excess: u64,
+}
+
+/// Select the deposit inputs from the UTXOs so that the program balance policy holds.
+///
+/// If the program balance stays at least the minimum balance after paying the fee, no deposit is
+/// made. Otherwise, the deposit inputs are chosen so that the program balance reaches the target
+/// balance, and each deposit input pays for its own weight.
+pub fn select_coins(
+    utxos: &[Utxo],
+    params: &CoinSelectionParams,
+    strategy: CoinSelectionStrategy,
+) -> Result<CoinSelection> {
+    ensure!(
+        params.min_balance <= params.target_balance,
+        "the minimum balance must not exceed the target balance"
+    );
+    ensure!(
+        params.max_inputs <= 1,
+        "the covenant supports at most one deposit input"
+    );
+
+    // If the old balance cannot even cover the fee, a deposit is needed.
+    if let Ok(no_deposit) = finalize(vec![], params) {
+        if no_deposit.new_balance >= params.min_balance {
+            return Ok(no_deposit);
+        }
+    }
+
+    let base_fee = fee_for_weight(params.fee_rate, params.base_weight)?;
+    let target =
+        (params.target_balance + DUST_AMOUNT + base_fee) as i64 - params.old_balance as i64;
+
+    // Only consider the UTXOs that are worth more than the fee to spend them.
+    let mut candidates = vec![];
+    for utxo in utxos.iter() {
+        let effective_value = utxo.tx_out.value.to_sat() as i64
+            - fee_for_weight(params.fee_rate, utxo.input_weight)? as i64;
+        if effective_value > 0 {
+            candidates.push((effective_value, utxo.clone()));
+        }
+    }
+    candidates.sort_by_key(|(effective_value, _)| std::cmp::Reverse(*effective_value));
+
+    let selected = match strategy {
+        CoinSelectionStrategy::BranchAndBound => branch_and_bound(&candidates, target, params)?,
+        CoinSelectionStrategy::LargestFirst => largest_first(&candidates, target, params)?,
+    };
+
+    let selection = finalize(selected, params)?;
+    ensure!(
+        selection.new_balance >= params.target_balance,
+        "the selected UTXOs do not reach the target balance"
+    );
+    Ok(selection)
+}
+
+fn fee_for_weight(fee_rate: FeeRate, weight: Weight) -> Result<u64> {
+    Ok(fee_rate
+        .fee_vb(weight.to_vbytes_ceil())
+        .ok_or_else(|| anyhow!("the fee overflows"))?
+        .to_sat())
+}
+
+fn finalize(selected: Vec<Utxo>, params: &CoinSelectionParams) -> Result<CoinSelection> {
+    let weight = selected
+        .iter()
+        .fold(params.base_weight, |acc, utxo| acc + utxo.input_weight);
+    let fee = fee_for_weight(params.fee_rate, weight)?;
+
+    if let Some(fee_cap) = params.fee_cap {
+        ensure!(fee <= fee_cap.to_sat(), "the fee exceeds the fee cap");
+    }
+
+    let deposit = selected
+        .iter()
+        .map(|utxo| utxo.tx_out.value.to_sat())
+        .sum::<u64>();
+    let new_balance = (params.old_balance + deposit)
+        .checked_sub(fee + DUST_AMOUNT)
+        .ok_or_else(|| anyhow!("the program balance cannot cover the fee"))?;
+
+    Ok(CoinSelection {
+        selected,
+        fee: Amount::from_sat(fee),
+        new_balance,
+        excess: new_balance.saturating_sub(params.target_balance),
+    })
+}
+
+fn within_fee_cap(params: &CoinSelectionParams, weight: Weight) -> Result<bool> {
+    Ok(match params.fee_cap {
+        Some(fee_cap) => fee_for_weight(params.fee_rate, weight)? <= fee_cap.to_sat(),
+        None => true,
+    })
+}
+
+fn largest_first(
+    candidates: &[(i64, Utxo)],
+    target: i64,
+    params: &CoinSelectionParams,
+) -> Result<Vec<Utxo>> {
+    let mut selected = vec![];
+    let mut sum = 0i64;
+    let mut weight = params.base_weight;
+
+    for (effective_value, utxo) in candidates.iter() {
+        if sum >= target || selected.len() == params.max_inputs {
+            break;
+        }
+        if !within_fee_cap(params, weight + utxo.input_weight)? {
+            continue;
+        }
+
+        sum += effective_value;
+        weight += utxo.input_weight;
+        selected.push(utxo.clone());
+    }
+
+    if sum < target {
+        bail!("the UTXOs are insufficient to reach the target balance");
+    }
+    Ok(selected)
+}
+
+fn branch_and_bound(
+    candidates: &[(i64, Utxo)],
+    target: i64,
+    params: &CoinSelectionParams,
+) -> Result<Vec<Utxo>> {
+    // remaining[i] is the total effective value of the candidates from index i.
+    let mut remaining = vec![0i64; candidates.len() + 1];
+    for i in (0..candidates.len()).rev() {
+        remaining[i] = remaining[i + 1] + candidates[i].0;
+    }
+
+    struct Search<'a> {
+        candidates: &'a [(i64, Utxo)],
+        remaining: Vec<i64>,
+        target: i64,
+        params: &'a CoinSelectionParams,
+        tries: usize,
+        current: Vec<usize>,
+        best: Option<(i64, Vec<usize>)>,
+    }
+
+    impl Search<'_> {
+        fn explore(&mut self, index: usize, sum: i64, weight: Weight) -> Result<()> {
+            self.tries += 1;
+            if self.tries > BNB_MAX_TRIES {
+                return Ok(());
+            }
+
+            // The target has been reached. Adding more inputs only increases the waste.
+            if sum >= self.target {
+                let waste = sum - self.target;
+                if !matches!(&self.best, Some((best, _)) if *best <= waste) {
+                    self.best = Some((waste, self.current.clone()));
+                }
+                return Ok(());
+            }
+
+            // Bound: the target is unreachable, or no more inputs are allowed.
+            if index == self.candidates.len()
+                || sum + self.remaining[index] < self.target
+                || self.current.len() == self.params.max_inputs
+            {
+                return Ok(());
+            }
+
+            // Bound: any further inclusion cannot beat the best solution.
+            if let Some((best, _)) = &self.best {
+                if *best == 0 {
+                    return Ok(());
+                }
+            }
+
+            let (effective_value, utxo) = &self.candidates[index];
+
+            // Branch: include the candidate.
+            let new_weight = weight + utxo.input_weight;
+            if within_fee_cap(self.params, new_weight)? {
+                self.current.push(index);
+                self.explore(index + 1, sum + effective_value, new_weight)?;
+                self.current.pop();
+            }
+
+            // Branch: exclude the candidate.
+            self.explore(index + 1, sum, weight)
+        }
+    }
+
+    let mut search = Search {
+        candidates,
+        remaining,
+        target,
+        params,
+        tries: 0,
+        current: vec![],
+        best: None,
+    };
+    search.explore(0, 0, params.base_weight)?;
+
+    match search.best {
+        Some((_, indices)) => Ok(indices
+            .into_iter()
+            .map(|i| candidates[i].1.clone())
+            .collect()),
+        None => bail!("the UTXOs are insufficient to reach the target balance"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::coin_selection::{select_coins, CoinSelectionParams, CoinSelectionStrategy, Utxo};
+    use crate::DUST_AMOUNT;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, FeeRate, OutPoint, ScriptBuf, TxOut, Txid, Weight};
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn get_utxos(prng: &mut ChaCha20Rng, values: &[u64]) -> Vec<Utxo> {
+        values
+            .iter()
+            .map(|value| {
+                let mut bytes = [0u8; 20];
+                prng.fill_bytes(&mut bytes);
+                Utxo {
+                    outpoint: OutPoint::new(Txid::hash(&bytes), 0),
+                    tx_out: TxOut {
+                        value: Amount::from_sat(*value),
+                        script_pubkey: ScriptBuf::new(),
+                    },
+                    input_weight: Weight::from_wu(272),
+                }
+            })
+            .collect()
+    }
+
+    fn get_params(old_balance: u64) -> CoinSelectionParams {
+        CoinSelectionParams {
+            base_weight: Weight::from_wu(4000),
+            fee_rate: FeeRate::from_sat_per_vb(10).unwrap(),
+            fee_cap: None,
+            max_inputs: 1,
+            old_balance,
+            min_balance: 700_000,
+            target_balance: 10_000_000,
+        }
+    }
+
+    #[test]
+    fn test_no_deposit() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let utxos = get_utxos(&mut prng, &[1_000_000, 2_000_000]);
+
+        let params = get_params(5_000_000);
+        let selection =
+            select_coins(&utxos, &params, CoinSelectionStrategy::BranchAndBound).unwrap();
+        assert!(selection.selected.is_empty());
+        assert_eq!(selection.fee.to_sat(), 10_000);
+        assert_eq!(selection.new_balance, 5_000_000 - 10_000 - DUST_AMOUNT);
+    }
+
+    #[test]
+    fn test_old_balance_below_fee() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let utxos = get_utxos(&mut prng, &[12_000_000]);
+
+        // The old balance cannot cover the fee and the caboose without a deposit.
+        let params = get_params(5_000);
+        for strategy in [
+            CoinSelectionStrategy::BranchAndBound,
+            CoinSelectionStrategy::LargestFirst,
+        ] {
+            let selection = select_coins(&utxos, &params, strategy).unwrap();
+            assert_eq!(selection.selected, vec![utxos[0].clone()]);
+            assert_eq!(selection.fee.to_sat(), 10_680);
+            assert_eq!(
+                selection.new_balance,
+                5_000 + 12_000_000 - 10_680 - DUST_AMOUNT
+            );
+        }
+    }
+
+    #[test]
+    fn test_branch_and_bound() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let utxos = get_utxos(
+            &mut prng,
+            &[20_000_000, 12_000_000, 10_000_000, 5_000_000, 1_000],
+        );
+
+        let params = get_params(500_000);
+
+        // The largest UTXO reaches the target, but it leaves a lot of excess.
+        let largest_first =
+            select_coins(&utxos, &params, CoinSelectionStrategy::LargestFirst).unwrap();
+        assert_eq!(largest_first.selected, vec![utxos[0].clone()]);
+
+        // A smaller UTXO reaches the target with less excess.
+        let bnb = select_coins(&utxos, &params, CoinSelectionStrategy::BranchAndBound).unwrap();
+        assert_eq!(bnb.selected, vec![utxos[2].clone()]);
+        assert!(bnb.excess < largest_first.excess);
+        assert_eq!(bnb.new_balance, bnb.excess + params.target_balance);
+
+        // The covenant only supports one deposit input.
+        let mut params_two_inputs = params.clone();
+        params_two_inputs.max_inputs = 2;
+        assert!(select_coins(
+            &utxos,
+            &params_two_inputs,
+            CoinSelectionStrategy::BranchAndBound
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_insufficient() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let utxos = get_utxos(&mut prng, &[6_000_000, 5_000_000]);
+
+        // No UTXO alone reaches the target.
+        let params = get_params(500_000);
+        assert!(select_coins(&utxos, &params, CoinSelectionStrategy::BranchAndBound).is_err());
+        assert!(select_coins(&utxos, &params, CoinSelectionStrategy::LargestFirst).is_err());
+
+        // The fee cap does not allow a deposit input.
+        let utxos = get_utxos(&mut prng, &[12_000_000]);
+        let mut params = get_params(500_000);
+        params.fee_cap = Some(Amount::from_sat(10_679));
+        assert!(select_coins(&utxos, &params, CoinSelectionStrategy::BranchAndBound).is_err());
+        params.fee_cap = Some(Amount::from_sat(10_680));
+        assert!(select_coins(&utxos, &params, CoinSelectionStrategy::BranchAndBound).is_ok());
+    }
+
+    #[test]
+    fn test_random() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let values = (0..8)
+                .map(|_| prng.gen_range(10_000..15_000_000))
+                .collect::<Vec<u64>>();
+            let utxos = get_utxos(&mut prng, &values);
+            let params = get_params(prng.gen_range(0..700_000));
+
+            for strategy in [
+                CoinSelectionStrategy::BranchAndBound,
+                CoinSelectionStrategy::LargestFirst,
+            ] {
+                if let Ok(selection) = select_coins(&utxos, &params, strategy) {
+                    assert!(selection.selected.len() <= params.max_inputs);
+                    assert!(selection.new_balance >= params.target_balance);
+
+                    let deposit = selection
+                        .selected
+                        .iter()
+                        .map(|utxo| utxo.tx_out.value.to_sat())
+                        .sum::<u64>();
+                    assert_eq!(
+                        params.old_balance + deposit,
+                        selection.new_balance + selection.fee.to_sat() + DUST_AMOUNT
+                    );
+                }
+            }
+
+            // The branch-and-bound strategy never leaves more excess than the largest-first one.
+            if let (Ok(bnb), Ok(largest_first)) = (
+                select_coins(&utxos, &params, CoinSelectionStrategy::BranchAndBound),
+                select_coins(&utxos, &params, CoinSelectionStrategy::LargestFirst),
+            ) {
+                assert!(bnb.excess <= largest_first.excess);
+            }
+        }
+    }
+}
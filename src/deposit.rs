@@ -0,0 +1,258 @@
+use crate::treepp::*;
+use crate::UNSPENDABLE_INTERNAL_KEY;
+use anyhow::{anyhow, ensure, Result};
+use bitcoin::hashes::Hash;
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::secp256k1::{All, Message, Secp256k1};
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache};
+use bitcoin::taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::{
+    CompressedPublicKey, ScriptBuf, TapLeafHash, TapSighashType, Transaction, TxOut, Weight,
+    Witness, XOnlyPublicKey,
+};
+use bitcoin_scriptexec::TxTemplate;
+use once_cell::sync::Lazy;
+
+/// The secp256k1 context shared by the signers.
+static SECP: Lazy<Secp256k1<All>> = Lazy::new(Secp256k1::new);
+
+/// Signer for a deposit input, which holds the secret key that controls the deposit.
+pub enum DepositSigner {
+    /// pay-to-witness-public-key-hash
+    P2WPKH(Keypair),
+    /// pay-to-taproot, spent through the key path (without a script tree)
+    P2TRKeyPath(Keypair),
+    /// pay-to-taproot, spent through the script path, where the only leaf is `<key> OP_CHECKSIG`
+    /// and the internal key is unspendable
+    P2TRScriptPath(Keypair),
+}
+
+impl DepositSigner {
+    /// Compute the script pub key of the deposit.
+    pub fn script_pub_key(&self) -> ScriptBuf {
+        let secp = &*SECP;
+        match self {
+            DepositSigner::P2WPKH(keypair) => {
+                ScriptBuf::new_p2wpkh(&CompressedPublicKey(keypair.public_key()).wpubkey_hash())
+            }
+            DepositSigner::P2TRKeyPath(keypair) => {
+                ScriptBuf::new_p2tr(secp, keypair.x_only_public_key().0, None)
+            }
+            DepositSigner::P2TRScriptPath(keypair) => {
+                let taproot_spend_info = Self::script_path_spend_info(keypair);
+                ScriptBuf::new_p2tr(
+                    secp,
+                    taproot_spend_info.internal_key(),
+                    taproot_spend_info.merkle_root(),
+                )
+            }
+        }
+    }
+
+    /// Predict the (maximum) weight that a deposit input signed by this signer adds to the
+    /// transaction, including the witness.
+    pub fn input_weight(&self) -> Weight {
+        // outpoint (36 bytes), empty script sig (1 byte), and sequence (4 bytes)
+        let non_witness_weight = 41 * 4;
+
+        let witness_weight = match self {
+            // items count, signature (up to 71 bytes with a low R), public key (33 bytes)
+            DepositSigner::P2WPKH(_) => 1 + 1 + 71 + 1 + 33,
+            // items count, signature (64 bytes)
+            DepositSigner::P2TRKeyPath(_) => 1 + 1 + 64,
+            // items count, signature (64 bytes), script (34 bytes), control block (33 bytes)
+            DepositSigner::P2TRScriptPath(_) => 1 + 1 + 64 + 1 + 34 + 1 + 33,
+        };
+
+        Weight::from_wu(non_witness_weight + witness_weight)
+    }
+
+    /// Produce the witness for the input at `input_idx` of the transaction, given the previous
+    /// outputs of all the inputs.
+    ///
+    /// The signature commits to all the inputs and all the outputs, so it needs to be produced
+    /// after the outputs of the transaction are final.
+    pub fn sign(&self, tx: &Transaction, input_idx: usize, prevouts: &[TxOut]) -> Result<Witness> {
+        ensure!(
+            prevouts.len() == tx.input.len(),
+            "the previous outputs of all the inputs must be provided"
+        );
+        ensure!(
+            prevouts[input_idx].script_pubkey == self.script_pub_key(),
+            "the input does not belong to this signer"
+        );
+
+        let secp = &*SECP;
+        let mut sighashcache = SighashCache::new(tx);
+
+        match self {
+            DepositSigner::P2WPKH(keypair) => {
+                let hash = sighashcache.p2wpkh_signature_hash(
+                    input_idx,
+                    &prevouts[input_idx].script_pubkey,
+                    prevouts[input_idx].value,
+                    EcdsaSighashType::All,
+                )?;
+                let signature = secp.sign_ecdsa_low_r(
+                    &Message::from_digest(hash.to_byte_array()),
+                    &keypair.secret_key(),
+                );
+
+                Ok(Witness::p2wpkh(
+                    &bitcoin::ecdsa::Signature::sighash_all(signature),
+                    &keypair.public_key(),
+                ))
+            }
+            DepositSigner::P2TRKeyPath(keypair) => {
+                let hash = sighashcache.taproot_key_spend_signature_hash(
+                    input_idx,
+                    &Prevouts::All(prevouts),
+                    TapSighashType::Default,
+                )?;
+                let tweaked_keypair = keypair.tap_tweak(secp, None).to_inner();
+                // BIP-340 recommends fresh auxiliary randomness for each signature.
+                let signature = secp.sign_schnorr_with_rng(
+                    &Message::from_digest(hash.to_byte_array()),
+                    &tweaked_keypair,
+                    &mut rand::thread_rng(),
+                );
+
+                Ok(Witness::p2tr_key_spend(&bitcoin::taproot::Signature {
+                    signature,
+                    sighash_type: TapSighashType::Default,
+                }))
+            }
+            DepositSigner::P2TRScriptPath(keypair) => {
+                let script = Self::script_path_leaf(&keypair.x_only_public_key().0);
+                let hash = sighashcache.taproot_script_spend_signature_hash(
+                    input_idx,
+                    &Prevouts::All(prevouts),
+                    TapLeafHash::from_script(&script, LeafVersion::TapScript),
+                    TapSighashType::Default,
+                )?;
+                let signature = secp.sign_schnorr_with_rng(
+                    &Message::from_digest(hash.to_byte_array()),
+                    keypair,
+                    &mut rand::thread_rng(),
+                );
+
+                let control_block = Self::script_path_spend_info(keypair)
+                    .control_block(&(script.clone(), LeafVersion::TapScript))
+                    .ok_or_else(|| anyhow!("the leaf is not in the script tree"))?;
+
+                let mut witness = Witness::new();
+                witness.push(signature.as_ref());
+                witness.push(script);
+                witness.push(control_block.serialize());
+                Ok(witness)
+            }
+        }
+    }
+
+    fn script_path_leaf(public_key: &XOnlyPublicKey) -> Script {
+        script! {
+            { public_key.serialize().to_vec() }
+            OP_CHECKSIG
+        }
+    }
+
+    fn script_path_spend_info(keypair: &Keypair) -> TaprootSpendInfo {
+        // The same unspendable internal key as the covenant program.
+        let internal_key = *UNSPENDABLE_INTERNAL_KEY;
+
+        TaprootBuilder::new()
+            .add_leaf(0, Self::script_path_leaf(&keypair.x_only_public_key().0))
+            .unwrap()
+            .finalize(&SECP, internal_key)
+            .unwrap()
+    }
+}
+
+/// Sign the deposit input of a transaction generated by `get_tx`, given the previous output of
+/// the deposit input.
+///
+/// This needs to be done again if the outputs change, which `bump_fee` does by itself.
+pub fn sign_deposit_input(
+    tx_template: &mut TxTemplate,
+    deposit_prevout: &TxOut,
+    signer: &DepositSigner,
+) -> Result<()> {
+    ensure!(
+        tx_template.tx.input.len() == 2,
+        "the transaction does not have a deposit input"
+    );
+
+    let prevouts = vec![tx_template.prevouts[0].clone(), deposit_prevout.clone()];
+    tx_template.tx.input[1].witness = signer.sign(&tx_template.tx, 1, &prevouts)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::deposit::DepositSigner;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+    use bitcoin_simulator::database::Database;
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_deposit_signers() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let secp = Secp256k1::new();
+
+        let db = Database::connect_temporary_database().unwrap();
+
+        for i in 0..3 {
+            let keypair = Keypair::new(&secp, &mut prng);
+            let signer = match i {
+                0 => DepositSigner::P2WPKH(keypair),
+                1 => DepositSigner::P2TRKeyPath(keypair),
+                _ => DepositSigner::P2TRScriptPath(keypair),
+            };
+
+            let mut bytes = [0u8; 20];
+            prng.fill_bytes(&mut bytes);
+
+            let prev_tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(Txid::hash(&bytes), 0),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(100_000),
+                    script_pubkey: signer.script_pub_key(),
+                }],
+            };
+            db.insert_transaction_unconditionally(&prev_tx).unwrap();
+
+            let mut tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(prev_tx.compute_txid(), 0),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(90_000),
+                    script_pubkey: signer.script_pub_key(),
+                }],
+            };
+            tx.input[0].witness = signer.sign(&tx, 0, &prev_tx.output).unwrap();
+            assert!(tx.input[0].segwit_weight() <= signer.input_weight());
+
+            db.verify_transaction(&tx).unwrap();
+        }
+    }
+}
@@ -27,7 +27,7 @@ impl Into<Script> for CounterState {
 
 /// Input of the counter example.
 #[derive(Clone)]
-pub struct CounterInput(Option<usize>);
+pub struct CounterInput(pub Option<usize>);
 
 impl Into<Script> for CounterInput {
     fn into(self) -> Script {
@@ -149,9 +149,180 @@ impl CovenantProgram for CounterProgram {
 mod test {
     use crate::examples::counter::{CounterInput, CounterProgram, CounterState};
     use crate::test::{simulation_test, SimulationInstruction};
+    use crate::treepp::*;
+    use crate::utils::analyzer::MAX_LEAF_SCRIPT_SIZE;
+    use crate::{
+        check_leaves, try_compute_taproot_spend_info, try_get_script_pub_key, CovenantProgram,
+    };
+    use anyhow::Result;
+    use bitcoin::opcodes::all::OP_NOP;
+    use bitcoin::TapSighashType;
     use rand::prelude::SliceRandom;
     use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha20Rng;
+    use std::collections::BTreeMap;
+    use std::marker::PhantomData;
+
+    /// The overrides of a variant of the counter program.
+    trait CounterVariant {
+        const CACHE_NAME: &'static str;
+        const TRUC: bool = false;
+        const ANNEX: bool = false;
+        const SIGHASH_TYPE: TapSighashType = TapSighashType::AllPlusAnyoneCanPay;
+
+        fn get_common_prefix() -> Script {
+            CounterProgram::get_common_prefix()
+        }
+
+        fn get_leaf_prefix() -> Script {
+            script! {}
+        }
+    }
+
+    /// The counter program with the overrides of a variant.
+    struct CounterVariantProgram<V: CounterVariant>(PhantomData<V>);
+
+    impl<V: CounterVariant> CovenantProgram for CounterVariantProgram<V> {
+        type State = CounterState;
+        type Input = CounterInput;
+
+        const CACHE_NAME: &'static str = V::CACHE_NAME;
+        const TRUC: bool = V::TRUC;
+        const ANNEX: bool = V::ANNEX;
+        const SIGHASH_TYPE: TapSighashType = V::SIGHASH_TYPE;
+
+        fn new() -> Self::State {
+            CounterProgram::new()
+        }
+
+        fn get_hash(state: &Self::State) -> Vec<u8> {
+            CounterProgram::get_hash(state)
+        }
+
+        fn get_all_scripts() -> BTreeMap<usize, Script> {
+            CounterProgram::get_all_scripts()
+        }
+
+        fn get_common_prefix() -> Script {
+            V::get_common_prefix()
+        }
+
+        fn get_leaf_prefix() -> Script {
+            V::get_leaf_prefix()
+        }
+
+        fn run(id: usize, old_state: &Self::State, input: &Self::Input) -> Result<Self::State> {
+            CounterProgram::run(id, old_state, input)
+        }
+    }
+
+    struct Truc;
+
+    impl CounterVariant for Truc {
+        const CACHE_NAME: &'static str = "TRUC_COUNTER";
+        const TRUC: bool = true;
+    }
+
+    struct Annex;
+
+    impl CounterVariant for Annex {
+        const CACHE_NAME: &'static str = "ANNEX_COUNTER";
+        const ANNEX: bool = true;
+    }
+
+    struct AllInputs;
+
+    impl CounterVariant for AllInputs {
+        const CACHE_NAME: &'static str = "ALL_INPUTS_COUNTER";
+        const SIGHASH_TYPE: TapSighashType = TapSighashType::All;
+
+        fn get_common_prefix() -> Script {
+            script! {
+                // stack:
+                // - deposit amount
+                // - deposit script pub key
+                // - old state hash
+                // - new state hash
+
+                // require the deposit, if any, to be exactly 123_456_000 sats, which is the
+                // amount that the simulation deposits
+                OP_2SWAP OP_DROP
+                OP_SIZE 0 OP_EQUAL
+                OP_IF
+                    OP_DROP
+                OP_ELSE
+                    { 123_456_000u64.to_le_bytes().to_vec() }
+                    OP_EQUALVERIFY
+                OP_ENDIF
+
+                { CounterProgram::get_common_prefix() }
+            }
+        }
+    }
+
+    struct TrucDefault;
+
+    impl CounterVariant for TrucDefault {
+        const CACHE_NAME: &'static str = "TRUC_DEFAULT_COUNTER";
+        const TRUC: bool = true;
+        const SIGHASH_TYPE: TapSighashType = TapSighashType::Default;
+
+        fn get_common_prefix() -> Script {
+            script! {
+                // ignore the deposit amount and the deposit script pub key
+                OP_2SWAP OP_2DROP
+                { CounterProgram::get_common_prefix() }
+            }
+        }
+    }
+
+    struct CodeSep;
+
+    impl CounterVariant for CodeSep {
+        const CACHE_NAME: &'static str = "CODE_SEP_COUNTER";
+
+        fn get_leaf_prefix() -> Script {
+            script! {
+                // a signature-checking section would be here
+                OP_CODESEPARATOR
+                1 OP_DROP
+                OP_CODESEPARATOR
+            }
+        }
+    }
+
+    struct Single;
+
+    impl CounterVariant for Single {
+        const CACHE_NAME: &'static str = "SINGLE_COUNTER";
+        const SIGHASH_TYPE: TapSighashType = TapSighashType::SinglePlusAnyoneCanPay;
+    }
+
+    struct Oversized;
+
+    impl CounterVariant for Oversized {
+        const CACHE_NAME: &'static str = "OVERSIZED_COUNTER";
+
+        fn get_common_prefix() -> Script {
+            // the leaves exceed the size of a standard leaf
+            Script::from_bytes(vec![OP_NOP.to_u8(); MAX_LEAF_SCRIPT_SIZE])
+        }
+    }
+
+    /// Run the simulation of a variant with the two scripts that take no input.
+    fn variant_simulation_test<V: CounterVariant>() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut test_generator = |_: &CounterState| {
+            let id = *[123456usize, 123457].choose(&mut prng).unwrap();
+            Some(SimulationInstruction::<CounterVariantProgram<V>> {
+                program_index: id,
+                program_input: CounterInput(None),
+            })
+        };
+
+        simulation_test::<CounterVariantProgram<V>>(20, &mut test_generator);
+    }
 
     #[test]
     fn test_simulation() {
@@ -172,4 +343,42 @@ mod test {
 
         simulation_test::<CounterProgram>(100, &mut test_generator);
     }
+
+    #[test]
+    fn test_simulation_truc() {
+        variant_simulation_test::<Truc>();
+    }
+
+    #[test]
+    fn test_simulation_annex() {
+        variant_simulation_test::<Annex>();
+    }
+
+    #[test]
+    fn test_simulation_all_inputs() {
+        variant_simulation_test::<AllInputs>();
+    }
+
+    #[test]
+    fn test_simulation_truc_default() {
+        variant_simulation_test::<TrucDefault>();
+    }
+
+    #[test]
+    fn test_simulation_code_sep() {
+        variant_simulation_test::<CodeSep>();
+    }
+
+    #[test]
+    fn test_single_not_supported() {
+        let err = try_get_script_pub_key::<CounterVariantProgram<Single>>().unwrap_err();
+        assert!(err.to_string().contains("SIGHASH_SINGLE"));
+    }
+
+    #[test]
+    fn test_oversized_leaves() {
+        assert!(check_leaves::<CounterVariantProgram<Oversized>>().is_err());
+        assert!(try_compute_taproot_spend_info::<CounterVariantProgram<Oversized>>().is_err());
+        assert!(try_get_script_pub_key::<CounterVariantProgram<Oversized>>().is_err());
+    }
 }
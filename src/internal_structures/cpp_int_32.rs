@@ -1,4 +1,5 @@
 use crate::treepp::*;
+use crate::utils::pseudo::OP_HINT;
 use crate::utils::push_u32_4bytes;
 
 /// Gadget for C++-like 32-bit signed integer.
@@ -54,6 +55,88 @@ impl CppInt32Gadget {
             OP_SIZE 4 OP_LESSTHAN OP_IF OP_PUSHBYTES_1 OP_PUSHBYTES_0 OP_CAT OP_ENDIF
         }
     }
+
+    /// Convert a 32-bit signed integer of 4 bytes on the stack into a Bitcoin integer, which is
+    /// the inverse of [`CppInt32Gadget::from_bitcoin_integer`].
+    ///
+    /// The Bitcoin integer is obtained through `OP_HINT` (see
+    /// [`CppInt32Gadget::get_hint_for_bitcoin_integer`]) and verified by serializing it again.
+    pub fn to_bitcoin_integer() -> Script {
+        script! {
+            OP_HINT
+            OP_DUP { CppInt32Gadget::from_bitcoin_integer() }
+            OP_ROT OP_EQUALVERIFY
+        }
+    }
+
+    /// Convert a 32-bit unsigned integer of 4 bytes on the stack, such as a sequence or a locktime,
+    /// into a Bitcoin integer of the lower 31 bits, with the top bit as a boolean above it.
+    ///
+    /// Unlike [`CppInt32Gadget::to_bitcoin_integer`], this accepts all the 4 bytes including
+    /// `0x00000080`. Both parts are obtained through `OP_HINT` (see
+    /// [`CppInt32Gadget::get_hint_for_unsigned_bitcoin_integer`]) and verified by serializing
+    /// them again.
+    pub fn to_unsigned_bitcoin_integer() -> Script {
+        script! {
+            OP_HINT
+            OP_DUP 0 OP_GREATERTHANOREQUAL OP_VERIFY
+            OP_HINT
+            // stack: bytes lower top
+
+            OP_OVER OP_SWAP
+            OP_DUP OP_TOALTSTACK
+            // stack: bytes lower lower top, altstack: top
+
+            // `OP_IF` requires the top bit to be minimally encoded as 0 or 1
+            OP_IF
+                OP_DUP OP_0NOTEQUAL
+                OP_IF
+                    OP_NEGATE { CppInt32Gadget::from_bitcoin_integer() }
+                OP_ELSE
+                    OP_DROP { push_u32_4bytes(0x80000000) }
+                OP_ENDIF
+            OP_ELSE
+                { CppInt32Gadget::from_positive_bitcoin_integer() }
+            OP_ENDIF
+
+            OP_ROT OP_EQUALVERIFY
+            OP_FROMALTSTACK
+        }
+    }
+
+    /// Generate the hint for [`CppInt32Gadget::to_bitcoin_integer`] from the 4 bytes in
+    /// little-endian, which is `None` for `0x00000080` (negative zero), as it has no Bitcoin
+    /// integer (see [`CppInt32Gadget::get_hint_for_unsigned_bitcoin_integer`] instead).
+    pub fn get_hint_for_bitcoin_integer(v: u32) -> Option<Vec<u8>> {
+        if v == 0x80000000 {
+            return None;
+        }
+
+        let n = if v & 0x80000000 != 0 {
+            -((v & 0x7fffffff) as i64)
+        } else {
+            v as i64
+        };
+
+        let mut bytes = [0u8; 8];
+        let len = bitcoin::script::write_scriptint(&mut bytes, n);
+        Some(bytes[0..len].to_vec())
+    }
+
+    /// Generate the hints for [`CppInt32Gadget::to_unsigned_bitcoin_integer`] from the 4 bytes in
+    /// little-endian, which are the lower 31 bits and the top bit, in this order.
+    pub fn get_hint_for_unsigned_bitcoin_integer(v: u32) -> (Vec<u8>, Vec<u8>) {
+        let mut bytes = [0u8; 8];
+        let len = bitcoin::script::write_scriptint(&mut bytes, (v & 0x7fffffff) as i64);
+        let lower = bytes[0..len].to_vec();
+
+        let top = if v & 0x80000000 != 0 {
+            vec![1u8]
+        } else {
+            vec![]
+        };
+        (lower, top)
+    }
 }
 
 #[cfg(test)]
@@ -61,6 +144,9 @@ mod test {
     use crate::internal_structures::cpp_int_32::CppInt32Gadget;
     use crate::treepp::*;
     use crate::utils::push_u32_4bytes;
+    use crate::utils::test_utils::push_hint;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
 
     #[test]
     fn test_cpp_int32_from_bitcoin_integer() {
@@ -157,4 +243,93 @@ mod test {
         let res = execute_script(script);
         assert!(res.success);
     }
+
+    #[test]
+    fn test_cpp_int32_to_bitcoin_integer() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut values = vec![0, 1, 0x7f, 0x80, 0xffff, 0x7fffffff, 0x80000001, 0xffffffff];
+        for _ in 0..20 {
+            values.push(prng.gen());
+        }
+
+        for v in values {
+            let n = if v & 0x80000000 != 0 {
+                -((v & 0x7fffffff) as i64)
+            } else {
+                v as i64
+            };
+
+            let script = script! {
+                { push_hint(&CppInt32Gadget::get_hint_for_bitcoin_integer(v).unwrap()) }
+                { push_u32_4bytes(v) }
+                { CppInt32Gadget::to_bitcoin_integer() }
+                { n } OP_NUMEQUAL
+            };
+            let res = execute_script(script);
+            assert!(res.success);
+        }
+
+        // a hint that does not match the bytes
+        let script = script! {
+            { push_hint(&CppInt32Gadget::get_hint_for_bitcoin_integer(0x1234).unwrap()) }
+            { push_u32_4bytes(0x1235) }
+            { CppInt32Gadget::to_bitcoin_integer() }
+            OP_DROP OP_TRUE
+        };
+        let res = execute_script(script);
+        assert!(!res.success);
+
+        // negative zero has no Bitcoin integer
+        assert!(CppInt32Gadget::get_hint_for_bitcoin_integer(0x80000000).is_none());
+    }
+
+    #[test]
+    fn test_cpp_int32_to_unsigned_bitcoin_integer() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut values = vec![
+            0, 1, 0x7f, 0x80, 0xffff, 0x7fffffff, 0x80000000, 0x80000001, 0xffffffff,
+        ];
+        for _ in 0..20 {
+            values.push(prng.gen());
+        }
+
+        for v in values {
+            let (lower, top) = CppInt32Gadget::get_hint_for_unsigned_bitcoin_integer(v);
+            let script = script! {
+                { push_hint(&lower) }
+                { push_hint(&top) }
+                { push_u32_4bytes(v) }
+                { CppInt32Gadget::to_unsigned_bitcoin_integer() }
+                { (v >> 31) as i64 } OP_EQUALVERIFY
+                { (v & 0x7fffffff) as i64 } OP_NUMEQUAL
+            };
+            let res = execute_script(script);
+            assert!(res.success);
+        }
+
+        // a top bit that does not match the bytes
+        let (lower, _) = CppInt32Gadget::get_hint_for_unsigned_bitcoin_integer(0x80001234);
+        let script = script! {
+            { push_hint(&lower) }
+            { push_hint(&[]) }
+            { push_u32_4bytes(0x80001234) }
+            { CppInt32Gadget::to_unsigned_bitcoin_integer() }
+            OP_2DROP OP_TRUE
+        };
+        let res = execute_script(script);
+        assert!(!res.success);
+
+        // a negative lower part
+        let script = script! {
+            { push_hint(&[0x85]) }
+            { push_hint(&[]) }
+            { push_u32_4bytes(0x85) }
+            { CppInt32Gadget::to_unsigned_bitcoin_integer() }
+            OP_2DROP OP_TRUE
+        };
+        let res = execute_script(script);
+        assert!(!res.success);
+    }
 }
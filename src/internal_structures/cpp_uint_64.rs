@@ -1,5 +1,6 @@
 use crate::internal_structures::cpp_int_32::CppInt32Gadget;
 use crate::treepp::*;
+use crate::utils::pseudo::OP_HINT;
 use crate::utils::{push_u32_4bytes, push_u64_8bytes};
 
 /// Gadget for 64-bit unsigned integer.
@@ -90,6 +91,83 @@ impl CppUInt64Gadget {
             OP_CAT
         }
     }
+
+    /// Convert the 64-bit unsigned integer of 8 bytes on the stack into four 16-bit limbs, which
+    /// is the inverse of [`CppUInt64Gadget::from_u64_in_16bit_limbs`].
+    ///
+    /// Output:
+    /// - the four 16-bit limbs, with the most significant one at the bottom
+    ///
+    /// The limbs are obtained through `OP_HINT` (see
+    /// [`CppUInt64Gadget::get_hints_for_16bit_limbs`]) and verified by serializing them again.
+    pub fn to_u64_in_16bit_limbs() -> Script {
+        script! {
+            for _ in 0..4 {
+                OP_HINT
+                OP_DUP 0 65536 OP_WITHIN OP_VERIFY
+            }
+
+            3 OP_PICK 3 OP_PICK 3 OP_PICK 3 OP_PICK
+            { CppUInt64Gadget::from_u64_in_16bit_limbs() }
+            5 OP_ROLL OP_EQUALVERIFY
+        }
+    }
+
+    /// Convert the 64-bit unsigned integer of 8 bytes on the stack into three limbs of 31, 31,
+    /// and 2 bits, so that each of them is a positive Bitcoin integer that can be added.
+    ///
+    /// Output:
+    /// - the limbs, with the most significant one at the bottom
+    ///
+    /// The hints are the same as [`CppUInt64Gadget::to_u64_in_16bit_limbs`].
+    pub fn to_u64_in_31bit_limbs() -> Script {
+        script! {
+            { CppUInt64Gadget::to_u64_in_16bit_limbs() }
+
+            // the bottom limb is the lower 15 bits of the second limb, and the first limb
+            OP_TOALTSTACK
+            OP_DUP 32768 OP_GREATERTHANOREQUAL
+            OP_IF
+                32768 OP_SUB { 1 }
+            OP_ELSE
+                { 0 }
+            OP_ENDIF
+            OP_SWAP OP_256MUL OP_256MUL
+            OP_FROMALTSTACK OP_ADD OP_TOALTSTACK
+
+            // the middle limb is the upper bit of the second limb, the third limb, and the lower
+            // 14 bits of the top limb
+            OP_SWAP OP_DUP OP_ADD OP_ADD
+            OP_SWAP
+
+            // split the top limb into its upper 2 bits and lower 14 bits
+            0 OP_SWAP
+            for _ in 0..3 {
+                OP_DUP 16384 OP_GREATERTHANOREQUAL
+                OP_IF
+                    16384 OP_SUB
+                    OP_SWAP OP_1ADD OP_SWAP
+                OP_ENDIF
+            }
+            OP_256MUL OP_256MUL OP_DUP OP_ADD
+            OP_ROT OP_ADD
+
+            OP_FROMALTSTACK
+        }
+    }
+
+    /// Generate the hints for [`CppUInt64Gadget::to_u64_in_16bit_limbs`].
+    pub fn get_hints_for_16bit_limbs(v: u64) -> Vec<Vec<u8>> {
+        (0..4)
+            .rev()
+            .map(|i| {
+                let mut bytes = [0u8; 8];
+                let len =
+                    bitcoin::script::write_scriptint(&mut bytes, ((v >> (16 * i)) & 0xffff) as i64);
+                bytes[0..len].to_vec()
+            })
+            .collect()
+    }
 }
 
 /// The top stack item is multiplied by 256
@@ -109,6 +187,27 @@ mod test {
     use crate::internal_structures::cpp_uint_64::CppUInt64Gadget;
     use crate::treepp::*;
     use crate::utils::push_u64_8bytes;
+    use crate::utils::test_utils::push_hint;
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn get_test_values() -> Vec<u64> {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut values = vec![
+            0,
+            1,
+            0x8000,
+            0x7fffffff,
+            0x80000000,
+            2_100_000_000_000_000,
+            u64::MAX,
+        ];
+        for _ in 0..20 {
+            values.push(prng.next_u64());
+        }
+        values
+    }
 
     #[test]
     fn test_cpp_uint64_from_bitcoin_integer() {
@@ -137,4 +236,53 @@ mod test {
         let res = execute_script(script);
         assert!(res.success);
     }
+
+    #[test]
+    fn test_cpp_uint64_to_u64_in_16bit_limbs() {
+        for v in get_test_values() {
+            let script = script! {
+                for hint in CppUInt64Gadget::get_hints_for_16bit_limbs(v).iter() {
+                    { push_hint(hint) }
+                }
+                { push_u64_8bytes(v) }
+                { CppUInt64Gadget::to_u64_in_16bit_limbs() }
+                for i in 0..4 {
+                    { ((v >> (16 * i)) & 0xffff) as i64 } OP_NUMEQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let res = execute_script(script);
+            assert!(res.success);
+        }
+
+        // hints that do not match the bytes
+        let script = script! {
+            for hint in CppUInt64Gadget::get_hints_for_16bit_limbs(0x1234).iter() {
+                { push_hint(hint) }
+            }
+            { push_u64_8bytes(0x1235) }
+            { CppUInt64Gadget::to_u64_in_16bit_limbs() }
+            OP_2DROP OP_2DROP OP_TRUE
+        };
+        let res = execute_script(script);
+        assert!(!res.success);
+    }
+
+    #[test]
+    fn test_cpp_uint64_to_u64_in_31bit_limbs() {
+        for v in get_test_values() {
+            let script = script! {
+                for hint in CppUInt64Gadget::get_hints_for_16bit_limbs(v).iter() {
+                    { push_hint(hint) }
+                }
+                { push_u64_8bytes(v) }
+                { CppUInt64Gadget::to_u64_in_31bit_limbs() }
+                { (v & 0x7fffffff) as i64 } OP_NUMEQUALVERIFY
+                { ((v >> 31) & 0x7fffffff) as i64 } OP_NUMEQUALVERIFY
+                { (v >> 62) as i64 } OP_NUMEQUAL
+            };
+            let res = execute_script(script);
+            assert!(res.success);
+        }
+    }
 }
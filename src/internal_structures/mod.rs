@@ -6,3 +6,6 @@ pub mod cpp_uint_64;
 
 /// Module for variable length integer in Bitcoin consensus encoding.
 pub mod variable_length_integer;
+
+/// Module for 64-bit unsigned integer arithmetic over 16-bit limbs.
+pub mod u64_limbs;
@@ -0,0 +1,262 @@
+use crate::internal_structures::cpp_uint_64::CppUInt64Gadget;
+use crate::treepp::*;
+
+/// Gadget for 64-bit unsigned integer arithmetic, where each integer is represented by four
+/// 16-bit limbs on the stack, with the most significant one at the bottom (the same as
+/// [`CppUInt64Gadget::from_u64_in_16bit_limbs`]).
+///
+/// All the limbs are assumed to be in the range of 0..65536, which is the case if they come from
+/// [`U64LimbsGadget::from_constant`] or [`U64LimbsGadget::from_amount`].
+pub struct U64LimbsGadget;
+
+impl U64LimbsGadget {
+    /// Construct the limbs from constant data.
+    pub fn from_constant(v: u64) -> Script {
+        script! {
+            for i in (0..4).rev() {
+                { ((v >> (16 * i)) & 0xffff) as i64 }
+            }
+        }
+    }
+
+    /// Construct the limbs from an amount of 8 bytes on the stack.
+    ///
+    /// The hints are obtained through `OP_HINT` (see
+    /// [`CppUInt64Gadget::get_hints_for_16bit_limbs`]).
+    pub fn from_amount() -> Script {
+        CppUInt64Gadget::to_u64_in_16bit_limbs()
+    }
+
+    /// Convert the limbs into an amount of 8 bytes.
+    pub fn to_amount() -> Script {
+        CppUInt64Gadget::from_u64_in_16bit_limbs()
+    }
+
+    /// Add two integers.
+    ///
+    /// Input:
+    /// - the limbs of a
+    /// - the limbs of b
+    ///
+    /// Output:
+    /// - the limbs of (a + b) mod 2^64
+    /// - the carry, which is 1 if a + b overflows and 0 otherwise
+    pub fn add_with_carry() -> Script {
+        script! {
+            // the carry
+            0
+            for i in 0..4 {
+                OP_ADD
+                { 4 - i } OP_ROLL OP_ADD
+                OP_DUP 65536 OP_GREATERTHANOREQUAL
+                OP_IF
+                    65536 OP_SUB { 1 }
+                OP_ELSE
+                    { 0 }
+                OP_ENDIF
+                OP_SWAP OP_TOALTSTACK
+            }
+            for _ in 0..4 {
+                OP_FROMALTSTACK
+            }
+            4 OP_ROLL
+        }
+    }
+
+    /// Subtract an integer from another.
+    ///
+    /// Input:
+    /// - the limbs of a
+    /// - the limbs of b
+    ///
+    /// Output:
+    /// - the limbs of (a - b) mod 2^64
+    /// - the borrow, which is 1 if a < b and 0 otherwise
+    pub fn sub_with_borrow() -> Script {
+        script! {
+            // the borrow
+            0
+            for i in 0..4 {
+                OP_ADD
+                { 4 - i } OP_ROLL OP_SWAP OP_SUB
+                OP_DUP 0 OP_LESSTHAN
+                OP_IF
+                    65536 OP_ADD { 1 }
+                OP_ELSE
+                    { 0 }
+                OP_ENDIF
+                OP_SWAP OP_TOALTSTACK
+            }
+            for _ in 0..4 {
+                OP_FROMALTSTACK
+            }
+            4 OP_ROLL
+        }
+    }
+
+    /// Check if an integer is smaller than another.
+    ///
+    /// Input:
+    /// - the limbs of a
+    /// - the limbs of b
+    ///
+    /// Output:
+    /// - 1 if a < b and 0 otherwise
+    pub fn less_than() -> Script {
+        script! {
+            { U64LimbsGadget::sub_with_borrow() }
+            OP_TOALTSTACK
+            OP_2DROP OP_2DROP
+            OP_FROMALTSTACK
+        }
+    }
+
+    /// Check if two integers are equal.
+    ///
+    /// Input:
+    /// - the limbs of a
+    /// - the limbs of b
+    ///
+    /// Output:
+    /// - 1 if a == b and 0 otherwise
+    pub fn equal() -> Script {
+        script! {
+            for i in 0..4 {
+                { 4 - i } OP_ROLL OP_NUMEQUAL OP_TOALTSTACK
+            }
+            OP_FROMALTSTACK
+            for _ in 0..3 {
+                OP_FROMALTSTACK OP_BOOLAND
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internal_structures::cpp_uint_64::CppUInt64Gadget;
+    use crate::internal_structures::u64_limbs::U64LimbsGadget;
+    use crate::treepp::*;
+    use crate::utils::push_u64_8bytes;
+    use crate::utils::test_utils::push_hint;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn get_rand_pair(prng: &mut ChaCha20Rng) -> (u64, u64) {
+        let a = prng.next_u64();
+        match prng.gen_range(0..4) {
+            0 => (a, prng.next_u64()),
+            // amounts within the money supply
+            1 => (
+                prng.gen_range(0..2_100_000_000_000_000),
+                prng.gen_range(0..2_100_000_000_000_000),
+            ),
+            // equal numbers
+            2 => (a, a),
+            // numbers that only differ in a single limb
+            _ => (
+                a,
+                a ^ ((prng.next_u64() & 0xffff) << (16 * prng.gen_range(0..4))),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_add_with_carry() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let (a, b) = get_rand_pair(&mut prng);
+            let (sum, carry) = a.overflowing_add(b);
+
+            let script = script! {
+                { U64LimbsGadget::from_constant(a) }
+                { U64LimbsGadget::from_constant(b) }
+                { U64LimbsGadget::add_with_carry() }
+                { carry as i64 } OP_NUMEQUALVERIFY
+                { U64LimbsGadget::from_constant(sum) }
+                { U64LimbsGadget::equal() }
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_sub_with_borrow() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let (a, b) = get_rand_pair(&mut prng);
+            let (diff, borrow) = a.overflowing_sub(b);
+
+            let script = script! {
+                { U64LimbsGadget::from_constant(a) }
+                { U64LimbsGadget::from_constant(b) }
+                { U64LimbsGadget::sub_with_borrow() }
+                { borrow as i64 } OP_NUMEQUALVERIFY
+                { U64LimbsGadget::from_constant(diff) }
+                { U64LimbsGadget::equal() }
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_less_than_and_equal() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..100 {
+            let (a, b) = get_rand_pair(&mut prng);
+
+            let script = script! {
+                { U64LimbsGadget::from_constant(a) }
+                { U64LimbsGadget::from_constant(b) }
+                { U64LimbsGadget::less_than() }
+                { (a < b) as i64 } OP_NUMEQUALVERIFY
+
+                { U64LimbsGadget::from_constant(a) }
+                { U64LimbsGadget::from_constant(b) }
+                { U64LimbsGadget::equal() }
+                { (a == b) as i64 } OP_NUMEQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_amount_conversion() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..20 {
+            let (a, b) = get_rand_pair(&mut prng);
+            let (sum, _) = a.overflowing_add(b);
+
+            let script = script! {
+                for hint in CppUInt64Gadget::get_hints_for_16bit_limbs(a).iter() {
+                    { push_hint(hint) }
+                }
+                for hint in CppUInt64Gadget::get_hints_for_16bit_limbs(b).iter() {
+                    { push_hint(hint) }
+                }
+                { push_u64_8bytes(a) }
+                { U64LimbsGadget::from_amount() }
+                { push_u64_8bytes(b) }
+                { U64LimbsGadget::from_amount() }
+                { U64LimbsGadget::add_with_carry() }
+                OP_DROP
+                { U64LimbsGadget::to_amount() }
+                { push_u64_8bytes(sum) }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::treepp::*;
+use crate::utils::pseudo::OP_HINT;
 use bitcoin::consensus::Encodable;
 use bitcoin::opcodes::all::{
     OP_PUSHBYTES_1, OP_PUSHBYTES_3, OP_PUSHBYTES_5, OP_PUSHBYTES_9, OP_PUSHNUM_1, OP_PUSHNUM_NEG1,
@@ -9,12 +10,123 @@ pub struct VariableLengthIntegerGadget;
 
 impl VariableLengthIntegerGadget {
     /// Construct the variable length integer from a Bitcoin integer on the stack
-    /// that is smaller than 128.
+    /// that is smaller than 253, which is encoded as a single byte.
     pub fn from_small_bitcoin_number() -> Script {
         script! {
-            // making sure the number is smaller than 128
-            OP_DUP
-            { 128 } OP_LESSTHAN OP_VERIFY
+            // making sure the number is in 0..253
+            OP_DUP 0 253 OP_WITHIN OP_VERIFY
+            { byte_from_number() }
+        }
+    }
+
+    /// Construct the variable length integer from any non-negative Bitcoin integer on the stack.
+    ///
+    /// Numbers of 253 or above are encoded as `0xfd` followed by two bytes or `0xfe` followed by
+    /// four bytes, whose values are obtained through `OP_HINT` and checked against the number (see
+    /// [`VariableLengthIntegerGadget::get_hints_from_bitcoin_number`]). A Bitcoin integer is
+    /// always below 2^31, so the `0xff` encoding is never needed.
+    pub fn from_bitcoin_number() -> Script {
+        script! {
+            OP_DUP 0 OP_GREATERTHANOREQUAL OP_VERIFY
+
+            OP_DUP 253 OP_LESSTHAN
+            OP_IF
+                { byte_from_number() }
+            OP_ELSE
+                OP_DUP 65536 OP_LESSTHAN
+                OP_IF
+                    { hinted_le_bytes(2) }
+                    OP_ROT OP_NUMEQUALVERIFY
+                    OP_PUSHBYTES_1 OP_RETURN_253 OP_SWAP OP_CAT
+                OP_ELSE
+                    { hinted_le_bytes(4) }
+                    OP_ROT OP_NUMEQUALVERIFY
+                    OP_PUSHBYTES_1 OP_RETURN_254 OP_SWAP OP_CAT
+                OP_ENDIF
+            OP_ENDIF
+        }
+    }
+
+    /// Decode a variable length integer whose bytes are obtained through `OP_HINT` (see
+    /// [`VariableLengthIntegerGadget::get_hints_for_decoding`]).
+    ///
+    /// Output:
+    /// - the variable length integer
+    /// - the integer as a Bitcoin integer
+    ///
+    /// Only the canonical encoding is accepted, and the integer must fit in a Bitcoin integer
+    /// (i.e., be below 2^31).
+    pub fn decode_from_hints() -> Script {
+        script! {
+            // the first byte, which is either the integer or the marker of the length
+            OP_HINT
+            OP_DUP 0 255 OP_WITHIN OP_VERIFY
+
+            OP_DUP 253 OP_LESSTHAN
+            OP_IF
+                OP_DUP { byte_from_number() } OP_SWAP
+            OP_ELSE
+                253 OP_NUMEQUAL
+                OP_IF
+                    { hinted_le_bytes(2) }
+                    OP_DUP 253 OP_GREATERTHANOREQUAL OP_VERIFY
+                    OP_SWAP
+                    OP_PUSHBYTES_1 OP_RETURN_253 OP_SWAP OP_CAT
+                OP_ELSE
+                    { hinted_le_bytes(4) }
+                    OP_DUP 65536 OP_GREATERTHANOREQUAL OP_VERIFY
+                    OP_SWAP
+                    OP_PUSHBYTES_1 OP_RETURN_254 OP_SWAP OP_CAT
+                OP_ENDIF
+                OP_SWAP
+            OP_ENDIF
+        }
+    }
+
+    /// Generate the hints for [`VariableLengthIntegerGadget::from_bitcoin_number`].
+    pub fn get_hints_from_bitcoin_number(v: u32) -> Vec<Vec<u8>> {
+        assert!(v < 1 << 31);
+        if v < 253 {
+            vec![]
+        } else if v < 65536 {
+            v.to_le_bytes()[0..2]
+                .iter()
+                .map(|&b| byte_to_number(b))
+                .collect()
+        } else {
+            v.to_le_bytes().iter().map(|&b| byte_to_number(b)).collect()
+        }
+    }
+
+    /// Generate the hints for [`VariableLengthIntegerGadget::decode_from_hints`].
+    pub fn get_hints_for_decoding(v: u32) -> Vec<Vec<u8>> {
+        assert!(v < 1 << 31);
+        let first = if v < 253 {
+            v as u8
+        } else if v < 65536 {
+            0xfd
+        } else {
+            0xfe
+        };
+
+        let mut hints = vec![byte_to_number(first)];
+        hints.extend(Self::get_hints_from_bitcoin_number(v));
+        hints
+    }
+
+    /// Construct the variable length integer from a Bitcoin integer on the stack that is at most
+    /// 520, the maximum size of a stack element, such as the output of `OP_SIZE`.
+    pub fn from_stack_element_size() -> Script {
+        script! {
+            OP_DUP 0 521 OP_WITHIN OP_VERIFY
+
+            OP_DUP 253 OP_LESSTHAN
+            OP_IF
+                { byte_from_number() }
+            OP_ELSE
+                // 253 to 520 take exactly two bytes as a Bitcoin integer.
+                OP_PUSHBYTES_1 OP_RETURN_253 OP_SWAP OP_CAT
+            OP_ENDIF
         }
     }
 
@@ -62,3 +174,204 @@ impl VariableLengthIntegerGadget {
         }
     }
 }
+
+/// Convert a Bitcoin integer in 0..256 on the stack into a single byte.
+pub(crate) fn byte_from_number() -> Script {
+    script! {
+        OP_DUP 128 OP_LESSTHAN
+        OP_IF
+            // 1 to 127 are already a single byte, and 0 needs to be a byte 0x00.
+            OP_DUP 0 OP_EQUAL
+            OP_IF
+                OP_DROP OP_PUSHBYTES_1 OP_PUSHBYTES_0
+            OP_ENDIF
+        OP_ELSE
+            // 128 to 255 take two bytes as a Bitcoin integer, but the single byte with the
+            // sign bit set is the Bitcoin integer 128 - v, except for 128 (negative zero).
+            OP_DUP 128 OP_EQUAL
+            OP_IF
+                OP_DROP OP_PUSHBYTES_1 OP_LEFT
+            OP_ELSE
+                128 OP_SWAP OP_SUB
+            OP_ENDIF
+        OP_ENDIF
+    }
+}
+
+/// Obtain `n` bytes through `OP_HINT`, each as a Bitcoin integer in 0..256 in little-endian order,
+/// and output their concatenation as well as the Bitcoin integer that they represent.
+///
+/// The integer must be below 2^31, or the script fails.
+fn hinted_le_bytes(n: usize) -> Script {
+    script! {
+        for _ in 0..n {
+            OP_HINT
+            OP_DUP 0 256 OP_WITHIN OP_VERIFY
+        }
+
+        // compute the integer from the most significant byte
+        OP_DUP
+        for i in (0..n - 1).rev() {
+            for _ in 0..8 {
+                OP_DUP OP_ADD
+            }
+            { n - i } OP_PICK OP_ADD
+        }
+        OP_TOALTSTACK
+
+        { n - 1 } OP_ROLL { byte_from_number() }
+        for i in 1..n {
+            { n - i } OP_ROLL { byte_from_number() }
+            OP_CAT
+        }
+        OP_FROMALTSTACK
+    }
+}
+
+/// Encode a byte as a Bitcoin integer, which is how the bytes are hinted.
+fn byte_to_number(b: u8) -> Vec<u8> {
+    if b == 0 {
+        vec![]
+    } else if b < 0x80 {
+        vec![b]
+    } else {
+        vec![b, 0x00]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internal_structures::variable_length_integer::VariableLengthIntegerGadget;
+    use crate::treepp::*;
+    use crate::utils::test_utils::push_hint;
+    use bitcoin::consensus::Encodable;
+
+    const TEST_VALUES: [u32; 14] = [
+        0, 1, 127, 128, 129, 252, 253, 255, 256, 0x7fff, 0x8000, 0xffff, 0x10000, 0x7fffffff,
+    ];
+
+    #[test]
+    fn test_from_stack_element_size() {
+        for v in 0..=520usize {
+            let mut expected = vec![];
+            bitcoin::VarInt::from(v as u64)
+                .consensus_encode(&mut expected)
+                .unwrap();
+
+            let script = script! {
+                { v as i64 }
+                { VariableLengthIntegerGadget::from_stack_element_size() }
+                OP_SIZE { expected.len() as i64 } OP_EQUALVERIFY
+                { VariableLengthIntegerGadget::from_constant(v) }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        let script = script! {
+            521
+            { VariableLengthIntegerGadget::from_stack_element_size() }
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    #[test]
+    fn test_from_small_bitcoin_number() {
+        for v in 0..253usize {
+            let script = script! {
+                { v as i64 }
+                { VariableLengthIntegerGadget::from_small_bitcoin_number() }
+                { VariableLengthIntegerGadget::from_constant(v) }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        for v in [-1, 253] {
+            let script = script! {
+                { v }
+                { VariableLengthIntegerGadget::from_small_bitcoin_number() }
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_from_bitcoin_number() {
+        for v in TEST_VALUES {
+            let script = script! {
+                for hint in VariableLengthIntegerGadget::get_hints_from_bitcoin_number(v).iter() {
+                    { push_hint(hint) }
+                }
+                { v as i64 }
+                { VariableLengthIntegerGadget::from_bitcoin_number() }
+                { VariableLengthIntegerGadget::from_constant(v as usize) }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        // hints that do not match the number
+        let script = script! {
+            for hint in VariableLengthIntegerGadget::get_hints_from_bitcoin_number(300).iter() {
+                { push_hint(hint) }
+            }
+            301
+            { VariableLengthIntegerGadget::from_bitcoin_number() }
+            OP_DROP
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    #[test]
+    fn test_decode_from_hints() {
+        for v in TEST_VALUES {
+            let script = script! {
+                for hint in VariableLengthIntegerGadget::get_hints_for_decoding(v).iter() {
+                    { push_hint(hint) }
+                }
+                { VariableLengthIntegerGadget::decode_from_hints() }
+                { v as i64 } OP_NUMEQUALVERIFY
+                { VariableLengthIntegerGadget::from_constant(v as usize) }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        // non-canonical encodings of 252 and 0xffff, and the unsupported 0xff marker
+        for hints in [
+            vec![vec![0xfd, 0x00], vec![0xfc, 0x00], vec![]],
+            vec![
+                vec![0xfe, 0x00],
+                vec![0xff, 0x00],
+                vec![0xff, 0x00],
+                vec![],
+                vec![],
+            ],
+            vec![vec![0xff, 0x00], vec![], vec![], vec![], vec![]],
+        ] {
+            let script = script! {
+                for hint in hints.iter() {
+                    { push_hint(hint) }
+                }
+                { VariableLengthIntegerGadget::decode_from_hints() }
+                OP_2DROP
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+}
@@ -15,19 +15,25 @@ pub(crate) mod treepp {
 }
 use treepp::*;
 
-use crate::bitcoin_script::covenant;
+use crate::bitcoin_script::{
+    all_inputs, annex, covenant_with_hint_schema, truc, CovenantHint, CovenantHintSchema,
+};
+use crate::deposit::DepositSigner;
+use crate::structures::codesep_pos::get_last_code_sep_pos;
 use crate::structures::tagged_hash::get_hashed_tag;
-use anyhow::Result;
+use crate::utils::analyzer::check_within_limits;
+use crate::utils::hint_schema::HintWitness;
+use anyhow::{anyhow, ensure, Result};
 use bitcoin::absolute::LockTime;
 use bitcoin::consensus::Encodable;
 use bitcoin::key::UntweakedPublicKey;
 use bitcoin::opcodes::all::{OP_PUSHBYTES_36, OP_RETURN};
-use bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoin::sighash::{Annex, Prevouts, SighashCache};
 use bitcoin::taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo};
 use bitcoin::transaction::Version;
 use bitcoin::{
-    Amount, OutPoint, ScriptBuf, Sequence, TapLeafHash, TapSighashType, Transaction, TxIn, TxOut,
-    Txid, Witness, WitnessProgram,
+    Amount, FeeRate, OutPoint, ScriptBuf, Sequence, TapLeafHash, TapSighashType, Transaction, TxIn,
+    TxOut, Txid, VarInt, Weight, Witness, WitnessProgram,
 };
 use bitcoin_scriptexec::{convert_to_witness, TxTemplate};
 use once_cell::sync::Lazy;
@@ -42,6 +48,20 @@ pub static SECP256K1_GENERATOR: Lazy<Vec<u8>> = Lazy::new(|| {
     hex::decode("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap()
 });
 
+/// The unspendable internal key of the covenant program, which has no known discrete logarithm.
+pub static UNSPENDABLE_INTERNAL_KEY: Lazy<UntweakedPublicKey> = Lazy::new(|| {
+    UntweakedPublicKey::from(
+        bitcoin::secp256k1::PublicKey::from_str(
+            "0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0",
+        )
+        .unwrap(),
+    )
+});
+
+/// The pay-to-anchor (P2A) script pub key, which is `OP_1 <0x4e73>`.
+pub static P2A_SCRIPT_PUB_KEY: Lazy<ScriptBuf> =
+    Lazy::new(|| ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73]));
+
 /// Modules for some internal structures such as C++-like integers and Bitcoin VI.
 pub mod internal_structures;
 
@@ -57,6 +77,15 @@ pub mod utils;
 /// The covenant script implementation.
 pub mod bitcoin_script;
 
+/// Module for selecting the deposit inputs from a local UTXO set.
+pub mod coin_selection;
+
+/// Module for signing the deposit inputs.
+pub mod deposit;
+
+/// Module for exporting covenant transactions as PSBTs.
+pub mod psbt;
+
 /// Test module
 pub mod test;
 
@@ -66,6 +95,17 @@ pub mod examples;
 /// The dust amount for a P2WSH transaction.
 pub const DUST_AMOUNT: u64 = 330;
 
+/// The maximum virtual size of a TRUC transaction (BIP-431).
+pub const TRUC_MAX_VSIZE: u64 = 10_000;
+
+/// The incremental relay fee rate (in sat/vB), which a replacement transaction needs to pay in
+/// addition to the fee of the original transaction (BIP-125).
+pub const INCREMENTAL_RELAY_FEE_RATE: u64 = 1;
+
+/// The hint schema map.
+pub static HINT_SCHEMAS: OnceLock<Mutex<BTreeMap<&'static str, CovenantHintSchema>>> =
+    OnceLock::new();
+
 /// The script map.
 pub static SCRIPT_MAPS: OnceLock<Mutex<BTreeMap<&'static str, BTreeMap<usize, Script>>>> =
     OnceLock::new();
@@ -85,6 +125,36 @@ pub trait CovenantProgram {
     /// Unique name for caching.
     const CACHE_NAME: &'static str;
 
+    /// Whether the covenant transactions are TRUC (version 3) transactions with a zero-value
+    /// pay-to-anchor output after the caboose.
+    ///
+    /// Such a transaction is expected to pay no fee by itself. Instead, a child transaction
+    /// spending the anchor output pays the fee (CPFP). The transaction, with the witness of the
+    /// covenant leaf and that of the deposit input, must be no larger than [`TRUC_MAX_VSIZE`].
+    const TRUC: bool = false;
+
+    /// Whether the covenant input may carry an annex, which is committed by the signature hash.
+    ///
+    /// The annex is not standard on the mainnet, so this is only useful on networks that relay
+    /// transactions with an annex. See [`get_tx_with_annex`].
+    const ANNEX: bool = false;
+
+    /// The hash type with which the covenant signs the transaction, which is one of
+    /// `AllPlusAnyoneCanPay` (the default), `All`, and `Default`.
+    ///
+    /// With `All` or `Default`, the signature commits to the deposit input, and the covenant
+    /// leaves the deposit amount and the deposit script pub key (or two empty strings if there is
+    /// no deposit input) below the state hashes for the application. Such a program cannot allow
+    /// the annex.
+    ///
+    /// Note: `SIGHASH_SINGLE` is not supported, and such a program gets an error instead of an
+    /// address. The signature would only commit to the program output, whose taproot address is
+    /// fixed and cannot carry the new state hash, since the output key cannot be tweaked in the
+    /// script. The caboose with the new state hash would then be outside of the signature, and
+    /// anyone relaying the transaction could replace the new state. `SIGHASH_NONE` commits to
+    /// no output at all, so it is not supported either.
+    const SIGHASH_TYPE: TapSighashType = TapSighashType::AllPlusAnyoneCanPay;
+
     /// Create an empty state.
     fn new() -> Self::State;
 
@@ -97,6 +167,16 @@ pub trait CovenantProgram {
     /// Get the common prefix script.
     fn get_common_prefix() -> Script;
 
+    /// Get the script that runs before the covenant in every leaf, which is empty by default.
+    ///
+    /// It can hold its own signature-checking section, consuming the elements at the top of the
+    /// stack (which come from the program input) and leaving nothing on the stack. If it contains
+    /// OP_CODESEPARATOR, the covenant's signature commits to the position of the last one, so the
+    /// OP_CODESEPARATOR must not be in a conditional branch.
+    fn get_leaf_prefix() -> Script {
+        script! {}
+    }
+
     /// Run the program to move from the previous state to the new state.
     fn run(id: usize, old_state: &Self::State, input: &Self::Input) -> Result<Self::State>;
 }
@@ -119,79 +199,215 @@ pub struct CovenantInput {
     /// The second input in the new transaction, used to deposit more money into the program.
     /// Note: The witness must be provided for this input.
     pub optional_deposit_input: Option<TxIn>,
+    /// The output spent by the deposit input, which is required if the covenant signs all the
+    /// inputs (see [`CovenantProgram::SIGHASH_TYPE`]).
+    pub optional_deposit_prevout: Option<TxOut>,
 
     /// The balance of the new state, which needs to be smaller than the old balance plus the deposit,
     /// but does not need to equal (some sats will be used to cover the transaction fee).
     pub new_balance: u64,
 }
 
-/// Initialize the taproot spend info.
-pub fn compute_taproot_spend_info<T: CovenantProgram>() -> TaprootSpendInfo {
-    let secp = bitcoin::secp256k1::Secp256k1::new();
-    let internal_key = UntweakedPublicKey::from(
-        bitcoin::secp256k1::PublicKey::from_str(
-            "0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0",
-        )
-        .unwrap(),
+/// Get the position of the last OP_CODESEPARATOR before the covenant in a leaf script, which
+/// starts with the leaf prefix.
+///
+/// The covenant itself has no OP_CODESEPARATOR, so only the ones in the leaf prefix are executed
+/// before the covenant's signature check.
+fn get_code_sep_pos<T: CovenantProgram>(leaf: &Script) -> Result<Option<u32>> {
+    let leaf_prefix_len = T::get_leaf_prefix().instructions().count();
+    get_last_code_sep_pos(leaf, leaf_prefix_len)
+}
+
+/// Get the covenant part of the scripts, which depends on the transaction format.
+fn get_covenant<T: CovenantProgram>() -> Result<Script> {
+    Ok(get_covenant_with_hint_schema::<T>()?.0)
+}
+
+/// Get the covenant part of the scripts, as well as the schema of the hints it consumes.
+fn get_covenant_with_hint_schema<T: CovenantProgram>() -> Result<(Script, CovenantHintSchema)> {
+    // Every leaf starts with the leaf prefix, so it determines the code separator position.
+    let code_sep_pos = get_code_sep_pos::<T>(&T::get_leaf_prefix())?;
+
+    ensure!(
+        [
+            TapSighashType::AllPlusAnyoneCanPay,
+            TapSighashType::All,
+            TapSighashType::Default
+        ]
+        .contains(&T::SIGHASH_TYPE),
+        "the covenant does not support {}, as the signature must commit to the caboose",
+        T::SIGHASH_TYPE
     );
 
-    let mut map = SCRIPT_MAPS
+    if T::SIGHASH_TYPE != TapSighashType::AllPlusAnyoneCanPay {
+        ensure!(
+            !T::ANNEX,
+            "the annex is only supported when the covenant signs with AllPlusAnyoneCanPay"
+        );
+        return if T::TRUC {
+            all_inputs::truc_covenant_with_hint_schema(&T::SIGHASH_TYPE, code_sep_pos)
+        } else {
+            all_inputs::covenant_with_hint_schema(&T::SIGHASH_TYPE, code_sep_pos)
+        };
+    }
+
+    match (T::TRUC, T::ANNEX) {
+        (false, false) => covenant_with_hint_schema(code_sep_pos),
+        (true, false) => truc::covenant_with_hint_schema(code_sep_pos),
+        (false, true) => annex::covenant_with_hint_schema(code_sep_pos),
+        (true, true) => annex::truc_covenant_with_hint_schema(code_sep_pos),
+    }
+}
+
+/// Get the schema of the hints in the witness of a leaf, which are the hints of the covenant,
+/// followed by the old state, the new state, and the input of the application.
+pub fn get_hint_schema<T: CovenantProgram>() -> Result<CovenantHintSchema> {
+    let mut map = HINT_SCHEMAS
         .get_or_init(|| Mutex::new(BTreeMap::new()))
         .lock()
         .unwrap();
-    let scripts = map.entry(T::CACHE_NAME).or_insert_with(T::get_all_scripts);
+    if let Some(schema) = map.get(T::CACHE_NAME) {
+        return Ok(schema.clone());
+    }
+
+    let (_, mut schema) = get_covenant_with_hint_schema::<T>()?;
+    schema.hint_group(CovenantHint::OldState);
+    schema.hint_group(CovenantHint::NewState);
+    schema.hint_group(CovenantHint::Input);
+    map.insert(T::CACHE_NAME, schema.clone());
+    Ok(schema)
+}
 
+/// Get the leaves of the program, each being the leaf prefix, the covenant, the common prefix,
+/// and the script, indexed by the script's id.
+fn get_leaves<T: CovenantProgram>() -> Result<BTreeMap<usize, Script>> {
+    let leaf_prefix = T::get_leaf_prefix();
+    let covenant = get_covenant::<T>()?;
     let common_prefix = T::get_common_prefix();
 
-    let taproot_builder = TaprootBuilder::with_huffman_tree(scripts.iter().map(|(_, script)| {
-        (
-            1,
-            script! {
-                covenant
+    let mut map = SCRIPT_MAPS
+        .get_or_init(|| Mutex::new(BTreeMap::new()))
+        .lock()
+        .unwrap();
+    let scripts = map.entry(T::CACHE_NAME).or_insert_with(T::get_all_scripts);
+
+    Ok(scripts
+        .iter()
+        .map(|(id, script)| {
+            let leaf = script! {
+                { leaf_prefix.clone() }
+                { covenant.clone() }
                 { common_prefix.clone() }
                 { script.clone() }
-            },
-        )
-    }))
-    .unwrap();
+            };
+            (*id, leaf)
+        })
+        .collect())
+}
 
-    let taproot_spend_info = taproot_builder.finalize(&secp, internal_key).unwrap();
-    taproot_spend_info
+/// Check the given leaves of the program (see [`check_leaves`]).
+fn check_leaves_of<T: CovenantProgram>(leaves: &BTreeMap<usize, Script>) -> Result<()> {
+    get_code_sep_pos::<T>(&T::get_leaf_prefix())?;
+
+    for (id, leaf) in leaves.iter() {
+        let name = format!("the leaf {} of {}", id, T::CACHE_NAME);
+        check_within_limits(leaf, &name)?;
+        get_code_sep_pos::<T>(leaf).map_err(|e| anyhow!("{}: {}", name, e))?;
+    }
+
+    Ok(())
 }
 
-/// Compute the script pub key.
-pub fn get_script_pub_key<T: CovenantProgram>() -> ScriptBuf {
+/// Check every leaf of the program against the script limits (see
+/// [`utils::analyzer::ScriptAnalyzer`]), as well as the OP_CODESEPARATOR before the covenant.
+///
+/// The same checks run when the taproot spend info is computed, so that a program with a leaf
+/// over the limits does not get an address.
+pub fn check_leaves<T: CovenantProgram>() -> Result<()> {
+    check_leaves_of::<T>(&get_leaves::<T>()?)
+}
+
+/// Initialize the taproot spend info.
+///
+/// # Panics
+///
+/// Panics if the leaves cannot be built or do not pass [`check_leaves`] (see
+/// [`try_compute_taproot_spend_info`]).
+pub fn compute_taproot_spend_info<T: CovenantProgram>() -> TaprootSpendInfo {
+    try_compute_taproot_spend_info::<T>().unwrap()
+}
+
+/// Initialize the taproot spend info, or return an error if the leaves cannot be built or do not
+/// pass [`check_leaves`].
+pub fn try_compute_taproot_spend_info<T: CovenantProgram>() -> Result<TaprootSpendInfo> {
     let secp = bitcoin::secp256k1::Secp256k1::new();
-    let internal_key = UntweakedPublicKey::from(
-        bitcoin::secp256k1::PublicKey::from_str(
-            "0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0",
-        )
-        .unwrap(),
-    );
+    let internal_key = *UNSPENDABLE_INTERNAL_KEY;
+
+    let leaves = get_leaves::<T>()?;
+    check_leaves_of::<T>(&leaves)?;
 
+    let taproot_builder =
+        TaprootBuilder::with_huffman_tree(leaves.into_values().map(|leaf| (1, leaf)))?;
+
+    let taproot_spend_info = taproot_builder
+        .finalize(&secp, internal_key)
+        .map_err(|_| anyhow!("the taproot tree is incomplete"))?;
+    Ok(taproot_spend_info)
+}
+
+/// Get the taproot spend info from the cache, computing it for the first time if necessary.
+fn get_taproot_spend_info<T: CovenantProgram>() -> Result<TaprootSpendInfo> {
     let mut map = TAPROOT_SPEND_INFOS
         .get_or_init(|| Mutex::new(BTreeMap::new()))
         .lock()
         .unwrap();
-    let taproot_spend_info = map
-        .entry(T::CACHE_NAME)
-        .or_insert_with(compute_taproot_spend_info::<T>);
+    if let Some(taproot_spend_info) = map.get(T::CACHE_NAME) {
+        return Ok(taproot_spend_info.clone());
+    }
+
+    let taproot_spend_info = try_compute_taproot_spend_info::<T>()?;
+    map.insert(T::CACHE_NAME, taproot_spend_info.clone());
+    Ok(taproot_spend_info)
+}
+
+/// Compute the script pub key.
+///
+/// # Panics
+///
+/// Panics if the leaves cannot be built (see [`try_get_script_pub_key`]).
+pub fn get_script_pub_key<T: CovenantProgram>() -> ScriptBuf {
+    try_get_script_pub_key::<T>().unwrap()
+}
+
+/// Compute the script pub key, or return an error if the leaves cannot be built.
+pub fn try_get_script_pub_key<T: CovenantProgram>() -> Result<ScriptBuf> {
+    let secp = bitcoin::secp256k1::Secp256k1::new();
+    let internal_key = *UNSPENDABLE_INTERNAL_KEY;
+
+    let taproot_spend_info = get_taproot_spend_info::<T>()?;
 
     let witness_program =
         WitnessProgram::p2tr(&secp, internal_key, taproot_spend_info.merkle_root());
     let script_pub_key = ScriptBuf::new_witness_program(&witness_program);
-    script_pub_key
+    Ok(script_pub_key)
 }
 
 /// Compute the control block and script.
+///
+/// # Panics
+///
+/// Panics if the leaves cannot be built or the script does not exist (see
+/// [`try_get_control_block_and_script`]).
 pub fn get_control_block_and_script<T: CovenantProgram>(id: usize) -> (Vec<u8>, Script) {
-    let mut map = TAPROOT_SPEND_INFOS
-        .get_or_init(|| Mutex::new(BTreeMap::new()))
-        .lock()
-        .unwrap();
-    let taproot_spend_info = map
-        .entry(T::CACHE_NAME)
-        .or_insert_with(compute_taproot_spend_info::<T>);
+    try_get_control_block_and_script::<T>(id).unwrap()
+}
+
+/// Compute the control block and script, or return an error if the leaves cannot be built or the
+/// script does not exist.
+pub fn try_get_control_block_and_script<T: CovenantProgram>(
+    id: usize,
+) -> Result<(Vec<u8>, Script)> {
+    let taproot_spend_info = get_taproot_spend_info::<T>()?;
 
     let mut map2 = SCRIPT_MAPS
         .get_or_init(|| Mutex::new(BTreeMap::new()))
@@ -201,13 +417,14 @@ pub fn get_control_block_and_script<T: CovenantProgram>(id: usize) -> (Vec<u8>,
         .entry(T::CACHE_NAME)
         .or_insert_with(T::get_all_scripts)
         .get(&id)
-        .unwrap()
+        .ok_or_else(|| anyhow!("the script {} does not exist in {}", id, T::CACHE_NAME))?
         .clone();
 
     let common_prefix = T::get_common_prefix();
 
     let script = script! {
-        covenant
+        { T::get_leaf_prefix() }
+        { get_covenant::<T>()? }
         { common_prefix.clone() }
         { script.clone() }
     };
@@ -215,55 +432,28 @@ pub fn get_control_block_and_script<T: CovenantProgram>(id: usize) -> (Vec<u8>,
     let mut control_block_bytes = Vec::new();
     taproot_spend_info
         .control_block(&(script.clone(), LeafVersion::TapScript))
-        .unwrap()
-        .encode(&mut control_block_bytes)
-        .unwrap();
+        .ok_or_else(|| anyhow!("the script is not in the taproot tree"))?
+        .encode(&mut control_block_bytes)?;
 
-    (control_block_bytes, script)
+    Ok((control_block_bytes, script))
 }
 
-/// Generate the new transaction and return the new transaction as well as the randomizer
-pub fn get_tx<T: CovenantProgram>(
-    info: &CovenantInput,
-    id: usize,
-    old_state: &T::State,
-    new_state: &T::State,
-    input: &T::Input,
-) -> (TxTemplate, u32) {
-    let script_pub_key = get_script_pub_key::<T>();
-    let (control_block_bytes, script) = get_control_block_and_script::<T>(id);
-
-    let tap_leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
-
-    // Initialize a new transaction.
-    let mut tx = Transaction {
-        version: Version::TWO,
-        lock_time: LockTime::ZERO,
-        input: vec![],
-        output: vec![],
-    };
-
-    // Push the previous program as the first input, with the witness left blank as a placeholder.
-    tx.input.push(TxIn {
-        previous_output: OutPoint::new(info.old_txid.clone(), 0),
-        script_sig: ScriptBuf::new(),
-        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-        witness: Witness::new(), // placeholder
-    });
-
-    // If there is an optional deposit input, include it as well.
-    if let Some(input) = &info.optional_deposit_input {
-        tx.input.push(input.clone());
-    }
-
-    // Push the first output, which is the new program (and the only change is in the balance).
-    tx.output.push(TxOut {
-        value: Amount::from_sat(info.new_balance),
-        script_pubkey: script_pub_key.clone(),
-    });
-
-    let old_state_hash = T::get_hash(old_state);
-    let new_state_hash = T::get_hash(new_state);
+/// Search for a randomizer for the caboose with which the signature hash would work with the
+/// Schnorr trick, and append the caboose (as well as the anchor output in the TRUC format) to the
+/// transaction, which should only have the program output at this moment.
+///
+/// Return the randomizer and the signature element "e".
+/// The previous outputs need to include the deposit input's if the covenant signs all the inputs.
+fn find_randomizer<T: CovenantProgram>(
+    tx: &mut Transaction,
+    prevouts: &[TxOut],
+    new_state_hash: &[u8],
+    tap_leaf_hash: TapLeafHash,
+    code_sep_pos: Option<u32>,
+    annex: Option<&Annex>,
+) -> (u32, Vec<u8>) {
+    // The covenant's signature commits to the last OP_CODESEPARATOR before it, if any.
+    let code_sep_pos = code_sep_pos.unwrap_or(0xffffffffu32);
 
     // Start the search of a working randomizer from 0.
     let mut randomizer = 0u32;
@@ -271,10 +461,9 @@ pub fn get_tx<T: CovenantProgram>(
     // Initialize a placeholder for e, which is the signature element "e" in Schnorr signature.
     // Finding e relies on trial-and-error. Specifically, e is a tagged hash of the signature preimage,
     // and the signature preimage is calculated by serializing the transaction in a specific way.
-    let e;
     loop {
         let mut script_bytes = vec![OP_RETURN.to_u8(), OP_PUSHBYTES_36.to_u8()];
-        script_bytes.extend_from_slice(&new_state_hash);
+        script_bytes.extend_from_slice(new_state_hash);
         script_bytes.extend_from_slice(&randomizer.to_le_bytes());
 
         // Generate the corresponding caboose with the new counter.
@@ -288,23 +477,28 @@ pub fn get_tx<T: CovenantProgram>(
             script_pubkey: ScriptBuf::new_witness_program(&witness_program),
         });
 
+        // In the TRUC format, the anchor output follows the caboose.
+        if T::TRUC {
+            tx.output.push(truc::anchor_output());
+        }
+
         // Initialize the SighashCache object for computing the signature preimage.
         let mut sighashcache = SighashCache::new(tx.clone());
 
-        // Compute the taproot hash assuming AllPlusAnyoneCanPay.
+        // Compute the taproot hash with the hash type of the program.
+        let prevouts = if T::SIGHASH_TYPE == TapSighashType::AllPlusAnyoneCanPay {
+            Prevouts::One(0, prevouts[0].clone())
+        } else {
+            Prevouts::All(prevouts)
+        };
         let hash = AsRef::<[u8]>::as_ref(
             &sighashcache
-                .taproot_script_spend_signature_hash(
+                .taproot_signature_hash(
                     0,
-                    &Prevouts::One(
-                        0,
-                        &TxOut {
-                            value: Amount::from_sat(info.old_balance),
-                            script_pubkey: script_pub_key.clone(),
-                        },
-                    ),
-                    tap_leaf_hash,
-                    TapSighashType::AllPlusAnyoneCanPay,
+                    &prevouts,
+                    annex.cloned(),
+                    Some((tap_leaf_hash, code_sep_pos)),
+                    T::SIGHASH_TYPE,
                 )
                 .unwrap(),
         )
@@ -327,83 +521,252 @@ pub fn get_tx<T: CovenantProgram>(
         // Nevertheless, requiring so makes sure that we can avoid the corner case (ending at 0xff),
         // and it is consistent with the Schnorr trick article.
         if e_expected[31] == 0x01 {
-            e = Some(e_expected);
-            break;
+            return (randomizer, e_expected);
         } else {
-            // Remove the nonfunctional output and retry.
-            tx.output.pop().unwrap();
+            // Remove the nonfunctional output (as well as the anchor output) and retry.
+            tx.output.truncate(1);
             randomizer += 1;
         }
     }
+}
 
-    // now start preparing the witness
-    let mut script_execution_witness = Vec::<Vec<u8>>::new();
+/// Predict the weight that a deposit input adds to the covenant transaction, given the weight of
+/// the deposit input itself (see [`deposit::DepositSigner::input_weight`]) and its previous
+/// output.
+///
+/// If the covenant signs all the inputs, the covenant witness also carries the deposit input.
+pub fn get_deposit_input_weight<T: CovenantProgram>(
+    input_weight: Weight,
+    prevout: &TxOut,
+) -> Weight {
+    if T::SIGHASH_TYPE == TapSighashType::AllPlusAnyoneCanPay {
+        return input_weight;
+    }
 
-    // new balance (8 bytes)
-    script_execution_witness.push(info.new_balance.to_le_bytes().to_vec());
+    // the outpoint (36 bytes instead of an empty string), the amount (8 bytes), the script pub
+    // key, and the sequence (4 bytes)
+    let script_pub_key_len = prevout.script_pubkey.len();
+    let hints_weight =
+        36 + (1 + 8) + (VarInt(script_pub_key_len as u64).size() + script_pub_key_len) + (1 + 4);
+    input_weight + Weight::from_wu(hints_weight as u64)
+}
 
-    // this script's scriptpubkey (34 bytes)
-    script_execution_witness.push(script_pub_key.to_bytes());
+/// Generate the new transaction and return the new transaction as well as the randomizer
+///
+/// # Panics
+///
+/// Panics if the transaction cannot be generated (see [`try_get_tx`]).
+pub fn get_tx<T: CovenantProgram>(
+    info: &CovenantInput,
+    id: usize,
+    old_state: &T::State,
+    new_state: &T::State,
+    input: &T::Input,
+) -> (TxTemplate, u32) {
+    try_get_tx::<T>(info, id, old_state, new_state, input).unwrap()
+}
+
+/// Generate the new transaction and return the new transaction as well as the randomizer, or
+/// return an error if the transaction cannot be generated.
+pub fn try_get_tx<T: CovenantProgram>(
+    info: &CovenantInput,
+    id: usize,
+    old_state: &T::State,
+    new_state: &T::State,
+    input: &T::Input,
+) -> Result<(TxTemplate, u32)> {
+    get_tx_with_annex::<T>(info, id, old_state, new_state, input, None)
+}
+
+/// Generate the new transaction, where the covenant input carries the annex if provided, and
+/// return the new transaction as well as the randomizer.
+///
+/// The annex can only be provided if the program allows it (see [`CovenantProgram::ANNEX`]), and
+/// it needs to have at least one byte after the 0x50 prefix.
+pub fn get_tx_with_annex<T: CovenantProgram>(
+    info: &CovenantInput,
+    id: usize,
+    old_state: &T::State,
+    new_state: &T::State,
+    input: &T::Input,
+    annex: Option<Annex>,
+) -> Result<(TxTemplate, u32)> {
+    if let Some(annex) = &annex {
+        ensure!(T::ANNEX, "the program does not allow the annex");
+        ensure!(annex.as_bytes().len() > 1, "the annex must not be empty");
+    }
 
-    // the new counter hash
-    script_execution_witness.push(new_state_hash.clone());
+    let script_pub_key = try_get_script_pub_key::<T>()?;
+    let (control_block_bytes, script) = try_get_control_block_and_script::<T>(id)?;
 
-    // the old counter hash
-    script_execution_witness.push(old_state_hash.clone());
+    let tap_leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+    let code_sep_pos = get_code_sep_pos::<T>(&script)?;
 
-    // the randomizer (4 bytes)
-    script_execution_witness.push(randomizer.to_le_bytes().to_vec());
+    // Initialize a new transaction.
+    let mut tx = Transaction {
+        version: if T::TRUC { Version(3) } else { Version::TWO },
+        lock_time: LockTime::ZERO,
+        input: vec![],
+        output: vec![],
+    };
 
-    // previous tx's txid (32 bytes)
-    script_execution_witness.push(AsRef::<[u8]>::as_ref(&info.old_txid).to_vec());
+    // Push the previous program as the first input, with the witness left blank as a placeholder.
+    tx.input.push(TxIn {
+        previous_output: OutPoint::new(info.old_txid.clone(), 0),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness: Witness::new(), // placeholder
+    });
 
-    // previous balance (8 bytes)
-    script_execution_witness.push(info.old_balance.to_le_bytes().to_vec());
+    // If there is an optional deposit input, include it as well.
+    if let Some(input) = &info.optional_deposit_input {
+        tx.input.push(input.clone());
+    }
 
-    // tap leaf hash (32 bytes)
-    script_execution_witness.push(AsRef::<[u8]>::as_ref(&tap_leaf_hash).to_vec());
+    // Push the first output, which is the new program (and the only change is in the balance).
+    tx.output.push(TxOut {
+        value: Amount::from_sat(info.new_balance),
+        script_pubkey: script_pub_key.clone(),
+    });
 
-    // the sha256 without the last byte (31 bytes)
-    script_execution_witness.push(e.unwrap()[0..31].to_vec());
+    let old_state_hash = T::get_hash(old_state);
+    let new_state_hash = T::get_hash(new_state);
+
+    // The previous outputs, which include the deposit input's if provided. It must be provided if
+    // the covenant signs all the inputs.
+    let mut prevouts = vec![TxOut {
+        value: Amount::from_sat(info.old_balance),
+        script_pubkey: script_pub_key.clone(),
+    }];
+    if info.optional_deposit_input.is_some() {
+        if let Some(prevout) = &info.optional_deposit_prevout {
+            prevouts.push(prevout.clone());
+        }
+    }
+    let deposit = if T::SIGHASH_TYPE == TapSighashType::AllPlusAnyoneCanPay {
+        None
+    } else {
+        info.optional_deposit_input
+            .as_ref()
+            .map(|input| {
+                let prevout = info
+                    .optional_deposit_prevout
+                    .clone()
+                    .ok_or_else(|| anyhow!("the deposit previous output must be provided"))?;
+                Ok::<_, anyhow::Error>((input, prevout))
+            })
+            .transpose()?
+    };
+
+    let (randomizer, e) = find_randomizer::<T>(
+        &mut tx,
+        &prevouts,
+        &new_state_hash,
+        tap_leaf_hash,
+        code_sep_pos,
+        annex.as_ref(),
+    );
+
+    // now start preparing the witness, where the hints follow the order in the leaf
+    let schema = get_hint_schema::<T>()?;
+    let mut hints = HintWitness::new(&schema);
+
+    hints.set(
+        CovenantHint::NewBalance,
+        info.new_balance.to_le_bytes().to_vec(),
+    );
+    hints.set(CovenantHint::ScriptPubKey, script_pub_key.to_bytes());
+    hints.set(CovenantHint::NewStateHash, new_state_hash.clone());
+    hints.set(CovenantHint::OldStateHash, old_state_hash.clone());
+    hints.set(CovenantHint::Randomizer, randomizer.to_le_bytes().to_vec());
+
+    // the annex without the 0x50 prefix (or an empty string if there is no annex)
+    if T::ANNEX {
+        hints.set(
+            CovenantHint::Annex,
+            annex
+                .as_ref()
+                .map(|annex| annex.as_bytes()[1..].to_vec())
+                .unwrap_or_default(),
+        );
+    }
+
+    hints.set(
+        CovenantHint::OldTxid,
+        AsRef::<[u8]>::as_ref(&info.old_txid).to_vec(),
+    );
+    hints.set(
+        CovenantHint::OldAmount,
+        info.old_balance.to_le_bytes().to_vec(),
+    );
+
+    // the deposit input, if the covenant signs all the inputs
+    if T::SIGHASH_TYPE != TapSighashType::AllPlusAnyoneCanPay {
+        if let Some((input, prevout)) = &deposit {
+            let mut bytes = vec![];
+            input.previous_output.consensus_encode(&mut bytes).unwrap();
+            hints.set(CovenantHint::DepositOutPoint, bytes);
+
+            hints.set(
+                CovenantHint::DepositAmount,
+                prevout.value.to_sat().to_le_bytes().to_vec(),
+            );
+            hints.set(
+                CovenantHint::DepositScriptPubKey,
+                prevout.script_pubkey.to_bytes(),
+            );
+            hints.set(
+                CovenantHint::DepositSequence,
+                input.sequence.to_consensus_u32().to_le_bytes().to_vec(),
+            );
+        } else {
+            hints.set(CovenantHint::DepositOutPoint, vec![]);
+        }
+    }
+
+    hints.set(
+        CovenantHint::TapLeafHash,
+        AsRef::<[u8]>::as_ref(&tap_leaf_hash).to_vec(),
+    );
+
+    // the sha256 without the last byte
+    hints.set(CovenantHint::Challenge, e[0..31].to_vec());
 
-    // the first outpoint (32 + 4 = 36 bytes)
     {
         let mut bytes = vec![];
         info.input_outpoint1.consensus_encode(&mut bytes).unwrap();
-
-        script_execution_witness.push(bytes);
+        hints.set(CovenantHint::FirstOutPoint, bytes);
     }
 
-    // the second outpoint (0 or 36 bytes)
+    // the second outpoint (or an empty string if there is no second input)
     {
-        if info.input_outpoint2.is_some() {
-            let mut bytes = vec![];
-            info.input_outpoint2
-                .unwrap()
-                .consensus_encode(&mut bytes)
-                .unwrap();
-
-            script_execution_witness.push(bytes);
-        } else {
-            script_execution_witness.push(vec![]);
+        let mut bytes = vec![];
+        if let Some(outpoint) = info.input_outpoint2 {
+            outpoint.consensus_encode(&mut bytes).unwrap();
         }
+        hints.set(CovenantHint::SecondOutPoint, bytes);
     }
 
-    // previous randomizer
-    script_execution_witness.push(info.old_randomizer.to_le_bytes().to_vec());
+    hints.set(
+        CovenantHint::OldRandomizer,
+        info.old_randomizer.to_le_bytes().to_vec(),
+    );
 
     // application-specific witnesses
-    let old_state_in_script: Script = old_state.clone().into();
-    let new_state_in_script: Script = new_state.clone().into();
-    let input_in_script: Script = input.clone().into();
-    let application_witness = convert_to_witness(script! {
-        { old_state_in_script }
-        { new_state_in_script }
-        { input_in_script }
-    })
-    .unwrap();
-
-    script_execution_witness.extend_from_slice(&application_witness);
+    hints.set_group(
+        CovenantHint::OldState,
+        convert_to_witness(old_state.clone().into()).unwrap(),
+    );
+    hints.set_group(
+        CovenantHint::NewState,
+        convert_to_witness(new_state.clone().into()).unwrap(),
+    );
+    hints.set_group(
+        CovenantHint::Input,
+        convert_to_witness(input.clone().into()).unwrap(),
+    );
+
+    let script_execution_witness = hints.finalize();
 
     // Construct the witness that will be included in the TxIn.
     let mut script_tx_witness = Witness::new();
@@ -415,20 +778,201 @@ pub fn get_tx<T: CovenantProgram>(
     script_tx_witness.push(script);
     // the control block bytes
     script_tx_witness.push(control_block_bytes);
+    // the annex, which is always the last element if present
+    if let Some(annex) = &annex {
+        script_tx_witness.push(annex.as_bytes());
+    }
 
     // Include the witness in the TxIn.
     tx.input[0].witness = script_tx_witness;
 
+    // A TRUC transaction cannot exceed the size limit, which the large witness of the covenant
+    // leaf can hit. The witness of the deposit input, if any, is not known yet, so the caller
+    // needs to check the signed transaction again.
+    if T::TRUC {
+        check_truc_vsize(&tx)?;
+    }
+
     // Prepare the TxTemplate.
     let tx_template = TxTemplate {
         tx,
-        prevouts: vec![TxOut {
-            value: Amount::from_sat(info.old_balance),
-            script_pubkey: script_pub_key.clone(),
-        }],
+        prevouts,
         input_idx: 0,
-        taproot_annex_scriptleaf: Some((tap_leaf_hash.clone(), None)),
+        taproot_annex_scriptleaf: Some((
+            tap_leaf_hash.clone(),
+            annex.map(|annex| annex.as_bytes().to_vec()),
+        )),
+    };
+
+    Ok((tx_template, randomizer))
+}
+
+/// Check that a TRUC transaction is no larger than [`TRUC_MAX_VSIZE`].
+///
+/// [`get_tx`] checks the transaction before the deposit input is signed, so the caller needs to
+/// check the signed transaction again.
+pub fn check_truc_vsize(tx: &Transaction) -> Result<()> {
+    ensure!(
+        tx.vsize() as u64 <= TRUC_MAX_VSIZE,
+        "the TRUC transaction exceeds {} vB",
+        TRUC_MAX_VSIZE
+    );
+    Ok(())
+}
+
+/// Rebuild a transaction generated by [`get_tx`] with a higher fee rate, so that it can replace
+/// the original transaction that is stuck in the mempool (BIP-125).
+///
+/// The replacement keeps the leaf, the states, and the application witness, and lowers the
+/// program output to cover the higher fee. If there is a deposit input, the transaction template
+/// needs to carry its previous output, and the deposit input is signed again with the signer,
+/// whose predicted input weight is used for the fee since the new signature can differ in size.
+///
+/// Return the new transaction as well as the randomizer.
+pub fn bump_fee<T: CovenantProgram>(
+    tx_template: &TxTemplate,
+    new_fee_rate: FeeRate,
+    deposit_signer: Option<&DepositSigner>,
+) -> Result<(TxTemplate, u32)> {
+    let script_pub_key = try_get_script_pub_key::<T>()?;
+
+    let old_tx = &tx_template.tx;
+    ensure!(
+        !old_tx.output.is_empty(),
+        "the transaction does not have any output"
+    );
+    ensure!(
+        old_tx.output[0].script_pubkey == script_pub_key,
+        "the transaction does not belong to this covenant program"
+    );
+    ensure!(
+        tx_template.prevouts.len() == old_tx.input.len(),
+        "the previous outputs of all the inputs must be provided"
+    );
+    ensure!(
+        (old_tx.input.len() == 2) == deposit_signer.is_some(),
+        "the deposit signer must be provided if and only if there is a deposit input"
+    );
+
+    let (tap_leaf_hash, annex_bytes) = tx_template
+        .taproot_annex_scriptleaf
+        .clone()
+        .ok_or_else(|| anyhow!("the transaction does not spend a covenant leaf"))?;
+    let annex = annex_bytes
+        .as_ref()
+        .map(|bytes| Annex::new(bytes))
+        .transpose()?;
+
+    let input_value = tx_template
+        .prevouts
+        .iter()
+        .map(|prevout| prevout.value.to_sat())
+        .sum::<u64>();
+    let output_value = old_tx
+        .output
+        .iter()
+        .map(|output| output.value.to_sat())
+        .sum::<u64>();
+    let old_fee = input_value
+        .checked_sub(output_value)
+        .ok_or_else(|| anyhow!("the transaction spends more than its inputs"))?;
+
+    // In the covenant input, only fixed-length elements (the new balance, the randomizer, and the
+    // signature element "e") change, but the new signature of the deposit input can differ in size,
+    // so the replacement is assumed to have the predicted weight of the deposit input.
+    let old_vsize = old_tx.vsize() as u64;
+    let mut weight = old_tx.weight();
+    if let Some(signer) = deposit_signer {
+        weight = weight - old_tx.input[1].segwit_weight() + signer.input_weight();
+    }
+    let vsize = weight.to_vbytes_ceil();
+    ensure!(
+        new_fee_rate
+            .fee_vb(old_vsize)
+            .is_some_and(|fee| fee.to_sat() > old_fee),
+        "the requested fee rate must be higher than the fee rate of the original transaction"
+    );
+    let new_fee = new_fee_rate
+        .fee_vb(vsize)
+        .ok_or_else(|| anyhow!("the fee overflows"))?
+        .to_sat();
+
+    // BIP-125 requires the replacement to pay a higher fee rate (rule 6), as well as the fee of the
+    // original transaction plus the incremental relay fee for its own size (rules 3 and 4).
+    ensure!(
+        new_fee as u128 * old_vsize as u128 > old_fee as u128 * vsize as u128,
+        "the replacement must pay a higher fee rate than the original transaction"
+    );
+    ensure!(
+        new_fee >= old_fee + INCREMENTAL_RELAY_FEE_RATE * vsize,
+        "the replacement must pay for its own size at the incremental relay fee rate"
+    );
+
+    let new_balance = old_tx.output[0]
+        .value
+        .to_sat()
+        .checked_sub(new_fee - old_fee)
+        .ok_or_else(|| anyhow!("the program balance cannot cover the new fee"))?;
+    ensure!(
+        new_balance >= DUST_AMOUNT,
+        "the program balance would fall below the dust amount"
+    );
+
+    let mut tx = old_tx.clone();
+    tx.output.truncate(1);
+    tx.output[0].value = Amount::from_sat(new_balance);
+
+    // The witness starts with the hints of the covenant, followed by the application witness, the
+    // script, the control block, and the annex if present.
+    let script_tx_witness = tx.input[0].witness.to_vec();
+    let schema = get_hint_schema::<T>()?;
+    let (mut hints, num_hints) = HintWitness::parse(&schema, &script_tx_witness)?;
+    let new_state_hash = hints
+        .get(CovenantHint::NewStateHash)
+        .ok_or_else(|| anyhow!("the new state hash is missing"))?
+        .to_vec();
+
+    // The leaf script comes before the control block and the annex.
+    let leaf = script_tx_witness
+        .len()
+        .checked_sub(if annex.is_some() { 3 } else { 2 })
+        .map(|idx| Script::from_bytes(script_tx_witness[idx].clone()))
+        .ok_or_else(|| anyhow!("the leaf script is missing"))?;
+    ensure!(
+        TapLeafHash::from_script(&leaf, LeafVersion::TapScript) == tap_leaf_hash,
+        "the leaf script does not match the tap leaf hash"
+    );
+    let code_sep_pos = get_code_sep_pos::<T>(&leaf)?;
+
+    let (randomizer, e) = find_randomizer::<T>(
+        &mut tx,
+        &tx_template.prevouts,
+        &new_state_hash,
+        tap_leaf_hash,
+        code_sep_pos,
+        annex.as_ref(),
+    );
+
+    // Update the new balance, the randomizer, and the signature element "e".
+    hints.replace(CovenantHint::NewBalance, new_balance.to_le_bytes().to_vec());
+    hints.replace(CovenantHint::Randomizer, randomizer.to_le_bytes().to_vec());
+    hints.replace(CovenantHint::Challenge, e[0..31].to_vec());
+
+    let mut new_script_tx_witness = hints.finalize_before_groups();
+    new_script_tx_witness.extend_from_slice(&script_tx_witness[num_hints..]);
+    tx.input[0].witness = Witness::from_slice(&new_script_tx_witness);
+
+    // The outputs have changed, so the deposit input needs to be signed again.
+    if let Some(signer) = deposit_signer {
+        tx.input[1].witness = signer.sign(&tx, 1, &tx_template.prevouts)?;
+    }
+
+    let tx_template = TxTemplate {
+        tx,
+        prevouts: tx_template.prevouts.clone(),
+        input_idx: tx_template.input_idx,
+        taproot_annex_scriptleaf: tx_template.taproot_annex_scriptleaf.clone(),
     };
 
-    (tx_template, randomizer)
+    Ok((tx_template, randomizer))
 }
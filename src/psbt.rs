@@ -0,0 +1,177 @@
+use crate::{try_get_script_pub_key, CovenantProgram};
+use anyhow::{anyhow, ensure, Result};
+use bitcoin::psbt::Psbt;
+use bitcoin::taproot::{ControlBlock, LeafVersion};
+use bitcoin::{ScriptBuf, TapNodeHash, TxOut, Witness};
+use bitcoin_scriptexec::TxTemplate;
+
+/// Export a transaction generated by `get_tx` (or `bump_fee`) as a PSBT.
+///
+/// The covenant input is finalized, and it also carries the leaf script, the control block, the
+/// internal key, and the Merkle root (BIP-371) for inspection. The deposit input, whose previous
+/// output needs to be provided, carries the `witness_utxo`, and it is left for external signers
+/// unless its witness has already been provided. The covenant input carries the sighash type of
+/// the program, and since no sighash type commits to the witness of another input, signing the
+/// deposit input does not affect the covenant input.
+pub fn get_psbt<T: CovenantProgram>(
+    tx_template: &TxTemplate,
+    deposit_prevout: Option<TxOut>,
+) -> Result<Psbt> {
+    ensure!(
+        !tx_template.tx.input.is_empty() && !tx_template.prevouts.is_empty(),
+        "the covenant input and its previous output must be provided"
+    );
+    ensure!(
+        tx_template.prevouts[0].script_pubkey == try_get_script_pub_key::<T>()?,
+        "the transaction does not belong to this covenant program"
+    );
+    ensure!(
+        (tx_template.tx.input.len() == 2) == deposit_prevout.is_some(),
+        "the deposit previous output must be provided if and only if there is a deposit input"
+    );
+
+    let mut unsigned_tx = tx_template.tx.clone();
+    for input in unsigned_tx.input.iter_mut() {
+        input.witness = Witness::new();
+    }
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+
+    // The covenant witness ends with the full script and the control block, followed by the
+    // annex if present.
+    let covenant_witness = tx_template.tx.input[0].witness.clone();
+    let control_block_bytes = covenant_witness
+        .taproot_control_block()
+        .ok_or_else(|| anyhow!("the covenant input has no control block"))?;
+    let control_block = ControlBlock::decode(control_block_bytes)?;
+    let script = ScriptBuf::from_bytes(
+        covenant_witness
+            .tapscript()
+            .ok_or_else(|| anyhow!("the covenant input has no script"))?
+            .to_bytes(),
+    );
+
+    let (tap_leaf_hash, _) = tx_template
+        .taproot_annex_scriptleaf
+        .clone()
+        .ok_or_else(|| anyhow!("the transaction does not spend a covenant leaf"))?;
+
+    // Compute the Merkle root from the leaf and the Merkle path in the control block.
+    let mut merkle_root = TapNodeHash::from(tap_leaf_hash);
+    for sibling in control_block.merkle_branch.iter() {
+        merkle_root = TapNodeHash::from_node_hashes(merkle_root, *sibling);
+    }
+
+    let covenant_input = &mut psbt.inputs[0];
+    covenant_input.witness_utxo = Some(tx_template.prevouts[0].clone());
+    covenant_input.sighash_type = Some(T::SIGHASH_TYPE.into());
+    covenant_input.tap_internal_key = Some(control_block.internal_key);
+    covenant_input.tap_merkle_root = Some(merkle_root);
+    covenant_input
+        .tap_scripts
+        .insert(control_block, (script, LeafVersion::TapScript));
+    covenant_input.final_script_witness = Some(covenant_witness);
+
+    if let Some(deposit_prevout) = deposit_prevout {
+        let deposit_input = &mut psbt.inputs[1];
+        deposit_input.witness_utxo = Some(deposit_prevout);
+
+        let deposit_witness = &tx_template.tx.input[1].witness;
+        if !deposit_witness.is_empty() {
+            deposit_input.final_script_witness = Some(deposit_witness.clone());
+        }
+    }
+
+    Ok(psbt)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::examples::counter::{CounterInput, CounterProgram, CounterState};
+    use crate::psbt::get_psbt;
+    use crate::treepp::*;
+    use crate::{get_script_pub_key, get_tx, CovenantInput, CovenantProgram, DUST_AMOUNT};
+    use bitcoin::hashes::Hash;
+    use bitcoin::psbt::Psbt;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Txid, WScriptHash, Witness};
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_psbt() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut get_rand_txid = || {
+            let mut bytes = [0u8; 20];
+            prng.fill_bytes(&mut bytes);
+            Txid::hash(&bytes)
+        };
+
+        let trivial_p2wsh_script = script! {
+            OP_TRUE
+        };
+        let deposit_prevout = TxOut {
+            value: Amount::from_sat(123_456_000),
+            script_pubkey: ScriptBuf::new_p2wsh(&WScriptHash::hash(
+                trivial_p2wsh_script.as_bytes(),
+            )),
+        };
+
+        let info = CovenantInput {
+            old_randomizer: 12,
+            old_balance: 1_000_000_000,
+            old_txid: get_rand_txid(),
+            input_outpoint1: OutPoint::new(get_rand_txid(), 0),
+            input_outpoint2: None,
+            optional_deposit_input: Some(TxIn {
+                previous_output: OutPoint::new(get_rand_txid(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }),
+            optional_deposit_prevout: Some(deposit_prevout.clone()),
+            new_balance: 1_000_000_000 + 123_456_000 - 10_000 - DUST_AMOUNT,
+        };
+
+        let old_state = CounterState { counter: 0 };
+        let new_state = CounterState { counter: 1 };
+        let (tx_template, _) =
+            get_tx::<CounterProgram>(&info, 123456, &old_state, &new_state, &CounterInput(None));
+
+        let mut psbt =
+            get_psbt::<CounterProgram>(&tx_template, Some(deposit_prevout.clone())).unwrap();
+
+        // The PSBT survives serialization.
+        let psbt_bytes = psbt.serialize();
+        assert_eq!(Psbt::deserialize(&psbt_bytes).unwrap(), psbt);
+
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let (control_block, (script, _)) = psbt.inputs[0].tap_scripts.first_key_value().unwrap();
+        let output_key = get_script_pub_key::<CounterProgram>().as_bytes()[2..].to_vec();
+        assert!(control_block.verify_taproot_commitment(
+            &secp,
+            bitcoin::XOnlyPublicKey::from_slice(&output_key).unwrap(),
+            script
+        ));
+        assert_eq!(
+            psbt.inputs[0].sighash_type,
+            Some(CounterProgram::SIGHASH_TYPE.into())
+        );
+        assert_eq!(
+            psbt.inputs[0].final_script_witness.as_ref(),
+            Some(&tx_template.tx.input[0].witness)
+        );
+        assert!(psbt.inputs[1].final_script_witness.is_none());
+        assert_eq!(psbt.inputs[1].witness_utxo, Some(deposit_prevout));
+
+        // An external signer finalizes the deposit input.
+        let mut deposit_witness = Witness::new();
+        deposit_witness.push([]);
+        deposit_witness.push(trivial_p2wsh_script);
+        psbt.inputs[1].final_script_witness = Some(deposit_witness.clone());
+
+        let tx = psbt.extract_tx().unwrap();
+        assert_eq!(tx.input[0].witness, tx_template.tx.input[0].witness);
+        assert_eq!(tx.input[1].witness, deposit_witness);
+        assert_eq!(tx.output, tx_template.tx.output);
+    }
+}
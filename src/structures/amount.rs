@@ -33,6 +33,16 @@ impl AmountGadget {
     pub fn from_u64_in_16bit_limbs() -> Script {
         CppUInt64Gadget::from_u64_in_16bit_limbs()
     }
+
+    /// Convert the amount into u64 represented in four 16-bit limbs, using hints.
+    pub fn to_u64_in_16bit_limbs() -> Script {
+        CppUInt64Gadget::to_u64_in_16bit_limbs()
+    }
+
+    /// Convert the amount into u64 represented in limbs of 31, 31, and 2 bits, using hints.
+    pub fn to_u64_in_31bit_limbs() -> Script {
+        CppUInt64Gadget::to_u64_in_31bit_limbs()
+    }
 }
 
 #[cfg(test)]
@@ -1,12 +1,15 @@
+use crate::internal_structures::variable_length_integer::VariableLengthIntegerGadget;
 use crate::treepp::*;
+use crate::utils::pseudo::OP_CAT3;
 use bitcoin::consensus::Encodable;
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::sighash::Annex;
 
-/// Gadget for the annex (currently unused).
+/// Gadget for the annex.
 pub struct AnnexGadget;
 
 impl AnnexGadget {
-    /// Construct an empty annex (which is the only supported format for now).
+    /// Construct an empty annex, used when the annex is not present.
     pub fn none() -> Script {
         script! {
             OP_PUSHBYTES_0
@@ -22,4 +25,68 @@ impl AnnexGadget {
             { bytes }
         }
     }
+
+    /// Construct the hash of the annex (sha_annex in BIP-341) from constant data.
+    pub fn sha_from_constant(annex: &Annex) -> Script {
+        let mut bytes = vec![];
+        annex.consensus_encode(&mut bytes).unwrap();
+
+        script! {
+            { sha256::Hash::hash(&bytes).to_byte_array().to_vec() }
+        }
+    }
+
+    /// Construct the hash of the annex (sha_annex in BIP-341) from the provided annex on the
+    /// stack, without its first byte.
+    ///
+    /// The annex always starts with 0x50, which is added here, so the provided data is the rest of
+    /// the annex. Since the serialized annex, including its length, needs to fit in a single stack
+    /// element, the annex can be up to 517 bytes.
+    pub fn from_provided() -> Script {
+        script! {
+            OP_SIZE OP_1ADD
+            { VariableLengthIntegerGadget::from_stack_element_size() }
+
+            // the annex prefix 0x50
+            OP_PUSHBYTES_1 OP_RESERVED
+
+            OP_ROT OP_CAT3
+            OP_SHA256
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::structures::annex::AnnexGadget;
+    use crate::treepp::*;
+    use bitcoin::sighash::Annex;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_annex() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for len in [1, 2, 100, 127, 128, 129, 252, 253, 254, 255, 256, 517] {
+            let mut bytes = vec![0u8; len];
+            prng.fill_bytes(&mut bytes);
+            bytes[0] = 0x50;
+            if len > 1 {
+                bytes[1] = prng.gen_range(0x11..=0x80);
+            }
+
+            let annex = Annex::new(&bytes).unwrap();
+
+            let script = script! {
+                { bytes[1..].to_vec() }
+                { AnnexGadget::from_provided() }
+                { AnnexGadget::sha_from_constant(&annex) }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
 }
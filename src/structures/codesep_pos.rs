@@ -1,6 +1,8 @@
 use crate::internal_structures::cpp_int_32::CppInt32Gadget;
 use crate::treepp::*;
-use bitcoin::opcodes::all::OP_PUSHBYTES_4;
+use anyhow::{anyhow, ensure, Result};
+use bitcoin::opcodes::all::{OP_CODESEPARATOR, OP_ENDIF, OP_IF, OP_NOTIF, OP_PUSHBYTES_4};
+use bitcoin::script::Instruction;
 
 /// Gadget for the code separator position.
 pub struct CodeSepPosGadget;
@@ -29,3 +31,81 @@ impl CodeSepPosGadget {
         CppInt32Gadget::from_positive_bitcoin_integer()
     }
 }
+
+/// Find the position of the last OP_CODESEPARATOR among the first `end` opcodes of the leaf
+/// script, which is the code separator position in the signature hash of a signature check at
+/// the opcode position `end` (BIP-342).
+///
+/// The position counts the opcodes (including the data pushes) from the beginning of the leaf
+/// script. The whole leaf script needs to parse, and an OP_CODESEPARATOR among the first `end`
+/// opcodes must not be inside a conditional branch, since it would only be executed in some of
+/// the branches.
+pub fn get_last_code_sep_pos(leaf: &Script, end: usize) -> Result<Option<u32>> {
+    let mut last = None;
+    let mut depth = 0usize;
+    for (pos, instruction) in leaf.instructions().enumerate() {
+        let instruction =
+            instruction.map_err(|e| anyhow!("the opcode at position {} is invalid: {}", pos, e))?;
+        if pos >= end {
+            continue;
+        }
+        match instruction {
+            Instruction::Op(OP_IF) | Instruction::Op(OP_NOTIF) => depth += 1,
+            Instruction::Op(OP_ENDIF) => depth = depth.saturating_sub(1),
+            Instruction::Op(OP_CODESEPARATOR) => {
+                ensure!(
+                    depth == 0,
+                    "the OP_CODESEPARATOR at position {} is inside a conditional branch",
+                    pos
+                );
+                last = Some(pos as u32);
+            }
+            _ => {}
+        }
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::structures::codesep_pos::get_last_code_sep_pos;
+    use crate::treepp::*;
+
+    #[test]
+    fn test_get_last_code_sep_pos() {
+        let script = script! { 1 OP_DROP };
+        assert_eq!(get_last_code_sep_pos(&script, 2).unwrap(), None);
+
+        let script = script! {
+            OP_CODESEPARATOR
+            { vec![0xab; 40] } OP_DROP
+            OP_CODESEPARATOR
+            OP_TRUE
+        };
+        assert_eq!(get_last_code_sep_pos(&script, 5).unwrap(), Some(3));
+        // only the separators before the signature check count
+        assert_eq!(get_last_code_sep_pos(&script, 3).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn test_code_sep_in_branch() {
+        let script = script! {
+            OP_CODESEPARATOR
+            OP_IF
+                OP_CODESEPARATOR
+            OP_ENDIF
+            OP_TRUE
+        };
+        assert!(get_last_code_sep_pos(&script, 5).is_err());
+
+        // the separators after the signature check are not checked
+        assert_eq!(get_last_code_sep_pos(&script, 1).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn test_invalid_script() {
+        // a push of 4 bytes with only 2 bytes left
+        let script = Script::from_bytes(vec![0xab, 0x04, 0x01, 0x02]);
+        assert!(get_last_code_sep_pos(&script, 0).is_err());
+    }
+}
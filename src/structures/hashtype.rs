@@ -62,4 +62,28 @@ impl HashTypeGadget {
             OP_VERIFY
         }
     }
+
+    /// Construct the hash type from the provided hash type on the stack, and also output the
+    /// flags for the later gadgets to branch on.
+    ///
+    /// Output:
+    /// - hash type
+    /// - base type: 1 for all (including default), 2 for none, 3 for single
+    /// - anyonecanpay flag: 1 if anyonecanpay, 0 otherwise
+    ///
+    pub fn from_provided_with_flags() -> Script {
+        script! {
+            { HashTypeGadget::from_provided() }
+
+            // The default hash type 0x00 is not a minimally-encoded number, and it needs to be
+            // handled separately. The other hash types are valid numbers: 1, 2, 3, -1, -2, -3.
+            OP_DUP OP_PUSHBYTES_1 OP_PUSHBYTES_0 OP_EQUAL
+            OP_IF
+                1 0
+            OP_ELSE
+                OP_DUP OP_ABS
+                OP_OVER 0 OP_LESSTHAN
+            OP_ENDIF
+        }
+    }
 }
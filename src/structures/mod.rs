@@ -34,6 +34,9 @@ pub mod spend_type;
 /// Module for the tagged hash.
 pub mod tagged_hash;
 
+/// Module for the tap branch hash and the taproot Merkle path.
+pub mod tap_branch;
+
 /// Module for the tap leaf hash.
 pub mod tap_leaf_hash;
 
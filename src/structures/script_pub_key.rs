@@ -1,9 +1,12 @@
 use crate::internal_structures::variable_length_integer::VariableLengthIntegerGadget;
 use crate::treepp::*;
 use bitcoin::opcodes::all::{
-    OP_PUSHBYTES_20, OP_PUSHBYTES_22, OP_PUSHBYTES_32, OP_PUSHBYTES_34, OP_PUSHNUM_1,
+    OP_CHECKSIG, OP_DUP, OP_EQUAL, OP_EQUALVERIFY, OP_HASH160, OP_PUSHBYTES_2, OP_PUSHBYTES_20,
+    OP_PUSHBYTES_22, OP_PUSHBYTES_23, OP_PUSHBYTES_25, OP_PUSHBYTES_32, OP_PUSHBYTES_34,
+    OP_PUSHBYTES_4, OP_PUSHNUM_1,
 };
 use bitcoin::opcodes::OP_0;
+use bitcoin::script::PushBytesBuf;
 use bitcoin::ScriptBuf;
 
 /// Wrapper for supported script pub keys.
@@ -14,10 +17,20 @@ pub enum ScriptPubKey {
     P2WSH(Vec<u8>),
     /// pay-to-taproot, given the 32-byte taproot point
     P2TR(Vec<u8>),
+    /// pay-to-anchor, which is always `OP_1 <0x4e73>`
+    P2A,
+    /// pay-to-public-key-hash (legacy), given the 20-byte public key hash
+    P2PKH(Vec<u8>),
+    /// pay-to-script-hash (legacy), given the 20-byte script hash
+    P2SH(Vec<u8>),
+    /// data carrier, which is `OP_RETURN <data>`, given the data
+    OpReturn(Vec<u8>),
+    /// any other script pub key
+    Other(ScriptBuf),
 }
 
 /// Enums for different types of supported script pub keys.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ScriptPubKeyType {
     /// pay-to-witness-public-key-hash
     P2WPKH,
@@ -25,6 +38,67 @@ pub enum ScriptPubKeyType {
     P2WSH,
     /// pay-to-taproot
     P2TR,
+    /// pay-to-anchor
+    P2A,
+    /// pay-to-public-key-hash
+    P2PKH,
+    /// pay-to-script-hash
+    P2SH,
+    /// data carrier
+    OpReturn,
+    /// any other script pub key
+    Other,
+}
+
+impl ScriptPubKey {
+    /// Return the type of the script pub key.
+    pub fn script_pub_key_type(&self) -> ScriptPubKeyType {
+        match self {
+            ScriptPubKey::P2WPKH(_) => ScriptPubKeyType::P2WPKH,
+            ScriptPubKey::P2WSH(_) => ScriptPubKeyType::P2WSH,
+            ScriptPubKey::P2TR(_) => ScriptPubKeyType::P2TR,
+            ScriptPubKey::P2A => ScriptPubKeyType::P2A,
+            ScriptPubKey::P2PKH(_) => ScriptPubKeyType::P2PKH,
+            ScriptPubKey::P2SH(_) => ScriptPubKeyType::P2SH,
+            ScriptPubKey::OpReturn(_) => ScriptPubKeyType::OpReturn,
+            ScriptPubKey::Other(_) => ScriptPubKeyType::Other,
+        }
+    }
+}
+
+impl ScriptPubKeyType {
+    /// Return the type of a script pub key.
+    pub fn from_script(script_buf: &ScriptBuf) -> Self {
+        if script_buf.is_p2wpkh() {
+            ScriptPubKeyType::P2WPKH
+        } else if script_buf.is_p2wsh() {
+            ScriptPubKeyType::P2WSH
+        } else if script_buf.is_p2tr() {
+            ScriptPubKeyType::P2TR
+        } else if script_buf.as_bytes() == [0x51, 0x02, 0x4e, 0x73] {
+            ScriptPubKeyType::P2A
+        } else if script_buf.is_p2pkh() {
+            ScriptPubKeyType::P2PKH
+        } else if script_buf.is_p2sh() {
+            ScriptPubKeyType::P2SH
+        } else if script_buf.is_op_return() {
+            ScriptPubKeyType::OpReturn
+        } else {
+            ScriptPubKeyType::Other
+        }
+    }
+
+    /// Return the length of the script pub key if it is fixed for this type.
+    pub fn fixed_len(&self) -> Option<usize> {
+        match self {
+            ScriptPubKeyType::P2WPKH => Some(22),
+            ScriptPubKeyType::P2WSH | ScriptPubKeyType::P2TR => Some(34),
+            ScriptPubKeyType::P2A => Some(4),
+            ScriptPubKeyType::P2PKH => Some(25),
+            ScriptPubKeyType::P2SH => Some(23),
+            ScriptPubKeyType::OpReturn | ScriptPubKeyType::Other => None,
+        }
+    }
 }
 
 /// Gadget for the script public key.
@@ -70,6 +144,46 @@ impl ScriptPubKeyGadget {
         Script::from_bytes(script)
     }
 
+    /// Construct the pay-to-anchor script public key.
+    pub fn p2a() -> Script {
+        Script::from_bytes(vec![
+            OP_PUSHBYTES_4.to_u8(),
+            OP_PUSHNUM_1.to_u8(),
+            OP_PUSHBYTES_2.to_u8(),
+            0x4e,
+            0x73,
+        ])
+    }
+
+    /// Construct the script public key from the public key hash (legacy).
+    pub fn p2pkh_from_constant_hash(pkhash: &[u8]) -> Script {
+        assert_eq!(pkhash.len(), 20);
+
+        let mut script = vec![
+            OP_PUSHBYTES_25.to_u8(),
+            OP_DUP.to_u8(),
+            OP_HASH160.to_u8(),
+            OP_PUSHBYTES_20.to_u8(),
+        ];
+        script.extend_from_slice(pkhash);
+        script.extend_from_slice(&[OP_EQUALVERIFY.to_u8(), OP_CHECKSIG.to_u8()]);
+        Script::from_bytes(script)
+    }
+
+    /// Construct the script public key from the script hash (legacy).
+    pub fn p2sh_from_constant_hash(script_hash: &[u8]) -> Script {
+        assert_eq!(script_hash.len(), 20);
+
+        let mut script = vec![
+            OP_PUSHBYTES_23.to_u8(),
+            OP_HASH160.to_u8(),
+            OP_PUSHBYTES_20.to_u8(),
+        ];
+        script.extend_from_slice(script_hash);
+        script.push(OP_EQUAL.to_u8());
+        Script::from_bytes(script)
+    }
+
     /// Construct the script public key from the `ScriptPubKey` struct.
     pub fn from_constructor(script_pub_key: &ScriptPubKey) -> Script {
         match script_pub_key {
@@ -88,6 +202,26 @@ impl ScriptPubKeyGadget {
                 { ScriptPubKeyGadget::p2tr_from_public_key(public_key) }
                 OP_CAT
             },
+            ScriptPubKey::P2A => script! {
+                { VariableLengthIntegerGadget::from_constant(4) }
+                { ScriptPubKeyGadget::p2a() }
+                OP_CAT
+            },
+            ScriptPubKey::P2PKH(pkhash) => script! {
+                { VariableLengthIntegerGadget::from_constant(25) }
+                { ScriptPubKeyGadget::p2pkh_from_constant_hash(pkhash) }
+                OP_CAT
+            },
+            ScriptPubKey::P2SH(script_hash) => script! {
+                { VariableLengthIntegerGadget::from_constant(23) }
+                { ScriptPubKeyGadget::p2sh_from_constant_hash(script_hash) }
+                OP_CAT
+            },
+            ScriptPubKey::OpReturn(data) => {
+                let data = PushBytesBuf::try_from(data.clone()).unwrap();
+                ScriptPubKeyGadget::from_constant(&ScriptBuf::new_op_return(data))
+            }
+            ScriptPubKey::Other(script_buf) => ScriptPubKeyGadget::from_constant(script_buf),
         }
     }
 
@@ -102,32 +236,88 @@ impl ScriptPubKeyGadget {
 
     /// Construct the script public key from the provided data on the stack.
     ///
-    /// It accepts 22 bytes (P2WPKH) and 34 bytes (P2WSH or P2TR).
+    /// It accepts 22 bytes (P2WPKH), 34 bytes (P2WSH or P2TR), 25 bytes (P2PKH), 23 bytes
+    /// (P2SH), and the 4-byte P2A. Other scripts need [`Self::from_provided_arbitrary`].
     pub fn from_provided() -> Script {
         script! {
             OP_SIZE 22 OP_EQUAL
             OP_IF
                 OP_PUSHBYTES_1 OP_PUSHBYTES_22
             OP_ELSE
-                OP_SIZE 34 OP_EQUALVERIFY
-                OP_PUSHBYTES_1 OP_PUSHBYTES_34
+                OP_SIZE 34 OP_EQUAL
+                OP_IF
+                    OP_PUSHBYTES_1 OP_PUSHBYTES_34
+                OP_ELSE
+                    OP_SIZE 25 OP_EQUAL
+                    OP_IF
+                        OP_PUSHBYTES_1 OP_PUSHBYTES_25
+                    OP_ELSE
+                        OP_SIZE 23 OP_EQUAL
+                        OP_IF
+                            OP_PUSHBYTES_1 OP_PUSHBYTES_23
+                        OP_ELSE
+                            OP_DUP { ScriptPubKeyGadget::p2a() } OP_EQUALVERIFY
+                            4
+                        OP_ENDIF
+                    OP_ENDIF
+                OP_ENDIF
             OP_ENDIF
             OP_SWAP OP_CAT
         }
     }
+
+    /// Construct the script public key of the given type from the provided data on the stack.
+    ///
+    /// It checks the length of the types with a fixed length (and the bytes of P2A), and accepts
+    /// any script with at most 517 bytes for the data carrier and other types.
+    pub fn from_provided_with_type(script_pub_key_type: ScriptPubKeyType) -> Script {
+        match script_pub_key_type {
+            ScriptPubKeyType::P2A => script! {
+                OP_DUP { ScriptPubKeyGadget::p2a() } OP_EQUALVERIFY
+                { VariableLengthIntegerGadget::from_constant(4) }
+                OP_SWAP OP_CAT
+            },
+            ScriptPubKeyType::OpReturn | ScriptPubKeyType::Other => {
+                ScriptPubKeyGadget::from_provided_arbitrary()
+            }
+            _ => {
+                let len = script_pub_key_type.fixed_len().unwrap();
+                script! {
+                    OP_SIZE { len } OP_EQUALVERIFY
+                    { VariableLengthIntegerGadget::from_constant(len) }
+                    OP_SWAP OP_CAT
+                }
+            }
+        }
+    }
+
+    /// Construct the script public key from the provided data on the stack, which can be any
+    /// script, such as a legacy one or a data carrier, with at most 517 bytes (so that the result
+    /// fits in a stack element).
+    ///
+    /// The compact size of the length is computed in the script.
+    pub fn from_provided_arbitrary() -> Script {
+        script! {
+            OP_SIZE 518 OP_LESSTHAN OP_VERIFY
+            OP_SIZE { VariableLengthIntegerGadget::from_stack_element_size() }
+            OP_SWAP OP_CAT
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::structures::script_pub_key::{ScriptPubKey, ScriptPubKeyGadget};
+    use crate::structures::script_pub_key::{ScriptPubKey, ScriptPubKeyGadget, ScriptPubKeyType};
     use crate::treepp::*;
-    use crate::utils::pseudo::OP_CAT3;
+    use crate::utils::pseudo::OP_CAT4;
     use bitcoin::consensus::Encodable;
     use bitcoin::hashes::Hash;
     use bitcoin::key::TweakedPublicKey;
+    use bitcoin::script::PushBytesBuf;
     use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{PubkeyHash, ScriptHash};
     use bitcoin::{ScriptBuf, WPubkeyHash, WScriptHash, XOnlyPublicKey};
-    use rand::{RngCore, SeedableRng};
+    use rand::{Rng, RngCore, SeedableRng};
     use rand_chacha::ChaCha20Rng;
     use sha2::digest::Update;
     use sha2::{Digest, Sha256};
@@ -151,6 +341,8 @@ mod test {
             let pubkey = XOnlyPublicKey::from(keypair.1);
             let script_pub_key_3 = ScriptPubKey::P2TR(pubkey.serialize().to_vec());
 
+            let script_pub_key_4 = ScriptPubKey::P2A;
+
             let expected = {
                 let mut bytes = vec![];
 
@@ -160,10 +352,12 @@ mod test {
                     ScriptBuf::new_p2wsh(&WScriptHash::from_slice(&script_hash).unwrap());
                 let script_pubkey_3 =
                     ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(pubkey));
+                let script_pubkey_4 = ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73]);
 
                 script_pubkey_1.consensus_encode(&mut bytes).unwrap();
                 script_pubkey_2.consensus_encode(&mut bytes).unwrap();
                 script_pubkey_3.consensus_encode(&mut bytes).unwrap();
+                script_pubkey_4.consensus_encode(&mut bytes).unwrap();
 
                 let mut sha256 = Sha256::new();
                 Update::update(&mut sha256, &bytes);
@@ -176,7 +370,8 @@ mod test {
                 { ScriptPubKeyGadget::from_constructor(&script_pub_key_1) }
                 { ScriptPubKeyGadget::from_constructor(&script_pub_key_2) }
                 { ScriptPubKeyGadget::from_constructor(&script_pub_key_3) }
-                OP_CAT3
+                { ScriptPubKeyGadget::from_constructor(&script_pub_key_4) }
+                OP_CAT4
                 OP_SHA256
 
                 { expected }
@@ -187,4 +382,192 @@ mod test {
             assert!(exec_result.success);
         }
     }
+
+    #[test]
+    fn test_from_provided() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut hash = vec![0u8; 20];
+        prng.fill_bytes(&mut hash);
+        let mut script_hash = vec![0u8; 32];
+        prng.fill_bytes(&mut script_hash);
+        let pubkey = XOnlyPublicKey::from(Secp256k1::new().generate_keypair(&mut prng).1);
+        let mut data = vec![0u8; 40];
+        prng.fill_bytes(&mut data);
+
+        let test_cases = [
+            (
+                ScriptPubKey::P2WPKH(hash.clone()),
+                ScriptBuf::new_p2wpkh(&WPubkeyHash::from_slice(&hash).unwrap()),
+            ),
+            (
+                ScriptPubKey::P2WSH(script_hash.clone()),
+                ScriptBuf::new_p2wsh(&WScriptHash::from_slice(&script_hash).unwrap()),
+            ),
+            (
+                ScriptPubKey::P2TR(pubkey.serialize().to_vec()),
+                ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(pubkey)),
+            ),
+            (
+                ScriptPubKey::P2A,
+                ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73]),
+            ),
+            (
+                ScriptPubKey::P2PKH(hash.clone()),
+                ScriptBuf::new_p2pkh(&PubkeyHash::from_slice(&hash).unwrap()),
+            ),
+            (
+                ScriptPubKey::P2SH(hash.clone()),
+                ScriptBuf::new_p2sh(&ScriptHash::from_slice(&hash).unwrap()),
+            ),
+            (
+                ScriptPubKey::OpReturn(data.clone()),
+                ScriptBuf::new_op_return(PushBytesBuf::try_from(data.clone()).unwrap()),
+            ),
+            (
+                ScriptPubKey::Other(ScriptBuf::from_bytes(data.clone())),
+                ScriptBuf::from_bytes(data.clone()),
+            ),
+        ];
+
+        for (script_pub_key, script_buf) in test_cases.iter() {
+            let script_pub_key_type = script_pub_key.script_pub_key_type();
+            assert_eq!(
+                ScriptPubKeyType::from_script(script_buf),
+                script_pub_key_type
+            );
+
+            let mut expected = vec![];
+            script_buf.consensus_encode(&mut expected).unwrap();
+
+            // all the types with a fixed length are accepted by `from_provided`
+            if script_pub_key_type.fixed_len().is_some() {
+                let script = script! {
+                    { script_buf.to_bytes() }
+                    { ScriptPubKeyGadget::from_provided() }
+                    { expected.clone() }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+
+            let script = script! {
+                { script_buf.to_bytes() }
+                { ScriptPubKeyGadget::from_provided_with_type(script_pub_key_type) }
+                { ScriptPubKeyGadget::from_constructor(script_pub_key) }
+                OP_EQUALVERIFY
+                { script_buf.to_bytes() }
+                { ScriptPubKeyGadget::from_provided_with_type(script_pub_key_type) }
+                { expected }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        // a 4-byte script other than P2A
+        let script = script! {
+            { vec![0x51u8, 0x02, 0x4e, 0x74] }
+            { ScriptPubKeyGadget::from_provided() }
+            OP_DROP
+            OP_TRUE
+        };
+
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+
+        // a script of the wrong length for the type
+        let script = script! {
+            { vec![0xaau8; 24] }
+            { ScriptPubKeyGadget::from_provided_with_type(ScriptPubKeyType::P2PKH) }
+            OP_DROP
+            OP_TRUE
+        };
+
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    #[test]
+    fn test_legacy_and_data_carrier() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..10 {
+            let mut hash = vec![0u8; 20];
+            prng.fill_bytes(&mut hash);
+            let mut data = vec![0u8; prng.gen_range(0..80)];
+            prng.fill_bytes(&mut data);
+
+            let test_cases = [
+                (
+                    ScriptPubKey::P2PKH(hash.clone()),
+                    ScriptBuf::new_p2pkh(&PubkeyHash::from_slice(&hash).unwrap()),
+                ),
+                (
+                    ScriptPubKey::P2SH(hash.clone()),
+                    ScriptBuf::new_p2sh(&ScriptHash::from_slice(&hash).unwrap()),
+                ),
+                (
+                    ScriptPubKey::OpReturn(data.clone()),
+                    ScriptBuf::new_op_return(PushBytesBuf::try_from(data.clone()).unwrap()),
+                ),
+            ];
+
+            for (script_pub_key, expected) in test_cases.iter() {
+                let mut expected_bytes = vec![];
+                expected.consensus_encode(&mut expected_bytes).unwrap();
+
+                let script = script! {
+                    { ScriptPubKeyGadget::from_constructor(script_pub_key) }
+                    { expected_bytes.clone() }
+                    OP_EQUALVERIFY
+
+                    { expected.to_bytes() }
+                    { ScriptPubKeyGadget::from_provided_arbitrary() }
+                    { expected_bytes }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_provided_arbitrary() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for len in [0, 1, 100, 127, 128, 252, 253, 300, 517] {
+            let mut bytes = vec![0u8; len];
+            prng.fill_bytes(&mut bytes);
+            let script_buf = ScriptBuf::from_bytes(bytes.clone());
+
+            let mut expected = vec![];
+            script_buf.consensus_encode(&mut expected).unwrap();
+
+            let script = script! {
+                { bytes }
+                { ScriptPubKeyGadget::from_provided_arbitrary() }
+                { expected }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        let script = script! {
+            { vec![0u8; 518] }
+            { ScriptPubKeyGadget::from_provided_arbitrary() }
+            OP_DROP
+            OP_TRUE
+        };
+
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
 }
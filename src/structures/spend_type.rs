@@ -10,31 +10,69 @@ impl SpendTypeGadget {
         // 127 using a single byte in the stack without a lot of manual conversions.
         assert!(ext_flag == 0 || ext_flag == 1);
 
-        // annex has not been used in the mainnet.
-        assert_eq!(has_annex, false);
-
-        if ext_flag == 0 {
+        let spend_type = ext_flag * 2 + has_annex as u8;
+        if spend_type == 0 {
             script! {
                 OP_PUSHBYTES_1 OP_PUSHBYTES_0
             }
         } else {
             script! {
-                { 2 }
+                { spend_type as i64 }
             }
         }
     }
 
-    /// Construct the spend type from the provided type on the stack.
+    /// Construct the spend type from the provided spend type on the stack.
     ///
-    /// It verifies that the provided spend type is either 0 or 1.
+    /// It verifies that the provided spend type is between 0 and 3, i.e., the extension flag is
+    /// either 0 or 1, with or without the annex.
     pub fn from_provided() -> Script {
         script! {
+            OP_DUP 0 4 OP_WITHIN OP_VERIFY
             OP_DUP 0 OP_EQUAL OP_IF
-                OP_PUSHBYTES_1 OP_PUSHBYTES_0
-            OP_ELSE
-                OP_DUP 1 OP_EQUALVERIFY
-                OP_PUSHBYTES_1 OP_PUSHBYTES_1
+                OP_DROP OP_PUSHBYTES_1 OP_PUSHBYTES_0
             OP_ENDIF
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::structures::spend_type::SpendTypeGadget;
+    use crate::treepp::*;
+
+    #[test]
+    fn test_spend_type() {
+        for ext_flag in 0..2u8 {
+            for has_annex in [false, true] {
+                let spend_type = (ext_flag * 2 + has_annex as u8) as i64;
+
+                let script = script! {
+                    { SpendTypeGadget::from_constant(ext_flag, has_annex) }
+                    OP_SIZE 1 OP_EQUALVERIFY
+                    OP_DUP
+                    if spend_type == 0 {
+                        OP_PUSHBYTES_1 OP_PUSHBYTES_0
+                    } else {
+                        { spend_type }
+                    }
+                    OP_EQUALVERIFY
+
+                    { spend_type }
+                    { SpendTypeGadget::from_provided() }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+
+        let script = script! {
+            4
+            { SpendTypeGadget::from_provided() }
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+}
@@ -6,6 +6,8 @@ use sha2::Digest;
 pub enum HashTag {
     /// tap leaf hash, which hashes a script
     TapLeaf,
+    /// tap branch hash, which hashes two nodes in the taproot Merkle tree
+    TapBranch,
     /// tap tweak hash, which is used for tweaking
     TapTweak,
     /// tap sig hash, which is to compute the sighash for Taproot signature verification
@@ -19,6 +21,7 @@ impl HashTag {
     pub fn to_str(&self) -> &'static str {
         match self {
             HashTag::TapLeaf => "TapLeaf",
+            HashTag::TapBranch => "TapBranch",
             HashTag::TapTweak => "TapTweak",
             HashTag::TapSighash => "TapSighash",
             HashTag::BIP340Challenge => "BIP0340/challenge",
@@ -0,0 +1,162 @@
+use crate::structures::tagged_hash::{HashTag, TaggedHashGadget};
+use crate::treepp::*;
+use crate::utils::pseudo::OP_HINT;
+use bitcoin::TapNodeHash;
+
+/// Gadget for the tap branch hash, which combines two nodes in the taproot Merkle tree.
+pub struct TapBranchGadget;
+
+impl TapBranchGadget {
+    /// Compute the tap branch hash of the two provided nodes on the stack, which must already be
+    /// in the lexicographic order.
+    pub fn from_provided() -> Script {
+        script! {
+            OP_CAT
+            { TaggedHashGadget::from_provided(&HashTag::TapBranch) }
+        }
+    }
+
+    /// Compute the Merkle root from the provided node (such as a tap leaf hash) on the stack and a
+    /// hinted Merkle path of the given depth.
+    ///
+    /// Hint, for each level from the bottom:
+    /// - the sibling hash
+    /// - whether the sibling comes first (an empty string or 0x01)
+    ///
+    /// The order is hinted since Bitcoin script cannot compare two hashes lexicographically. This
+    /// does not weaken the proof: a wrong order results in a different Merkle root.
+    pub fn merkle_root_from_provided_path(depth: usize) -> Script {
+        script! {
+            OP_SIZE 32 OP_EQUALVERIFY
+            for _ in 0..depth {
+                // get a hint: the sibling hash
+                OP_HINT
+                OP_SIZE 32 OP_EQUALVERIFY
+
+                // get a hint: whether the sibling comes first
+                OP_HINT
+                OP_IF
+                    OP_SWAP
+                OP_ENDIF
+
+                { Self::from_provided() }
+            }
+        }
+    }
+
+    /// Generate the hints for [`TapBranchGadget::merkle_root_from_provided_path`], from the node
+    /// and the Merkle path (such as the one in the control block).
+    pub fn get_path_hints(node: TapNodeHash, path: &[TapNodeHash]) -> Vec<Vec<u8>> {
+        let mut hints = vec![];
+        let mut current = node;
+        for sibling in path.iter() {
+            hints.push(AsRef::<[u8]>::as_ref(sibling).to_vec());
+            if sibling < &current {
+                hints.push(vec![0x01]);
+            } else {
+                hints.push(vec![]);
+            }
+            current = TapNodeHash::from_node_hashes(current, *sibling);
+        }
+        hints
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::structures::tap_branch::TapBranchGadget;
+    use crate::treepp::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::taproot::{LeafVersion, TaprootBuilder};
+    use bitcoin::{TapLeafHash, TapNodeHash, XOnlyPublicKey};
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_tap_branch() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..10 {
+            let mut bytes = [0u8; 32];
+            prng.fill_bytes(&mut bytes);
+            let a = TapNodeHash::from_byte_array(bytes);
+            prng.fill_bytes(&mut bytes);
+            let b = TapNodeHash::from_byte_array(bytes);
+
+            let (first, second) = if a < b { (a, b) } else { (b, a) };
+
+            let script = script! {
+                { first.as_byte_array().to_vec() }
+                { second.as_byte_array().to_vec() }
+                { TapBranchGadget::from_provided() }
+                { TapNodeHash::from_node_hashes(a, b).as_byte_array().to_vec() }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_merkle_root_from_provided_path() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let secp = Secp256k1::new();
+        let internal_key = XOnlyPublicKey::from_str(
+            "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0",
+        )
+        .unwrap();
+
+        for _ in 0..5 {
+            let num_leaves = prng.gen_range(2..12);
+            let leaves = (0..num_leaves)
+                .map(|_| {
+                    let mut bytes = vec![0u8; prng.gen_range(1..50)];
+                    prng.fill_bytes(&mut bytes);
+                    Script::from_bytes(bytes)
+                })
+                .collect::<Vec<_>>();
+
+            let spend_info = TaprootBuilder::with_huffman_tree(
+                leaves
+                    .iter()
+                    .map(|leaf| (prng.gen_range(1..10), leaf.clone())),
+            )
+            .unwrap()
+            .finalize(&secp, internal_key)
+            .unwrap();
+            let merkle_root = spend_info.merkle_root().unwrap();
+
+            for leaf in leaves.iter() {
+                let control_block = spend_info
+                    .control_block(&(leaf.clone(), LeafVersion::TapScript))
+                    .unwrap();
+                let path = control_block.merkle_branch.as_slice();
+
+                let tap_leaf_hash = TapLeafHash::from_script(leaf, LeafVersion::TapScript);
+                let hints = TapBranchGadget::get_path_hints(tap_leaf_hash.into(), path);
+
+                let script = script! {
+                    for hint in hints.iter() {
+                        if hint.is_empty() {
+                            OP_PUSHBYTES_0
+                        } else if hint.len() == 1 {
+                            1
+                        } else {
+                            { hint.clone() }
+                        }
+                    }
+                    { tap_leaf_hash.as_byte_array().to_vec() }
+                    { TapBranchGadget::merkle_root_from_provided_path(path.len()) }
+                    { merkle_root.as_byte_array().to_vec() }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+    }
+}
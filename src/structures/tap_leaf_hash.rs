@@ -1,4 +1,7 @@
+use crate::internal_structures::variable_length_integer::VariableLengthIntegerGadget;
+use crate::structures::tagged_hash::{HashTag, TaggedHashGadget};
 use crate::treepp::*;
+use bitcoin::taproot::LeafVersion;
 use bitcoin::TapLeafHash;
 
 /// Gadget for tap leaf hash.
@@ -11,4 +14,70 @@ impl TapLeafHashGadget {
             { AsRef::<[u8]>::as_ref(&tap_leaf_hash).to_vec() }
         }
     }
+
+    /// Compute the tap leaf hash of the provided script on the stack, with the given leaf version.
+    ///
+    /// Since the tagged hash concatenates the message with the two hashed tags, the script can
+    /// have at most 452 bytes to stay within the 520-byte limit of a stack element.
+    pub fn from_provided_script(leaf_version: LeafVersion) -> Script {
+        script! {
+            OP_SIZE 453 OP_LESSTHAN OP_VERIFY
+
+            // prepend the compact size of the script
+            OP_SIZE { VariableLengthIntegerGadget::from_stack_element_size() }
+            OP_SWAP OP_CAT
+
+            // prepend the leaf version
+            { vec![leaf_version.to_consensus()] }
+            OP_SWAP OP_CAT
+
+            { TaggedHashGadget::from_provided(&HashTag::TapLeaf) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::structures::tap_leaf_hash::TapLeafHashGadget;
+    use crate::treepp::*;
+    use bitcoin::taproot::LeafVersion;
+    use bitcoin::TapLeafHash;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_tap_leaf_hash_from_provided_script() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut lens = vec![0, 1, 127, 128, 200, 252, 253, 300, 452];
+        for _ in 0..10 {
+            lens.push(prng.gen_range(0..453));
+        }
+
+        for len in lens {
+            let mut bytes = vec![0u8; len];
+            prng.fill_bytes(&mut bytes);
+            let leaf_script = Script::from_bytes(bytes.clone());
+
+            let expected = TapLeafHash::from_script(&leaf_script, LeafVersion::TapScript);
+
+            let script = script! {
+                { bytes }
+                { TapLeafHashGadget::from_provided_script(LeafVersion::TapScript) }
+                { TapLeafHashGadget::from_constant(&expected) }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        let script = script! {
+            { vec![0u8; 453] }
+            { TapLeafHashGadget::from_provided_script(LeafVersion::TapScript) }
+            OP_DROP OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
 }
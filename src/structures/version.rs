@@ -14,13 +14,53 @@ impl VersionGadget {
     }
 
     /// Construct the version from the data in the stack, verifying that
-    /// the data is either 1 or 2.
+    /// the data is either 1, 2, or 3 (TRUC).
     pub fn from_provided() -> Script {
         script! {
-            OP_DUP 1 OP_EQUAL
-            OP_OVER 2 OP_EQUAL OP_BOOLOR OP_VERIFY
+            OP_DUP 1 4 OP_WITHIN OP_VERIFY
             OP_PUSHBYTES_3 OP_PUSHBYTES_0 OP_PUSHBYTES_0 OP_PUSHBYTES_0
             OP_CAT
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::structures::version::VersionGadget;
+    use crate::treepp::*;
+    use bitcoin::consensus::Encodable;
+    use bitcoin::transaction::Version;
+
+    #[test]
+    fn test_version() {
+        for v in [1, 2, 3] {
+            let mut expected = vec![];
+            Version(v).consensus_encode(&mut expected).unwrap();
+
+            let script = script! {
+                { v }
+                { VersionGadget::from_provided() }
+                { expected.clone() }
+                OP_EQUALVERIFY
+                { VersionGadget::from_constant(&Version(v)) }
+                { expected }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        for v in [0, 4] {
+            let script = script! {
+                { v }
+                { VersionGadget::from_provided() }
+                OP_DROP
+                OP_TRUE
+            };
+
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+}
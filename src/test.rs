@@ -1,13 +1,23 @@
-use crate::treepp::*;
-use crate::{get_script_pub_key, get_tx, CovenantInput, CovenantProgram, DUST_AMOUNT};
+use crate::bitcoin_script::truc;
+use crate::coin_selection::{select_coins, CoinSelectionParams, CoinSelectionStrategy, Utxo};
+use crate::deposit::{sign_deposit_input, DepositSigner};
+use crate::psbt::get_psbt;
+use crate::{
+    bump_fee, check_leaves, check_truc_vsize, get_deposit_input_weight, get_script_pub_key,
+    get_tx_with_annex, CovenantInput, CovenantProgram, DUST_AMOUNT, INCREMENTAL_RELAY_FEE_RATE,
+};
 use bitcoin::absolute::LockTime;
 use bitcoin::hashes::Hash;
+use bitcoin::key::Keypair;
 use bitcoin::opcodes::all::{OP_PUSHBYTES_36, OP_RETURN};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::sighash::Annex;
 use bitcoin::transaction::Version;
 use bitcoin::{
-    Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, WScriptHash, Witness,
+    Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
     WitnessProgram,
 };
+use bitcoin_scriptexec::TxTemplate;
 use bitcoin_simulator::database::Database;
 use bitcoin_simulator::policy::Policy;
 use rand::{Rng, RngCore, SeedableRng};
@@ -29,6 +39,9 @@ pub fn simulation_test_with_policy<T: CovenantProgram>(
     test_generator: &mut impl FnMut(&T::State) -> Option<SimulationInstruction<T>>,
     policy: &Policy,
 ) -> u64 {
+    // Check the leaves before spending them.
+    check_leaves::<T>().unwrap();
+
     let mut total_fees = 0;
     let prng = Rc::new(RefCell::new(ChaCha20Rng::seed_from_u64(0)));
     let get_rand_txid = || {
@@ -52,8 +65,8 @@ pub fn simulation_test_with_policy<T: CovenantProgram>(
     let prev_witness_program = WitnessProgram::p2wsh(&ScriptBuf::from_bytes(script_bytes));
 
     // initialize the counter and accept it unconditionally
-    let init_tx = Transaction {
-        version: Version::TWO,
+    let mut init_tx = Transaction {
+        version: if T::TRUC { Version(3) } else { Version::TWO },
         lock_time: LockTime::ZERO,
         input: vec![TxIn {
             previous_output: OutPoint {
@@ -75,23 +88,24 @@ pub fn simulation_test_with_policy<T: CovenantProgram>(
             },
         ],
     };
+    if T::TRUC {
+        init_tx.output.push(truc::anchor_output());
+    }
 
     // Ignore whether the TxIn is valid, make the outputs available in the network.
     db.insert_transaction_unconditionally(&init_tx).unwrap();
 
-    // Prepare the trivial script, which is used for testing purposes to deposit more money
-    // into the program.
-    let trivial_p2wsh_script = script! {
-        OP_TRUE
+    // Prepare the keys, which are used for testing purposes to deposit more money into the program.
+    let secp = Secp256k1::new();
+    let get_rand_signer = || {
+        let keypair = Keypair::new(&secp, &mut *prng.borrow_mut());
+        match prng.borrow_mut().gen_range(0..3) {
+            0 => DepositSigner::P2WPKH(keypair),
+            1 => DepositSigner::P2TRKeyPath(keypair),
+            _ => DepositSigner::P2TRScriptPath(keypair),
+        }
     };
 
-    let trivial_p2wsh_script_pubkey =
-        ScriptBuf::new_p2wsh(&WScriptHash::hash(trivial_p2wsh_script.as_bytes()));
-
-    let mut trivial_p2wsh_witness = Witness::new();
-    trivial_p2wsh_witness.push([]);
-    trivial_p2wsh_witness.push(trivial_p2wsh_script);
-
     // Initialize the state.
     let mut old_state = init_state;
     let mut old_randomizer = init_randomizer;
@@ -104,15 +118,14 @@ pub fn simulation_test_with_policy<T: CovenantProgram>(
     #[cfg(feature = "debug")]
     eprintln!("{:?}", old_state);
 
-    for _ in 0..repeat {
-        let mut has_deposit_input = prng.borrow_mut().gen::<bool>();
+    // The wallet, from which the deposit inputs are selected, together with their signers.
+    let mut wallet: Vec<(Utxo, DepositSigner)> = vec![];
 
-        if old_balance < 700_000u64 {
-            has_deposit_input = true;
-        }
+    for _ in 0..repeat {
+        // Fund the wallet with a new UTXO, which can be deposited into the program.
+        {
+            let signer = get_rand_signer();
 
-        // If there is a deposit input
-        let deposit_input = if has_deposit_input {
             let fee_tx = Transaction {
                 version: Version::TWO,
                 lock_time: LockTime::ZERO,
@@ -127,24 +140,25 @@ pub fn simulation_test_with_policy<T: CovenantProgram>(
                 }], // a random input is needed to avoid TXID collision.
                 output: vec![TxOut {
                     value: Amount::from_sat(123_456_000),
-                    script_pubkey: trivial_p2wsh_script_pubkey.clone(),
+                    script_pubkey: signer.script_pub_key(),
                 }],
             };
 
             db.insert_transaction_unconditionally(&fee_tx).unwrap();
 
-            Some(TxIn {
-                previous_output: OutPoint {
+            let utxo = Utxo {
+                outpoint: OutPoint {
                     txid: fee_tx.compute_txid(),
                     vout: 0,
                 },
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-                witness: trivial_p2wsh_witness.clone(),
-            })
-        } else {
-            None
-        };
+                tx_out: fee_tx.output[0].clone(),
+                input_weight: get_deposit_input_weight::<T>(
+                    signer.input_weight(),
+                    &fee_tx.output[0],
+                ),
+            };
+            wallet.push((utxo, signer));
+        }
 
         let next_step = test_generator(&old_state);
         if next_step.is_none() {
@@ -155,30 +169,96 @@ pub fn simulation_test_with_policy<T: CovenantProgram>(
             program_input: input,
         } = next_step.unwrap();
 
-        let mut new_balance = old_balance;
-        if deposit_input.is_some() {
-            new_balance += 123_456_000;
-        }
+        // If the program allows, the covenant input carries an annex once in a while.
+        let annex_bytes = if T::ANNEX && prng.borrow_mut().gen::<bool>() {
+            let mut bytes = vec![0x50u8; prng.borrow_mut().gen_range(2..100)];
+            prng.borrow_mut().fill_bytes(&mut bytes[1..]);
+            Some(bytes)
+        } else {
+            None
+        };
+        let annex = annex_bytes.as_ref().map(|bytes| Annex::new(bytes).unwrap());
 
-        // test for the fee
-        let fee = {
-            let info = CovenantInput {
-                old_randomizer,
-                old_balance,
-                old_txid: old_txid.clone(),
-                input_outpoint1: old_tx_outpoint1.clone(),
-                input_outpoint2: old_tx_outpoint2.clone(),
-                optional_deposit_input: deposit_input.clone(),
-                new_balance: 0,
-            };
-            let new_state = T::run(id, &old_state, &input).unwrap();
-            let (tx_template, _) = get_tx::<T>(&info, id, &old_state, &new_state, &input);
-            db.calculate_fees(&tx_template.tx, &policy).unwrap()
+        let new_state = T::run(id, &old_state, &input).unwrap();
+
+        // Predict the weight of the transaction without a deposit input, and find the fee rate
+        // that the policy requires, which would be paid by a child transaction in the TRUC format.
+        let base_info = CovenantInput {
+            old_randomizer,
+            old_balance,
+            old_txid: old_txid.clone(),
+            input_outpoint1: old_tx_outpoint1.clone(),
+            input_outpoint2: old_tx_outpoint2.clone(),
+            optional_deposit_input: None,
+            optional_deposit_prevout: None,
+            new_balance: 0,
+        };
+        let (base_tx_template, _) = get_tx_with_annex::<T>(
+            &base_info,
+            id,
+            &old_state,
+            &new_state,
+            &input,
+            annex.clone(),
+        )
+        .unwrap();
+        let base_weight = base_tx_template.tx.weight();
+        let fee_rate = if T::TRUC {
+            FeeRate::ZERO
+        } else {
+            let vsize = base_tx_template.tx.vsize() as u64;
+            let fee = db.calculate_fees(&base_tx_template.tx, policy).unwrap();
+            FeeRate::from_sat_per_vb(fee.to_sat().div_ceil(vsize)).unwrap()
+        };
+
+        // Once in a while, the program asks to keep its current balance, which needs a deposit.
+        let min_balance = if prng.borrow_mut().gen::<bool>() {
+            old_balance
+        } else {
+            700_000
         };
+        let params = CoinSelectionParams {
+            base_weight,
+            fee_rate,
+            fee_cap: None,
+            max_inputs: 1,
+            old_balance,
+            min_balance,
+            target_balance: min_balance,
+        };
+        let utxos = wallet
+            .iter()
+            .map(|(utxo, _)| utxo.clone())
+            .collect::<Vec<Utxo>>();
+        let selection =
+            select_coins(&utxos, &params, CoinSelectionStrategy::BranchAndBound).unwrap();
+        let fee = selection.fee;
         total_fees += fee.to_sat();
 
-        new_balance -= fee.to_sat() as u64; // as for transaction fee
-        new_balance -= DUST_AMOUNT;
+        // Take the selected UTXO out of the wallet.
+        let deposit = selection.selected.first().map(|utxo| {
+            let idx = wallet
+                .iter()
+                .position(|(wallet_utxo, _)| wallet_utxo == utxo)
+                .unwrap();
+            let (utxo, signer) = wallet.remove(idx);
+
+            let deposit_input = TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(), // to be signed after the outputs are final
+            };
+
+            (deposit_input, utxo.tx_out, signer)
+        });
+        let deposit_input = deposit.as_ref().map(|(input, _, _)| input.clone());
+        let deposit_prevout = deposit.as_ref().map(|(_, prevout, _)| prevout.clone());
+        let sign_deposit = |tx_template: &mut TxTemplate| {
+            if let Some((_, deposit_prevout, signer)) = &deposit {
+                sign_deposit_input(tx_template, deposit_prevout, signer).unwrap();
+            }
+        };
 
         let info = CovenantInput {
             old_randomizer,
@@ -187,17 +267,83 @@ pub fn simulation_test_with_policy<T: CovenantProgram>(
             input_outpoint1: old_tx_outpoint1.clone(),
             input_outpoint2: old_tx_outpoint2.clone(),
             optional_deposit_input: deposit_input,
-            new_balance,
+            optional_deposit_prevout: deposit_prevout,
+            new_balance: selection.new_balance,
         };
 
-        let new_state = T::run(id, &old_state, &input).unwrap();
+        let (mut tx_template, mut randomizer) =
+            get_tx_with_annex::<T>(&info, id, &old_state, &new_state, &input, annex).unwrap();
+
+        // Export the transaction as a PSBT, in which an external signer finalizes the deposit
+        // input, which must not invalidate the covenant input under the sighash type.
+        let mut psbt = get_psbt::<T>(&tx_template, info.optional_deposit_prevout.clone()).unwrap();
+        assert_eq!(psbt.inputs[0].sighash_type, Some(T::SIGHASH_TYPE.into()));
+        sign_deposit(&mut tx_template);
+        if let Some(deposit_input) = tx_template.tx.input.get(1) {
+            psbt.inputs[1].final_script_witness = Some(deposit_input.witness.clone());
+        }
+        let psbt_tx = psbt.extract_tx_unchecked_fee_rate();
+        assert_eq!(psbt_tx, tx_template.tx);
+        db.verify_transaction(&psbt_tx).unwrap();
+
+        // Once in a while, assume that the transaction is stuck in the mempool, and replace it with
+        // one that pays twice the fee rate.
+        if !T::TRUC && prng.borrow_mut().gen_ratio(1, 4) {
+            let deposit_signer = deposit.as_ref().map(|(_, _, signer)| signer);
+            let deposit_amount = deposit
+                .as_ref()
+                .map(|(_, prevout, _)| prevout.value.to_sat())
+                .unwrap_or_default();
+
+            db.verify_transaction(&tx_template.tx).unwrap();
+            db.check_fees(&tx_template.tx, policy).unwrap();
+
+            let old_fee_rate = fee.to_sat() / tx_template.tx.vsize() as u64;
+            assert!(bump_fee::<T>(
+                &tx_template,
+                FeeRate::from_sat_per_vb(old_fee_rate).unwrap(),
+                deposit_signer,
+            )
+            .is_err());
+
+            let new_fee_rate = old_fee_rate * 2;
+            let (new_tx_template, new_randomizer) = bump_fee::<T>(
+                &tx_template,
+                FeeRate::from_sat_per_vb(new_fee_rate).unwrap(),
+                deposit_signer,
+            )
+            .unwrap();
 
-        let (tx_template, randomizer) = get_tx::<T>(&info, id, &old_state, &new_state, &input);
+            // The replacement pays the new fee rate under the policy, as well as the fee of the
+            // original transaction plus the incremental relay fee for its own size.
+            let new_tx = &new_tx_template.tx;
+            db.verify_transaction(new_tx).unwrap();
+            db.check_fees(
+                new_tx,
+                &Policy::default()
+                    .set_fee(new_fee_rate)
+                    .set_max_tx_weight(400000),
+            )
+            .unwrap();
+
+            let new_balance = new_tx.output[0].value.to_sat();
+            let new_fee = old_balance + deposit_amount - new_balance - DUST_AMOUNT;
+            assert!(new_fee >= fee.to_sat() + INCREMENTAL_RELAY_FEE_RATE * new_tx.vsize() as u64);
+            total_fees += new_fee - fee.to_sat();
+
+            tx_template = new_tx_template;
+            randomizer = new_randomizer;
+        }
+        let new_balance = tx_template.tx.output[0].value.to_sat();
 
         // Check if the new transaction conforms to the requirement.
         // If so, insert this transaction unconditionally.
         db.verify_transaction(&tx_template.tx).unwrap();
-        db.check_fees(&tx_template.tx, &policy).unwrap();
+        if T::TRUC {
+            check_truc_vsize(&tx_template.tx).unwrap();
+        } else {
+            db.check_fees(&tx_template.tx, &policy).unwrap();
+        }
         db.insert_transaction_unconditionally(&tx_template.tx)
             .unwrap();
 
@@ -0,0 +1,764 @@
+use crate::treepp::*;
+use anyhow::{ensure, Result};
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::{write_scriptint, Instruction};
+
+/// The maximal size of a stack element.
+pub const MAX_STACK_ELEMENT_SIZE: usize = 520;
+
+/// The maximal number of elements on the main stack and the altstack combined.
+pub const MAX_STACK_DEPTH: usize = 1000;
+
+/// The maximal size of a leaf script that we are willing to produce, which is bounded by the
+/// standard transaction weight (the leaf script is in the witness, where each byte weighs 1).
+pub const MAX_LEAF_SCRIPT_SIZE: usize = 400_000;
+
+/// The maximal size of a number produced by arithmetic opcodes.
+const MAX_NUMBER_SIZE: usize = 5;
+
+/// The result of the static analysis of a script.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptAnalysis {
+    /// The size of the script in bytes.
+    pub script_size: usize,
+    /// The number of opcodes, excluding data pushes.
+    pub num_opcodes: usize,
+    /// The number of witness elements that the script consumes (including the hints).
+    pub num_witness_elements: usize,
+    /// The maximal number of elements on the main stack and the altstack combined, including
+    /// witness elements that have not yet been consumed.
+    pub max_stack_depth: usize,
+    /// The worst-case size of the largest stack element that the script produces, which
+    /// excludes the witness elements whose sizes are never checked.
+    pub max_element_size: usize,
+    /// Violations of the limits that would make the script fail regardless of the witness.
+    pub errors: Vec<String>,
+    /// Places where the script relies on the interpreter to enforce a limit, as well as the
+    /// parts that the analyzer cannot follow precisely.
+    pub warnings: Vec<String>,
+}
+
+impl ScriptAnalysis {
+    /// Whether the analysis found no violation.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// An abstract stack element.
+#[derive(Clone, Debug)]
+struct Element {
+    /// The identity of the element, which is shared among its copies.
+    id: usize,
+    /// The worst-case size.
+    max_size: usize,
+    /// Whether the size is the same for every witness.
+    exact: bool,
+    /// The value, if it is a known number.
+    value: Option<i64>,
+    /// The identity of the element whose size this element is (from OP_SIZE).
+    size_of: Option<usize>,
+    /// If this element is true, the size of the element with this identity is at most the bound.
+    condition: Option<(usize, usize)>,
+}
+
+/// The abstract state of the interpreter along one path.
+#[derive(Clone, Debug, Default)]
+struct State {
+    stack: Vec<Element>,
+    altstack: Vec<Element>,
+    /// The number of witness elements consumed so far.
+    consumed: usize,
+    /// The maximal value of (stack + altstack - consumed) so far.
+    peak: isize,
+    /// Whether this path always fails (for example, reaching OP_RETURN).
+    dead: bool,
+}
+
+/// A conditional branch that is being analyzed.
+struct Frame {
+    /// The state when entering the branch.
+    entry: State,
+    /// The states at the end of the finished branches.
+    finished: Vec<State>,
+}
+
+/// Static analyzer that tracks the worst-case element sizes and the stack depth of a script.
+///
+/// Elements that come from the witness (including hints) are only bounded by the interpreter's
+/// limit of 520 bytes unless the script checks their sizes with OP_SIZE followed by
+/// OP_EQUALVERIFY, OP_LESSTHAN + OP_VERIFY, OP_WITHIN + OP_VERIFY, or similar. An OP_CAT that
+/// overflows for every witness is reported as an error, while an OP_CAT that may overflow only
+/// for some witnesses is reported as a warning, since the interpreter rejects such a witness.
+///
+/// The two branches of a conditional are analyzed separately and merged by taking the worst case.
+pub struct ScriptAnalyzer {
+    next_id: usize,
+    max_element_size: usize,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl ScriptAnalyzer {
+    /// Analyze a script.
+    pub fn analyze(script: &Script) -> ScriptAnalysis {
+        let mut analyzer = ScriptAnalyzer {
+            next_id: 0,
+            max_element_size: 0,
+            errors: vec![],
+            warnings: vec![],
+        };
+
+        let script_size = script.len();
+        if script_size > MAX_LEAF_SCRIPT_SIZE {
+            analyzer.errors.push(format!(
+                "the script has {} bytes, more than {}",
+                script_size, MAX_LEAF_SCRIPT_SIZE
+            ));
+        }
+
+        let mut num_opcodes = 0;
+        let mut state = State::default();
+        let mut frames: Vec<Frame> = vec![];
+
+        for (pos, instruction) in script.instructions().enumerate() {
+            let instruction = match instruction {
+                Ok(instruction) => instruction,
+                Err(e) => {
+                    analyzer
+                        .errors
+                        .push(format!("cannot parse the script: {}", e));
+                    break;
+                }
+            };
+
+            match instruction {
+                Instruction::PushBytes(bytes) => {
+                    let value = if bytes.len() <= 4 {
+                        instruction.script_num()
+                    } else {
+                        None
+                    };
+                    let element = analyzer.new_element(bytes.len(), true, value);
+                    analyzer.push(&mut state, element);
+                }
+                Instruction::Op(opcode) => {
+                    num_opcodes += 1;
+                    match opcode {
+                        OP_IF | OP_NOTIF => {
+                            analyzer.pop(&mut state);
+                            frames.push(Frame {
+                                entry: state.clone(),
+                                finished: vec![],
+                            });
+                        }
+                        OP_ELSE => match frames.last_mut() {
+                            Some(frame) => {
+                                let next = frame.entry.clone();
+                                frame.finished.push(std::mem::replace(&mut state, next));
+                            }
+                            None => {
+                                analyzer
+                                    .errors
+                                    .push(format!("unbalanced OP_ELSE at {}", pos));
+                                break;
+                            }
+                        },
+                        OP_ENDIF => match frames.pop() {
+                            Some(frame) => {
+                                let mut states = frame.finished;
+                                if states.is_empty() {
+                                    // the branch may be skipped
+                                    states.push(frame.entry);
+                                }
+                                states.push(state);
+                                state = analyzer.merge(states, pos);
+                            }
+                            None => {
+                                analyzer
+                                    .errors
+                                    .push(format!("unbalanced OP_ENDIF at {}", pos));
+                                break;
+                            }
+                        },
+                        _ => {
+                            if !state.dead {
+                                analyzer.step(&mut state, opcode, pos);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let depth =
+                (state.stack.len() + state.altstack.len()) as isize - state.consumed as isize;
+            state.peak = state.peak.max(depth);
+        }
+
+        if !frames.is_empty() {
+            analyzer
+                .errors
+                .push("the script has an unbalanced OP_IF".to_string());
+        }
+        if state.dead {
+            analyzer.errors.push("the script always fails".to_string());
+        }
+
+        let max_stack_depth = (state.peak + state.consumed as isize).max(0) as usize;
+        if max_stack_depth > MAX_STACK_DEPTH {
+            analyzer.errors.push(format!(
+                "the stack may have {} elements, more than {}",
+                max_stack_depth, MAX_STACK_DEPTH
+            ));
+        }
+
+        ScriptAnalysis {
+            script_size,
+            num_opcodes,
+            num_witness_elements: state.consumed,
+            max_stack_depth,
+            max_element_size: analyzer.max_element_size,
+            errors: analyzer.errors,
+            warnings: analyzer.warnings,
+        }
+    }
+
+    fn new_element(&mut self, max_size: usize, exact: bool, value: Option<i64>) -> Element {
+        self.next_id += 1;
+        Element {
+            id: self.next_id,
+            max_size,
+            exact,
+            value,
+            size_of: None,
+            condition: None,
+        }
+    }
+
+    fn new_number(&mut self, value: Option<i64>) -> Element {
+        match value {
+            Some(v) => {
+                let mut buf = [0u8; 8];
+                let len = write_scriptint(&mut buf, v);
+                self.new_element(len, true, value)
+            }
+            None => self.new_element(MAX_NUMBER_SIZE, false, None),
+        }
+    }
+
+    fn new_boolean(&mut self, condition: Option<(usize, usize)>) -> Element {
+        let mut element = self.new_element(1, false, None);
+        element.condition = condition;
+        element
+    }
+
+    fn new_witness_element(&mut self) -> Element {
+        self.new_element(MAX_STACK_ELEMENT_SIZE, false, None)
+    }
+
+    fn push(&mut self, state: &mut State, element: Element) {
+        self.max_element_size = self.max_element_size.max(element.max_size);
+        state.stack.push(element);
+    }
+
+    /// Make sure that the stack has at least `n` elements, by taking elements from the witness.
+    fn ensure(&mut self, state: &mut State, n: usize) {
+        while state.stack.len() < n {
+            let element = self.new_witness_element();
+            state.stack.insert(0, element);
+            state.consumed += 1;
+        }
+    }
+
+    fn pop(&mut self, state: &mut State) -> Element {
+        self.ensure(state, 1);
+        state.stack.pop().unwrap()
+    }
+
+    /// Return the element at the given depth (0 is the top).
+    fn peek(&mut self, state: &mut State, depth: usize) -> Element {
+        self.ensure(state, depth + 1);
+        state.stack[state.stack.len() - 1 - depth].clone()
+    }
+
+    /// Remove the element at the given depth (0 is the top).
+    fn remove(&mut self, state: &mut State, depth: usize) -> Element {
+        self.ensure(state, depth + 1);
+        let idx = state.stack.len() - 1 - depth;
+        state.stack.remove(idx)
+    }
+
+    /// Restrict the size of the element (and its copies) with the given identity.
+    fn constrain(state: &mut State, id: usize, bound: usize) {
+        for element in state.stack.iter_mut().chain(state.altstack.iter_mut()) {
+            if element.id == id {
+                element.max_size = element.max_size.min(bound);
+            }
+        }
+    }
+
+    fn verify(state: &mut State, element: &Element) {
+        if let Some((id, bound)) = element.condition {
+            Self::constrain(state, id, bound);
+        }
+    }
+
+    /// The size bound implied by `size <= value + offset`, where one element is the size of
+    /// another element and the other one is a known number.
+    fn size_bound(size: &Element, number: &Element, offset: i64) -> Option<(usize, usize)> {
+        match (size.size_of, number.value) {
+            (Some(id), Some(value)) if value + offset >= 0 => Some((id, (value + offset) as usize)),
+            _ => None,
+        }
+    }
+
+    fn step(&mut self, state: &mut State, opcode: Opcode, pos: usize) {
+        match opcode {
+            OP_PUSHNUM_NEG1 => {
+                let element = self.new_element(1, true, Some(-1));
+                self.push(state, element);
+            }
+            OP_PUSHNUM_1 | OP_PUSHNUM_2 | OP_PUSHNUM_3 | OP_PUSHNUM_4 | OP_PUSHNUM_5
+            | OP_PUSHNUM_6 | OP_PUSHNUM_7 | OP_PUSHNUM_8 | OP_PUSHNUM_9 | OP_PUSHNUM_10
+            | OP_PUSHNUM_11 | OP_PUSHNUM_12 | OP_PUSHNUM_13 | OP_PUSHNUM_14 | OP_PUSHNUM_15
+            | OP_PUSHNUM_16 => {
+                let value = (opcode.to_u8() - OP_PUSHNUM_1.to_u8() + 1) as i64;
+                let element = self.new_element(1, true, Some(value));
+                self.push(state, element);
+            }
+            OP_NOP | OP_CODESEPARATOR | OP_NOP1 | OP_CLTV | OP_CSV | OP_NOP4 | OP_NOP5
+            | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9 | OP_NOP10 => {}
+            OP_VERIFY => {
+                let element = self.pop(state);
+                Self::verify(state, &element);
+            }
+            OP_RETURN => {
+                state.dead = true;
+            }
+            OP_TOALTSTACK => {
+                let element = self.pop(state);
+                state.altstack.push(element);
+            }
+            OP_FROMALTSTACK => match state.altstack.pop() {
+                Some(element) => self.push(state, element),
+                None => {
+                    self.errors
+                        .push(format!("OP_FROMALTSTACK on an empty altstack at {}", pos));
+                    state.dead = true;
+                }
+            },
+            OP_2DROP => {
+                self.pop(state);
+                self.pop(state);
+            }
+            OP_2DUP => {
+                let a = self.peek(state, 1);
+                let b = self.peek(state, 0);
+                self.push(state, a);
+                self.push(state, b);
+            }
+            OP_3DUP => {
+                let a = self.peek(state, 2);
+                let b = self.peek(state, 1);
+                let c = self.peek(state, 0);
+                self.push(state, a);
+                self.push(state, b);
+                self.push(state, c);
+            }
+            OP_2OVER => {
+                let a = self.peek(state, 3);
+                let b = self.peek(state, 2);
+                self.push(state, a);
+                self.push(state, b);
+            }
+            OP_2ROT => {
+                let a = self.remove(state, 5);
+                let b = self.remove(state, 4);
+                self.push(state, a);
+                self.push(state, b);
+            }
+            OP_2SWAP => {
+                let a = self.remove(state, 3);
+                let b = self.remove(state, 2);
+                self.push(state, a);
+                self.push(state, b);
+            }
+            OP_IFDUP => {
+                self.warnings
+                    .push(format!("OP_IFDUP at {} is not analyzed precisely", pos));
+                let a = self.peek(state, 0);
+                self.push(state, a);
+            }
+            OP_DEPTH => {
+                let element = self.new_number(None);
+                self.push(state, element);
+            }
+            OP_DROP => {
+                self.pop(state);
+            }
+            OP_DUP => {
+                let a = self.peek(state, 0);
+                self.push(state, a);
+            }
+            OP_NIP => {
+                self.remove(state, 1);
+            }
+            OP_OVER => {
+                let a = self.peek(state, 1);
+                self.push(state, a);
+            }
+            OP_PICK | OP_ROLL => {
+                let n = self.pop(state);
+                let element = match n.value {
+                    Some(depth) if depth >= 0 => {
+                        if opcode == OP_PICK {
+                            self.peek(state, depth as usize)
+                        } else {
+                            self.remove(state, depth as usize)
+                        }
+                    }
+                    Some(_) => {
+                        self.errors
+                            .push(format!("negative depth for {:?} at {}", opcode, pos));
+                        state.dead = true;
+                        return;
+                    }
+                    None => {
+                        // the depth depends on the witness (such as OP_HINT), so this is
+                        // treated as an element from the witness
+                        if opcode == OP_ROLL {
+                            state.consumed += 1;
+                        }
+                        let element = self.new_witness_element();
+                        state.stack.push(element);
+                        return;
+                    }
+                };
+                self.push(state, element);
+            }
+            OP_ROT => {
+                let a = self.remove(state, 2);
+                self.push(state, a);
+            }
+            OP_SWAP => {
+                let a = self.remove(state, 1);
+                self.push(state, a);
+            }
+            OP_TUCK => {
+                let a = self.peek(state, 0);
+                self.ensure(state, 2);
+                let idx = state.stack.len() - 2;
+                state.stack.insert(idx, a);
+            }
+            OP_CAT => {
+                let b = self.pop(state);
+                let a = self.pop(state);
+                let max_size = a.max_size + b.max_size;
+                let exact = a.exact && b.exact;
+                if max_size > MAX_STACK_ELEMENT_SIZE {
+                    if exact {
+                        self.errors.push(format!(
+                            "OP_CAT at {} produces an element of {} bytes",
+                            pos, max_size
+                        ));
+                    } else {
+                        self.warnings.push(format!(
+                            "OP_CAT at {} may produce an element of {} bytes",
+                            pos, max_size
+                        ));
+                    }
+                }
+                self.max_element_size = self.max_element_size.max(max_size);
+                // the interpreter fails if the element is too large
+                let element = self.new_element(max_size.min(MAX_STACK_ELEMENT_SIZE), exact, None);
+                state.stack.push(element);
+            }
+            OP_SIZE => {
+                let a = self.peek(state, 0);
+                let mut element = if a.exact {
+                    self.new_number(Some(a.max_size as i64))
+                } else {
+                    self.new_number(None)
+                };
+                element.size_of = Some(a.id);
+                self.push(state, element);
+            }
+            OP_EQUAL | OP_EQUALVERIFY | OP_NUMEQUAL | OP_NUMEQUALVERIFY => {
+                let b = self.pop(state);
+                let a = self.pop(state);
+                let condition = Self::size_bound(&a, &b, 0).or_else(|| Self::size_bound(&b, &a, 0));
+                let element = self.new_boolean(condition);
+                if opcode == OP_EQUALVERIFY || opcode == OP_NUMEQUALVERIFY {
+                    Self::verify(state, &element);
+                } else {
+                    self.push(state, element);
+                }
+            }
+            OP_LESSTHAN | OP_LESSTHANOREQUAL | OP_GREATERTHAN | OP_GREATERTHANOREQUAL => {
+                let b = self.pop(state);
+                let a = self.pop(state);
+                let condition = match opcode {
+                    OP_LESSTHAN => Self::size_bound(&a, &b, -1),
+                    OP_LESSTHANOREQUAL => Self::size_bound(&a, &b, 0),
+                    OP_GREATERTHAN => Self::size_bound(&b, &a, -1),
+                    _ => Self::size_bound(&b, &a, 0),
+                };
+                let element = self.new_boolean(condition);
+                self.push(state, element);
+            }
+            OP_WITHIN => {
+                let max = self.pop(state);
+                self.pop(state);
+                let x = self.pop(state);
+                let condition = Self::size_bound(&x, &max, -1);
+                let element = self.new_boolean(condition);
+                self.push(state, element);
+            }
+            OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS => {
+                let a = self.pop(state);
+                let value = a.value.map(|v| match opcode {
+                    OP_1ADD => v + 1,
+                    OP_1SUB => v - 1,
+                    OP_NEGATE => -v,
+                    _ => v.abs(),
+                });
+                let element = self.new_number(value);
+                self.push(state, element);
+            }
+            OP_ADD | OP_SUB | OP_MIN | OP_MAX => {
+                let b = self.pop(state);
+                let a = self.pop(state);
+                let value = match (a.value, b.value) {
+                    (Some(a), Some(b)) => Some(match opcode {
+                        OP_ADD => a + b,
+                        OP_SUB => a - b,
+                        OP_MIN => a.min(b),
+                        _ => a.max(b),
+                    }),
+                    _ => None,
+                };
+                let element = self.new_number(value);
+                self.push(state, element);
+            }
+            OP_NOT | OP_0NOTEQUAL => {
+                self.pop(state);
+                let element = self.new_boolean(None);
+                self.push(state, element);
+            }
+            OP_BOOLAND | OP_BOOLOR | OP_NUMNOTEQUAL => {
+                self.pop(state);
+                self.pop(state);
+                let element = self.new_boolean(None);
+                self.push(state, element);
+            }
+            OP_RIPEMD160 | OP_SHA1 | OP_HASH160 => {
+                self.pop(state);
+                let element = self.new_element(20, true, None);
+                self.push(state, element);
+            }
+            OP_SHA256 | OP_HASH256 => {
+                self.pop(state);
+                let element = self.new_element(32, true, None);
+                self.push(state, element);
+            }
+            OP_CHECKSIG => {
+                self.pop(state);
+                self.pop(state);
+                let element = self.new_boolean(None);
+                self.push(state, element);
+            }
+            OP_CHECKSIGVERIFY => {
+                self.pop(state);
+                self.pop(state);
+            }
+            OP_CHECKSIGADD => {
+                self.pop(state);
+                self.pop(state);
+                self.pop(state);
+                let element = self.new_number(None);
+                self.push(state, element);
+            }
+            _ => {
+                self.errors
+                    .push(format!("unsupported opcode {:?} at {}", opcode, pos));
+                state.dead = true;
+            }
+        }
+    }
+
+    /// Merge the states at the end of the branches of a conditional.
+    fn merge(&mut self, states: Vec<State>, pos: usize) -> State {
+        let peak = states.iter().map(|s| s.peak).max().unwrap();
+        let consumed = states.iter().map(|s| s.consumed).max().unwrap();
+
+        let mut alive = states.into_iter().filter(|s| !s.dead).collect::<Vec<_>>();
+        if alive.is_empty() {
+            return State {
+                peak,
+                consumed,
+                dead: true,
+                ..Default::default()
+            };
+        }
+
+        let mut merged = alive.remove(0);
+        for state in alive.into_iter() {
+            if state.stack.len() != merged.stack.len()
+                || state.altstack.len() != merged.altstack.len()
+            {
+                self.warnings.push(format!(
+                    "the branches ending at {} leave different numbers of elements",
+                    pos
+                ));
+            }
+            merged.stack = self.merge_stack(&merged.stack, &state.stack);
+            merged.altstack = self.merge_stack(&merged.altstack, &state.altstack);
+        }
+        merged.peak = peak;
+        merged.consumed = consumed;
+        merged
+    }
+
+    /// Merge two stacks elementwise from the top.
+    fn merge_stack(&mut self, a: &[Element], b: &[Element]) -> Vec<Element> {
+        let len = a.len().min(b.len());
+        let mut merged = Vec::with_capacity(len);
+        for (x, y) in a[a.len() - len..].iter().zip(b[b.len() - len..].iter()) {
+            if x.id == y.id {
+                merged.push(x.clone());
+            } else {
+                let value = if x.value == y.value { x.value } else { None };
+                merged.push(self.new_element(
+                    x.max_size.max(y.max_size),
+                    x.exact && y.exact && x.max_size == y.max_size,
+                    value,
+                ));
+            }
+        }
+        merged
+    }
+}
+
+/// Analyze a script and return an error if it violates the limits.
+pub fn check_within_limits(script: &Script, name: &str) -> Result<()> {
+    let analysis = ScriptAnalyzer::analyze(script);
+    ensure!(
+        analysis.is_ok(),
+        "{} violates the script limits: {:?}",
+        name,
+        analysis.errors
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::treepp::*;
+    use crate::utils::analyzer::{ScriptAnalyzer, MAX_STACK_DEPTH};
+    use crate::utils::pseudo::OP_HINT;
+
+    #[test]
+    fn test_element_size() {
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            { vec![0u8; 300] } { vec![0u8; 200] } OP_CAT
+            OP_SHA256 OP_DUP OP_CAT
+            OP_DROP
+        });
+        assert!(analysis.is_ok());
+        assert!(analysis.warnings.is_empty());
+        assert_eq!(analysis.max_element_size, 500);
+        assert_eq!(analysis.max_stack_depth, 2);
+
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            { vec![0u8; 300] } OP_DUP OP_CAT OP_DROP
+        });
+        assert!(!analysis.is_ok());
+        assert_eq!(analysis.max_element_size, 600);
+
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            { vec![0u8; 300] } OP_SIZE 300 OP_EQUALVERIFY OP_DROP
+        });
+        assert!(analysis.is_ok());
+    }
+
+    #[test]
+    fn test_hints() {
+        // unchecked hints rely on the interpreter
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            OP_HINT OP_HINT OP_CAT OP_DROP
+        });
+        assert!(analysis.is_ok());
+        assert_eq!(analysis.warnings.len(), 1);
+        assert_eq!(analysis.num_witness_elements, 2);
+
+        // checked hints
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            OP_HINT OP_SIZE 300 OP_LESSTHAN OP_VERIFY
+            OP_HINT OP_SIZE 200 OP_EQUALVERIFY
+            OP_CAT OP_DUP OP_CAT OP_DROP
+        });
+        assert!(analysis.is_ok());
+        assert_eq!(analysis.warnings.len(), 1);
+        assert_eq!(analysis.max_element_size, 998);
+
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            OP_HINT OP_SIZE 0 201 OP_WITHIN OP_VERIFY
+            OP_DUP OP_CAT OP_DROP
+        });
+        assert!(analysis.is_ok());
+        assert!(analysis.warnings.is_empty());
+        assert_eq!(analysis.max_element_size, 400);
+    }
+
+    #[test]
+    fn test_branches_and_depth() {
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            OP_HINT
+            OP_IF
+                { vec![0u8; 100] }
+            OP_ELSE
+                { vec![1u8; 100] } { vec![2u8; 300] } OP_CAT
+            OP_ENDIF
+            OP_DUP OP_CAT OP_DROP
+        });
+        assert!(analysis.is_ok());
+        assert_eq!(analysis.warnings.len(), 1);
+        assert_eq!(analysis.max_element_size, 800);
+
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            OP_HINT
+            OP_IF
+                { vec![0u8; 400] }
+            OP_ELSE
+                { vec![1u8; 100] } { vec![2u8; 300] } OP_CAT
+            OP_ENDIF
+            OP_DUP OP_CAT OP_DROP
+        });
+        assert!(!analysis.is_ok());
+
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            for _ in 0..MAX_STACK_DEPTH {
+                1 OP_TOALTSTACK
+            }
+            for _ in 0..MAX_STACK_DEPTH {
+                OP_FROMALTSTACK OP_DROP
+            }
+        });
+        assert!(analysis.is_ok());
+        assert_eq!(analysis.max_stack_depth, MAX_STACK_DEPTH);
+
+        let analysis = ScriptAnalyzer::analyze(&script! {
+            for _ in 0..MAX_STACK_DEPTH {
+                OP_TOALTSTACK
+            }
+            1 OP_DROP
+            for _ in 0..MAX_STACK_DEPTH {
+                OP_FROMALTSTACK OP_DROP
+            }
+        });
+        assert!(!analysis.is_ok());
+        assert_eq!(analysis.num_witness_elements, MAX_STACK_DEPTH);
+        assert_eq!(analysis.max_stack_depth, MAX_STACK_DEPTH + 1);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::internal_structures::variable_length_integer::byte_from_number;
+use crate::treepp::*;
+use crate::utils::pseudo::OP_HINT;
+use bitcoin_scriptexec::utils::scriptint_vec;
+
+/// Gadget for decomposing a number or a byte string into bits, nibbles, or other limbs of a
+/// fixed number of bits.
+///
+/// The limbs are obtained through `OP_HINT`, checked to be in range, and recombined to match the
+/// input. They are returned with the most significant one at the bottom.
+pub struct BitDecompositionGadget;
+
+impl BitDecompositionGadget {
+    /// Decompose a non-negative Bitcoin integer on the stack into `num_limbs` limbs of
+    /// `limb_bits` bits each. The integer must be smaller than 2^(num_limbs * limb_bits), which
+    /// can be at most 2^31.
+    ///
+    /// The hints are generated by [`BitDecompositionGadget::get_hints_for_limbs`].
+    pub fn to_limbs(num_limbs: usize, limb_bits: usize) -> Script {
+        assert!(num_limbs > 0 && limb_bits > 0);
+        assert!(num_limbs * limb_bits <= 31);
+
+        script! {
+            { hinted_limbs(num_limbs, limb_bits) }
+            { num_limbs + 1 } OP_ROLL OP_NUMEQUALVERIFY
+        }
+    }
+
+    /// Decompose a non-negative Bitcoin integer on the stack into `num_bits` bits.
+    pub fn to_bits(num_bits: usize) -> Script {
+        Self::to_limbs(num_bits, 1)
+    }
+
+    /// Decompose a byte string of `num_bytes` bytes on the stack into limbs of `limb_bits` bits
+    /// each, which must divide 8. The limbs of the first byte are at the bottom.
+    ///
+    /// A field can be first extracted from a longer byte string by
+    /// [`crate::utils::split::SplitGadget`].
+    ///
+    /// The hints are generated by [`BitDecompositionGadget::get_hints_for_bytes`].
+    pub fn bytes_to_limbs(num_bytes: usize, limb_bits: usize) -> Script {
+        assert!(num_bytes > 0);
+        assert!(limb_bits > 0 && 8 % limb_bits == 0);
+        let limbs_per_byte = 8 / limb_bits;
+
+        script! {
+            // the reconstructed byte string
+            OP_PUSHBYTES_0 OP_TOALTSTACK
+
+            for _ in 0..num_bytes {
+                { hinted_limbs(limbs_per_byte, limb_bits) }
+                { byte_from_number() }
+                OP_FROMALTSTACK OP_SWAP OP_CAT OP_TOALTSTACK
+            }
+
+            OP_FROMALTSTACK
+            { num_bytes * limbs_per_byte + 1 } OP_ROLL OP_EQUALVERIFY
+        }
+    }
+
+    /// Decompose a byte string of `num_bytes` bytes on the stack into bits.
+    pub fn bytes_to_bits(num_bytes: usize) -> Script {
+        Self::bytes_to_limbs(num_bytes, 1)
+    }
+
+    /// Decompose a byte string of `num_bytes` bytes on the stack into nibbles.
+    pub fn bytes_to_nibbles(num_bytes: usize) -> Script {
+        Self::bytes_to_limbs(num_bytes, 4)
+    }
+
+    /// Generate the hints for [`BitDecompositionGadget::to_limbs`].
+    pub fn get_hints_for_limbs(v: u32, num_limbs: usize, limb_bits: usize) -> Vec<Vec<u8>> {
+        assert!(num_limbs * limb_bits <= 31);
+        assert!(v >> (num_limbs * limb_bits) == 0);
+
+        (0..num_limbs)
+            .rev()
+            .map(|i| scriptint_vec(((v >> (i * limb_bits)) & ((1 << limb_bits) - 1)) as i64))
+            .collect()
+    }
+
+    /// Generate the hints for [`BitDecompositionGadget::bytes_to_limbs`].
+    pub fn get_hints_for_bytes(bytes: &[u8], limb_bits: usize) -> Vec<Vec<u8>> {
+        assert!(limb_bits > 0 && 8 % limb_bits == 0);
+
+        bytes
+            .iter()
+            .flat_map(|&b| Self::get_hints_for_limbs(b as u32, 8 / limb_bits, limb_bits))
+            .collect()
+    }
+}
+
+/// Obtain `num_limbs` limbs of `limb_bits` bits each through `OP_HINT`, from the most significant
+/// one, and output them as well as the number that they represent.
+fn hinted_limbs(num_limbs: usize, limb_bits: usize) -> Script {
+    script! {
+        0 OP_TOALTSTACK
+        for _ in 0..num_limbs {
+            OP_HINT
+            OP_DUP 0 { 1 << limb_bits } OP_WITHIN OP_VERIFY
+            OP_DUP
+            OP_FROMALTSTACK
+            for _ in 0..limb_bits {
+                OP_DUP OP_ADD
+            }
+            OP_ADD OP_TOALTSTACK
+        }
+        OP_FROMALTSTACK
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::treepp::*;
+    use crate::utils::bits::BitDecompositionGadget;
+    use crate::utils::split::{SplitGadget, SplitLength, SplitPart};
+    use crate::utils::test_utils::push_hint;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_to_limbs() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..50 {
+            let limb_bits = prng.gen_range(1..9);
+            let num_limbs = prng.gen_range(1..=31 / limb_bits);
+            let v = prng.gen_range(0..1u32 << (num_limbs * limb_bits));
+
+            let script = script! {
+                for hint in BitDecompositionGadget::get_hints_for_limbs(v, num_limbs, limb_bits).iter() {
+                    { push_hint(hint) }
+                }
+                { v }
+                { BitDecompositionGadget::to_limbs(num_limbs, limb_bits) }
+                for i in 0..num_limbs {
+                    { (v >> (i * limb_bits)) & ((1 << limb_bits) - 1) } OP_NUMEQUALVERIFY
+                }
+                OP_TRUE
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_to_bits_rejects_non_boolean() {
+        // 5 = 0b101, but with the hints 2 and 1 instead of 1, 0, 1
+        let script = script! {
+            2 1 OP_PUSHBYTES_0
+            5
+            { BitDecompositionGadget::to_bits(3) }
+            OP_2DROP OP_DROP OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+
+        // a number that does not fit
+        let script = script! {
+            1 1 1
+            8
+            { BitDecompositionGadget::to_bits(3) }
+            OP_2DROP OP_DROP OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    #[test]
+    fn test_bytes_to_limbs() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for limb_bits in [1, 2, 4, 8] {
+            let mut bytes = vec![0u8; 3];
+            prng.fill_bytes(&mut bytes);
+
+            let script = script! {
+                for hint in BitDecompositionGadget::get_hints_for_bytes(&bytes, limb_bits).iter() {
+                    { push_hint(hint) }
+                }
+                { bytes.clone() }
+                { BitDecompositionGadget::bytes_to_limbs(3, limb_bits) }
+                for b in bytes.iter().rev() {
+                    for i in 0..8 / limb_bits {
+                        { (*b as u32 >> (i * limb_bits)) & ((1 << limb_bits) - 1) }
+                        OP_NUMEQUALVERIFY
+                    }
+                }
+                OP_TRUE
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_split_and_bytes_to_nibbles() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut blob = vec![0u8; 40];
+        prng.fill_bytes(&mut blob);
+
+        // take the single byte at offset 32
+        let split = SplitGadget::new(vec![
+            SplitPart {
+                len: SplitLength::Fixed(32),
+                keep: false,
+            },
+            SplitPart {
+                len: SplitLength::Fixed(1),
+                keep: true,
+            },
+            SplitPart {
+                len: SplitLength::Fixed(7),
+                keep: false,
+            },
+        ]);
+
+        let mut hints = split.get_hints(&blob, &[]);
+        hints.extend(BitDecompositionGadget::get_hints_for_bytes(
+            &blob[32..33],
+            4,
+        ));
+
+        let script = script! {
+            for hint in hints.iter() {
+                { push_hint(hint) }
+            }
+            { blob.clone() }
+            { split.to_script() }
+            { BitDecompositionGadget::bytes_to_nibbles(1) }
+            { (blob[32] & 0xf) as u32 } OP_NUMEQUALVERIFY
+            { (blob[32] >> 4) as u32 } OP_NUMEQUAL
+        };
+
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+}
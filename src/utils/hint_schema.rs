@@ -0,0 +1,458 @@
+use crate::treepp::*;
+use crate::utils::pseudo::OP_HINT;
+use anyhow::{anyhow, ensure, Result};
+use std::fmt::Debug;
+
+/// The size constraint of a hint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HintSize {
+    /// Exactly the given number of bytes.
+    Exact(usize),
+    /// Either an empty string or exactly the given number of bytes.
+    EmptyOr(usize),
+    /// Not an empty string.
+    NonEmpty,
+    /// Any size.
+    Any,
+}
+
+impl HintSize {
+    /// Whether a hint of this size is allowed.
+    pub fn allows(&self, len: usize) -> bool {
+        match self {
+            HintSize::Exact(n) => len == *n,
+            HintSize::EmptyOr(n) => len == 0 || len == *n,
+            HintSize::NonEmpty => len > 0,
+            HintSize::Any => true,
+        }
+    }
+
+    /// The script that checks the size of the element on the top of the stack.
+    pub fn check(&self) -> Script {
+        match self {
+            HintSize::Exact(n) => script! {
+                OP_SIZE { *n } OP_EQUALVERIFY
+            },
+            HintSize::EmptyOr(n) => script! {
+                OP_SIZE 0 OP_EQUAL OP_TOALTSTACK
+                OP_SIZE { *n } OP_EQUAL OP_FROMALTSTACK OP_BOOLOR OP_VERIFY
+            },
+            HintSize::NonEmpty => script! {
+                OP_SIZE 0 OP_GREATERTHAN OP_VERIFY
+            },
+            HintSize::Any => script! {},
+        }
+    }
+}
+
+/// A hint declared in the schema.
+#[derive(Clone, Debug)]
+struct HintEntry<K> {
+    key: K,
+    size: HintSize,
+    /// The hint is only present if this hint is not an empty string.
+    present_if: Option<K>,
+    /// The hint is a group of elements, whose number is only known in the witness.
+    group: bool,
+}
+
+/// Schema of the hints that a script consumes with OP_HINT, in the order of consumption.
+///
+/// Gadgets declare their hints (with size constraints) through the schema, which returns the
+/// script that pulls each hint and checks its size, so the order of the hints follows the order
+/// in which the script is built. The witness is then assembled by the keys with [`HintWitness`],
+/// which puts the hints in the same order.
+#[derive(Clone, Debug)]
+pub struct HintSchema<K> {
+    entries: Vec<HintEntry<K>>,
+}
+
+impl<K> Default for HintSchema<K> {
+    fn default() -> Self {
+        Self { entries: vec![] }
+    }
+}
+
+impl<K: Copy + Eq + Debug> HintSchema<K> {
+    /// Create an empty schema.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn declare(&mut self, key: K, size: HintSize, present_if: Option<K>, group: bool) {
+        assert!(
+            self.position(key).is_none(),
+            "the hint {:?} is declared twice",
+            key
+        );
+        if let Some(condition) = present_if {
+            assert!(
+                self.position(condition).is_some(),
+                "the hint {:?} depends on the hint {:?}, which must be declared before",
+                key,
+                condition
+            );
+        }
+        self.entries.push(HintEntry {
+            key,
+            size,
+            present_if,
+            group,
+        });
+    }
+
+    /// Declare a hint, and return the script that pulls it and checks its size.
+    pub fn hint(&mut self, key: K, size: HintSize) -> Script {
+        self.declare(key, size, None, false);
+        script! {
+            OP_HINT
+            { size.check() }
+        }
+    }
+
+    /// Declare a hint whose size is checked by the caller, and return the script that pulls it.
+    pub fn hint_unchecked(&mut self, key: K, size: HintSize) -> Script {
+        self.declare(key, size, None, false);
+        script! {
+            OP_HINT
+        }
+    }
+
+    /// Declare a hint that is only pulled if an earlier hint is not an empty string, and return
+    /// the script that pulls it and checks its size. The script needs to be in a branch that
+    /// only runs in this case.
+    pub fn hint_if_present(&mut self, key: K, size: HintSize, present_if: K) -> Script {
+        self.declare(key, size, Some(present_if), false);
+        script! {
+            OP_HINT
+            { size.check() }
+        }
+    }
+
+    /// Declare a group of hints that a script pulls by itself, such as the states and the input
+    /// of an application, whose number of elements is only known when the witness is assembled.
+    pub fn hint_group(&mut self, key: K) {
+        self.declare(key, HintSize::Any, None, true);
+    }
+
+    /// Append the hints of another schema, which come after the hints of this schema.
+    pub fn extend(&mut self, other: HintSchema<K>) {
+        for entry in other.entries.into_iter() {
+            self.declare(entry.key, entry.size, entry.present_if, entry.group);
+        }
+    }
+
+    /// The keys of the hints in the order of consumption.
+    pub fn keys(&self) -> Vec<K> {
+        self.entries.iter().map(|entry| entry.key).collect()
+    }
+
+    /// The number of hints in the schema.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the schema has no hints.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn position(&self, key: K) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.key == key)
+    }
+}
+
+/// The hints of a witness, assembled by the keys of a [`HintSchema`].
+#[derive(Clone, Debug)]
+pub struct HintWitness<'a, K> {
+    schema: &'a HintSchema<K>,
+    values: Vec<Option<Vec<Vec<u8>>>>,
+}
+
+impl<'a, K: Copy + Eq + Debug> HintWitness<'a, K> {
+    /// Start a witness for the schema.
+    pub fn new(schema: &'a HintSchema<K>) -> Self {
+        Self {
+            schema,
+            values: vec![None; schema.len()],
+        }
+    }
+
+    fn index(&self, key: K) -> usize {
+        self.schema
+            .position(key)
+            .unwrap_or_else(|| panic!("the hint {:?} is not in the schema", key))
+    }
+
+    /// Provide a hint.
+    pub fn set(&mut self, key: K, value: Vec<u8>) {
+        let idx = self.index(key);
+        assert!(
+            self.values[idx].is_none(),
+            "the hint {:?} is provided twice",
+            key
+        );
+        self.replace(key, value);
+    }
+
+    /// Provide a hint, replacing the existing one if any.
+    pub fn replace(&mut self, key: K, value: Vec<u8>) {
+        let idx = self.index(key);
+        let entry = &self.schema.entries[idx];
+        assert!(!entry.group, "the hint {:?} is a group", key);
+        assert!(
+            entry.size.allows(value.len()),
+            "the hint {:?} has {} bytes, which does not match {:?}",
+            key,
+            value.len(),
+            entry.size
+        );
+        self.values[idx] = Some(vec![value]);
+    }
+
+    /// Provide a group of hints.
+    pub fn set_group(&mut self, key: K, values: Vec<Vec<u8>>) {
+        let idx = self.index(key);
+        assert!(
+            self.schema.entries[idx].group,
+            "the hint {:?} is not a group",
+            key
+        );
+        assert!(
+            self.values[idx].is_none(),
+            "the hint {:?} is provided twice",
+            key
+        );
+        self.values[idx] = Some(values);
+    }
+
+    /// Get a hint that has been provided.
+    pub fn get(&self, key: K) -> Option<&[u8]> {
+        let idx = self.index(key);
+        assert!(
+            !self.schema.entries[idx].group,
+            "the hint {:?} is a group",
+            key
+        );
+        self.values[idx].as_ref().map(|values| values[0].as_slice())
+    }
+
+    /// Whether the hint is present in the witness, given the hints it depends on.
+    fn is_present(&self, idx: usize) -> bool {
+        match self.schema.entries[idx].present_if {
+            None => true,
+            Some(condition) => self.values[self.index(condition)]
+                .as_ref()
+                .is_some_and(|values| !values[0].is_empty()),
+        }
+    }
+
+    /// Return the hints in the order of consumption.
+    ///
+    /// It panics if a hint is missing, or if a hint is provided while it would not be pulled.
+    pub fn finalize(self) -> Vec<Vec<u8>> {
+        self.finalize_until(self.values.len())
+    }
+
+    /// Return the hints before the first group in the order of consumption, which are the ones
+    /// that [`HintWitness::parse`] reads.
+    pub fn finalize_before_groups(self) -> Vec<Vec<u8>> {
+        let end = self
+            .schema
+            .entries
+            .iter()
+            .position(|entry| entry.group)
+            .unwrap_or(self.values.len());
+        self.finalize_until(end)
+    }
+
+    fn finalize_until(&self, end: usize) -> Vec<Vec<u8>> {
+        let mut witness = vec![];
+        for idx in 0..end {
+            let key = self.schema.entries[idx].key;
+            if self.is_present(idx) {
+                witness.extend(
+                    self.values[idx]
+                        .clone()
+                        .unwrap_or_else(|| panic!("the hint {:?} is missing", key)),
+                );
+            } else {
+                assert!(
+                    self.values[idx].is_none(),
+                    "the hint {:?} is provided, but would not be pulled",
+                    key
+                );
+            }
+        }
+        witness
+    }
+
+    /// Read the hints from the beginning of the witness elements, and return them together with
+    /// the number of elements read.
+    ///
+    /// Since the number of elements in a group is unknown, it stops at the first group, which is
+    /// left missing together with the hints after it.
+    pub fn parse(schema: &'a HintSchema<K>, elements: &[Vec<u8>]) -> Result<(Self, usize)> {
+        let mut witness = Self::new(schema);
+        let mut consumed = 0;
+        for idx in 0..schema.len() {
+            if !witness.is_present(idx) {
+                continue;
+            }
+            let entry = &schema.entries[idx];
+            if entry.group {
+                break;
+            }
+            let value = elements
+                .get(consumed)
+                .ok_or_else(|| anyhow!("the hint {:?} is missing", entry.key))?;
+            ensure!(
+                entry.size.allows(value.len()),
+                "the hint {:?} has {} bytes, which does not match {:?}",
+                entry.key,
+                value.len(),
+                entry.size
+            );
+            witness.values[idx] = Some(vec![value.clone()]);
+            consumed += 1;
+        }
+        Ok((witness, consumed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::treepp::*;
+    use crate::utils::hint_schema::{HintSchema, HintSize, HintWitness};
+    use crate::utils::pseudo::OP_HINT;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum TestHint {
+        Amount,
+        OutPoint,
+        ScriptPubKey,
+        Data,
+        Extra,
+    }
+
+    fn test_script(schema: &mut HintSchema<TestHint>) -> Script {
+        script! {
+            { schema.hint(TestHint::Amount, HintSize::Exact(8)) }
+            { schema.hint(TestHint::OutPoint, HintSize::EmptyOr(36)) }
+            OP_SIZE 0 OP_EQUAL
+            OP_IF
+                OP_DROP
+            OP_ELSE
+                OP_DROP
+                { schema.hint_if_present(TestHint::ScriptPubKey, HintSize::NonEmpty, TestHint::OutPoint) }
+                OP_DROP
+            OP_ENDIF
+            { schema.hint(TestHint::Data, HintSize::Any) }
+            OP_DROP OP_DROP
+        }
+    }
+
+    #[test]
+    fn test_hint_schema() {
+        let mut schema = HintSchema::new();
+        let script = test_script(&mut schema);
+        assert_eq!(
+            schema.keys(),
+            vec![
+                TestHint::Amount,
+                TestHint::OutPoint,
+                TestHint::ScriptPubKey,
+                TestHint::Data
+            ]
+        );
+
+        for present in [false, true] {
+            // the hints can be provided in any order
+            let mut witness = HintWitness::new(&schema);
+            witness.set(TestHint::Data, vec![1, 2, 3]);
+            if present {
+                witness.set(TestHint::ScriptPubKey, vec![0x51]);
+                witness.set(TestHint::OutPoint, vec![0xab; 36]);
+            } else {
+                witness.set(TestHint::OutPoint, vec![]);
+            }
+            witness.set(TestHint::Amount, vec![0xcd; 8]);
+
+            let hints = witness.finalize();
+            assert_eq!(hints.len(), if present { 4 } else { 3 });
+
+            let (parsed, consumed) = HintWitness::parse(&schema, &hints).unwrap();
+            assert_eq!(consumed, hints.len());
+            assert_eq!(parsed.get(TestHint::Data), Some(&[1u8, 2, 3][..]));
+            assert_eq!(parsed.finalize(), hints);
+
+            let exec_script = script! {
+                for hint in hints.iter() {
+                    { hint.clone() }
+                }
+                { script.clone() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(exec_script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_hint_group() {
+        let mut schema = HintSchema::new();
+        let mut script = test_script(&mut schema);
+        schema.hint_group(TestHint::Extra);
+        script = script! {
+            { script }
+            OP_HINT OP_HINT OP_CAT
+            { vec![0xaau8, 0xbb, 0xcc] } OP_EQUALVERIFY
+        };
+
+        let mut witness = HintWitness::new(&schema);
+        witness.set(TestHint::Amount, vec![0xcd; 8]);
+        witness.set(TestHint::OutPoint, vec![]);
+        witness.set(TestHint::Data, vec![1, 2, 3]);
+        witness.set_group(TestHint::Extra, vec![vec![0xaa], vec![0xbb, 0xcc]]);
+        let hints = witness.finalize();
+        assert_eq!(hints.len(), 5);
+
+        // the group is left to the caller
+        let (parsed, consumed) = HintWitness::parse(&schema, &hints).unwrap();
+        assert_eq!(consumed, 3);
+        assert_eq!(parsed.finalize_before_groups(), hints[..3].to_vec());
+
+        let exec_script = script! {
+            for hint in hints.iter() {
+                { hint.clone() }
+            }
+            { script.clone() }
+            OP_TRUE
+        };
+        let exec_result = execute_script(exec_script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    #[should_panic(expected = "the hint Amount has 4 bytes, which does not match Exact(8)")]
+    fn test_hint_size_mismatch() {
+        let mut schema = HintSchema::new();
+        test_script(&mut schema);
+
+        let mut witness = HintWitness::new(&schema);
+        witness.set(TestHint::Amount, vec![0; 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the hint ScriptPubKey is missing")]
+    fn test_hint_missing() {
+        let mut schema = HintSchema::new();
+        test_script(&mut schema);
+
+        let mut witness = HintWitness::new(&schema);
+        witness.set(TestHint::Amount, vec![0; 8]);
+        witness.set(TestHint::OutPoint, vec![0; 36]);
+        witness.set(TestHint::Data, vec![]);
+        witness.finalize();
+    }
+}
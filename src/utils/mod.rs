@@ -1,10 +1,25 @@
 use crate::treepp::*;
 use bitcoin::opcodes::all::{OP_PUSHBYTES_4, OP_PUSHBYTES_8};
 
+/// Module for the static analysis of script limits.
+pub mod analyzer;
+/// Module for decomposing numbers and bytes into bits or limbs.
+pub mod bits;
+/// Module for the schema of the hints that a script consumes.
+pub mod hint_schema;
 /// Modules for some pseudo opcodes.
 pub mod pseudo;
+/// Module for the SHA-256 compression function in script.
+pub mod sha256;
+/// Module for splitting a byte string into pieces.
+pub mod split;
+/// Module for building scripts with named stack variables.
+pub mod stack_builder;
 /// Module for stack hash.
 pub mod stack_hash;
+/// Module for the helpers shared by the tests.
+#[cfg(test)]
+pub(crate) mod test_utils;
 
 /// Push a 32-bit unsigned integer as a 4-byte string on the stack.
 pub fn push_u32_4bytes(v: u32) -> Script {
@@ -0,0 +1,784 @@
+use crate::internal_structures::cpp_uint_64::OP_256MUL;
+use crate::internal_structures::variable_length_integer::byte_from_number;
+use crate::treepp::*;
+use crate::utils::bits::BitDecompositionGadget;
+use crate::utils::pseudo::OP_HINT;
+use crate::utils::split::{SplitGadget, SplitLength, SplitPart};
+use bitcoin::hashes::{sha256, HashEngine};
+
+/// The initial state of SHA-256.
+const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// The round constants of SHA-256.
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Gadget for the SHA-256 compression function, which allows hashing data that exceeds the
+/// 520-byte limit of `OP_SHA256`.
+///
+/// The state is represented by eight 32-bit words, each of which is two 16-bit limbs on the
+/// stack (the upper one at the bottom), with the first word at the bottom. This is the same
+/// representation as the 64-bit integers in [`crate::internal_structures::u64_limbs`].
+pub struct Sha256Gadget;
+
+impl Sha256Gadget {
+    /// Push the initial state of SHA-256.
+    pub fn initial_state() -> Script {
+        push_state(&IV)
+    }
+
+    /// Construct the state from a midstate of 32 bytes on the stack, which is the state after
+    /// a number of blocks, with each word in big-endian.
+    ///
+    /// The hints are generated by [`Sha256Gadget::get_hints_for_midstate`].
+    pub fn state_from_midstate() -> Script {
+        script! {
+            OP_SIZE 32 OP_EQUALVERIFY
+            { BitDecompositionGadget::bytes_to_limbs(32, 8) }
+            { combine_bytes(32) }
+        }
+    }
+
+    /// Convert the state into 32 bytes, which is the digest if all the blocks (including the
+    /// padding) have been compressed.
+    pub fn state_to_digest() -> Script {
+        script! {
+            15 OP_ROLL { limb_to_bytes() }
+            for j in 1..16 {
+                { 16 - j } OP_ROLL { limb_to_bytes() }
+                OP_CAT
+            }
+        }
+    }
+
+    /// Compress a block into the state.
+    ///
+    /// Input:
+    /// - the state
+    /// - the block of 64 bytes
+    ///
+    /// Output:
+    /// - the new state
+    ///
+    /// Since the bitwise operations are emulated bit by bit, the script is about 380 KB, so a
+    /// standard leaf (see [`crate::utils::analyzer::MAX_LEAF_SCRIPT_SIZE`]) holds at most one
+    /// compression.
+    ///
+    /// The hints are generated by [`Sha256Gadget::get_hints_for_compress`].
+    pub fn compress() -> Script {
+        let mut stack = WordStack::new(8 + 16);
+        let h: Vec<usize> = (0..8).collect();
+        let mut w: Vec<usize> = (8..24).collect();
+        let mut v: Vec<usize> = h.iter().map(|&i| stack.pick(i)).collect();
+
+        for t in 0..64 {
+            if t >= 16 {
+                // W[t] = sigma1(W[t - 2]) + W[t - 7] + sigma0(W[t - 15]) + W[t - 16]
+                stack.pick(w[t - 2]);
+                stack.decompose();
+                stack.xor_rotations(&[17, 19], 10);
+                stack.drop_bits(32);
+                stack.recombine();
+                stack.pick(w[t - 7]);
+                stack.add();
+                stack.pick(w[t - 15]);
+                stack.decompose();
+                stack.xor_rotations(&[7, 18], 3);
+                stack.drop_bits(32);
+                stack.recombine();
+                stack.add();
+                stack.roll(w[t - 16]);
+                w.push(stack.add());
+            }
+
+            // T1 = h + Sigma1(e) + Ch(e, f, g) + K[t] + W[t]
+            for &i in [v[4], v[5], v[6]].iter() {
+                stack.pick(i);
+                stack.decompose();
+            }
+            stack.ch();
+            stack.drop_bits(64);
+            stack.xor_rotations(&[6, 11, 25], 32);
+            stack.drop_bits(32);
+            stack.recombine();
+            stack.recombine();
+            stack.add();
+            stack.pick(v[7]);
+            stack.add();
+            stack.push_constant(K[t]);
+            stack.add();
+            stack.pick(w[t]);
+            let t1 = stack.add();
+
+            // T2 = Sigma0(a) + Maj(a, b, c)
+            for &i in [v[0], v[1], v[2]].iter() {
+                stack.pick(i);
+                stack.decompose();
+            }
+            stack.maj();
+            stack.drop_bits(64);
+            stack.xor_rotations(&[2, 13, 22], 32);
+            stack.drop_bits(32);
+            stack.recombine();
+            stack.recombine();
+            stack.add();
+
+            // a = T1 + T2, e = d + T1
+            stack.pick(t1);
+            let new_a = stack.add();
+            stack.roll(t1);
+            stack.roll(v[3]);
+            let new_e = stack.add();
+            stack.drop_word(v[7]);
+
+            v = vec![new_a, v[0], v[1], v[2], new_e, v[4], v[5], v[6]];
+        }
+
+        for &i in w[48..].iter() {
+            stack.drop_word(i);
+        }
+        for i in 0..8 {
+            stack.roll(h[i]);
+            stack.roll(v[i]);
+            stack.add();
+        }
+
+        script! {
+            OP_SIZE 64 OP_EQUALVERIFY
+            { BitDecompositionGadget::bytes_to_limbs(64, 8) }
+            { combine_bytes(64) }
+            for script in stack.scripts.into_iter() {
+                { script }
+            }
+        }
+    }
+
+    /// Generate the hints for [`Sha256Gadget::state_from_midstate`].
+    pub fn get_hints_for_midstate(midstate: &[u8; 32]) -> Vec<Vec<u8>> {
+        BitDecompositionGadget::get_hints_for_bytes(midstate, 8)
+    }
+
+    /// Generate the hints for [`Sha256Gadget::compress`].
+    pub fn get_hints_for_compress(block: &[u8; 64]) -> Vec<Vec<u8>> {
+        BitDecompositionGadget::get_hints_for_bytes(block, 8)
+    }
+
+    /// Compute the midstate after hashing the prefix, whose length must be a multiple of 64.
+    pub fn compute_midstate(prefix: &[u8]) -> [u8; 32] {
+        assert_eq!(prefix.len() % 64, 0);
+
+        let mut engine = sha256::HashEngine::default();
+        engine.input(prefix);
+        engine.midstate().to_byte_array()
+    }
+}
+
+/// The steps of absorbing an element into [`Sha256StreamBuilder`].
+enum AbsorbStep {
+    /// Split the element into a head that completes the block in the buffer and the rest.
+    SplitElement { head: usize, rest: usize },
+    /// Append the element to the buffer.
+    Cat,
+    /// Split a block out of the buffer and compress it.
+    SplitBlock { rest: usize },
+}
+
+fn plan_absorb(buffer_len: usize, len: usize) -> (Vec<AbsorbStep>, usize) {
+    assert!(len <= 520);
+
+    let mut steps = vec![];
+    let mut buffer_len = buffer_len;
+    if buffer_len + len > 520 {
+        let head = 64 - buffer_len;
+        steps.push(AbsorbStep::SplitElement {
+            head,
+            rest: len - head,
+        });
+        buffer_len = len - head;
+    } else {
+        steps.push(AbsorbStep::Cat);
+        buffer_len += len;
+    }
+    while buffer_len >= 64 {
+        steps.push(AbsorbStep::SplitBlock {
+            rest: buffer_len - 64,
+        });
+        buffer_len -= 64;
+    }
+    (steps, buffer_len)
+}
+
+fn get_padding(buffer_len: usize, total_len: usize) -> Vec<u8> {
+    let padded_len = (buffer_len + 9).div_ceil(64) * 64;
+
+    let mut padding = vec![0u8; padded_len - buffer_len];
+    padding[0] = 0x80;
+    let len = padding.len();
+    padding[len - 8..].copy_from_slice(&((total_len as u64) * 8).to_be_bytes());
+    padding
+}
+
+fn split_two(first: usize, second: usize) -> SplitGadget {
+    SplitGadget::new(vec![
+        SplitPart {
+            len: SplitLength::Fixed(first),
+            keep: true,
+        },
+        SplitPart {
+            len: SplitLength::Fixed(second),
+            keep: true,
+        },
+    ])
+}
+
+/// Builder for hashing a message of any length with SHA-256, where the message is absorbed
+/// element by element, each of a length known when the script is constructed.
+///
+/// While absorbing, the stack holds the state (see [`Sha256Gadget`]) and a buffer of less than
+/// 64 bytes, which are [`Sha256StreamBuilder::STACK_SIZE`] elements in total.
+///
+/// Each block costs a compression of about 380 KB, so only a message of at most 55 bytes (or a
+/// single block after a midstate) fits in a standard leaf. The script for a longer message, such
+/// as one over the 520-byte limit of `OP_SHA256`, is only usable off-chain, e.g., to verify the
+/// hints, or in a non-standard transaction. Hashing data over 520 bytes in a covenant leaf is
+/// therefore not supported.
+#[derive(Clone, Debug, Default)]
+pub struct Sha256StreamBuilder {
+    /// The length of the message that has been absorbed, including the prefix of a midstate.
+    pub total_len: usize,
+    /// Whether the hashing starts from a midstate.
+    pub from_midstate: bool,
+}
+
+impl Sha256StreamBuilder {
+    /// The number of stack elements used by the state and the buffer.
+    pub const STACK_SIZE: usize = 17;
+
+    /// Create the builder that hashes from the beginning.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the builder that continues from a midstate, which is obtained through `OP_HINT`,
+    /// after a prefix of the given length (a multiple of 64).
+    ///
+    /// The prefix is not checked, so the application must not rely on it.
+    pub fn new_with_midstate(prefix_len: usize) -> Self {
+        assert_eq!(prefix_len % 64, 0);
+        Self {
+            total_len: prefix_len,
+            from_midstate: true,
+        }
+    }
+
+    /// Construct the script that initializes the state and the buffer.
+    pub fn init(&self) -> Script {
+        script! {
+            if self.from_midstate {
+                OP_HINT
+                { Sha256Gadget::state_from_midstate() }
+            } else {
+                { Sha256Gadget::initial_state() }
+            }
+            OP_PUSHBYTES_0
+        }
+    }
+
+    /// Construct the script that absorbs the element of `len` bytes on the stack.
+    pub fn absorb(&mut self, len: usize) -> Script {
+        let (steps, _) = plan_absorb(self.total_len % 64, len);
+        self.total_len += len;
+
+        script! {
+            OP_SIZE { len } OP_EQUALVERIFY
+            for step in steps.iter() {
+                { absorb_step_script(step) }
+            }
+        }
+    }
+
+    /// Construct the script that pads the message and outputs the digest.
+    pub fn finalize(&mut self) -> Script {
+        let padding = get_padding(self.total_len % 64, self.total_len);
+        let len = padding.len();
+
+        script! {
+            { padding }
+            { self.absorb(len) }
+            OP_DROP
+            { Sha256Gadget::state_to_digest() }
+        }
+    }
+}
+
+fn absorb_step_script(step: &AbsorbStep) -> Script {
+    match step {
+        AbsorbStep::SplitElement { head, rest } => script! {
+            { split_two(*head, *rest).to_script() }
+            OP_TOALTSTACK
+            OP_CAT
+            { Sha256Gadget::compress() }
+            OP_FROMALTSTACK
+        },
+        AbsorbStep::Cat => script! {
+            OP_CAT
+        },
+        AbsorbStep::SplitBlock { rest } => script! {
+            { split_two(64, *rest).to_script() }
+            OP_TOALTSTACK
+            { Sha256Gadget::compress() }
+            OP_FROMALTSTACK
+        },
+    }
+}
+
+/// Generator of the hints for [`Sha256StreamBuilder`], which absorbs the same elements.
+#[derive(Clone, Debug, Default)]
+pub struct Sha256StreamWitness {
+    /// The hints so far.
+    pub hints: Vec<Vec<u8>>,
+    buffer: Vec<u8>,
+    total_len: usize,
+}
+
+impl Sha256StreamWitness {
+    /// Create the generator that hashes from the beginning.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the generator that continues from the midstate after the prefix.
+    pub fn new_with_midstate(prefix: &[u8]) -> Self {
+        let midstate = Sha256Gadget::compute_midstate(prefix);
+
+        let mut hints = vec![midstate.to_vec()];
+        hints.extend(Sha256Gadget::get_hints_for_midstate(&midstate));
+        Self {
+            hints,
+            buffer: vec![],
+            total_len: prefix.len(),
+        }
+    }
+
+    /// Absorb the element.
+    pub fn absorb(&mut self, data: &[u8]) {
+        let (steps, _) = plan_absorb(self.buffer.len(), data.len());
+        self.total_len += data.len();
+
+        for step in steps.iter() {
+            match step {
+                AbsorbStep::SplitElement { head, rest } => {
+                    self.hints
+                        .extend(split_two(*head, *rest).get_hints(data, &[]));
+                    self.buffer.extend_from_slice(&data[0..*head]);
+                    self.compress_buffer();
+                    self.buffer = data[*head..].to_vec();
+                }
+                AbsorbStep::Cat => {
+                    self.buffer.extend_from_slice(data);
+                }
+                AbsorbStep::SplitBlock { rest } => {
+                    self.hints
+                        .extend(split_two(64, *rest).get_hints(&self.buffer, &[]));
+                    self.compress_buffer();
+                }
+            }
+        }
+    }
+
+    /// Pad the message and return all the hints.
+    pub fn finalize(mut self) -> Vec<Vec<u8>> {
+        let padding = get_padding(self.buffer.len(), self.total_len);
+        self.absorb(&padding);
+        self.hints
+    }
+
+    fn compress_buffer(&mut self) {
+        let block: [u8; 64] = self.buffer[0..64].try_into().unwrap();
+        self.hints
+            .extend(Sha256Gadget::get_hints_for_compress(&block));
+        self.buffer = self.buffer[64..].to_vec();
+    }
+}
+
+fn push_state(state: &[u32; 8]) -> Script {
+    script! {
+        for word in state.iter() {
+            { (word >> 16) as i64 }
+            { (word & 0xffff) as i64 }
+        }
+    }
+}
+
+/// Combine `n` byte values on the stack, with the first one at the bottom, into `n / 2` 16-bit
+/// limbs in big-endian.
+fn combine_bytes(n: usize) -> Script {
+    script! {
+        for j in 0..n / 2 {
+            { n - 1 - j } OP_ROLL OP_256MUL
+            { n - 1 - j } OP_ROLL OP_ADD
+        }
+    }
+}
+
+/// Convert a 16-bit limb into two bytes in big-endian.
+fn limb_to_bytes() -> Script {
+    script! {
+        0 OP_SWAP
+        for i in (0..8).rev() {
+            OP_DUP { 256 << i } OP_GREATERTHANOREQUAL
+            OP_IF
+                { 256 << i } OP_SUB
+                OP_SWAP { 1 << i } OP_ADD OP_SWAP
+            OP_ENDIF
+        }
+        OP_SWAP { byte_from_number() }
+        OP_SWAP { byte_from_number() }
+        OP_CAT
+    }
+}
+
+/// A model of the words on the stack during the compression, which computes the depths of the
+/// words and collects the scripts.
+///
+/// Each word has an identifier. Bits of words being processed sit above all the words, and bits
+/// of the results of the bitwise functions are kept in the altstack.
+struct WordStack {
+    words: Vec<usize>,
+    num_bits: usize,
+    next_id: usize,
+    scripts: Vec<Script>,
+}
+
+impl WordStack {
+    fn new(num_words: usize) -> Self {
+        Self {
+            words: (0..num_words).collect(),
+            num_bits: 0,
+            next_id: num_words,
+            scripts: vec![],
+        }
+    }
+
+    fn new_word(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.words.push(id);
+        id
+    }
+
+    /// The depth of the lower limb of the word.
+    fn depth(&self, id: usize) -> usize {
+        let idx = self.words.iter().position(|&w| w == id).unwrap();
+        self.num_bits + 2 * (self.words.len() - 1 - idx)
+    }
+
+    /// Copy the word to the top, which must be either decomposed into bits right away or there
+    /// must be no bits on the stack.
+    fn pick(&mut self, id: usize) -> usize {
+        let depth = self.depth(id);
+        self.scripts.push(script! {
+            { depth + 1 } OP_PICK { depth + 1 } OP_PICK
+        });
+        self.new_word()
+    }
+
+    fn roll(&mut self, id: usize) {
+        assert_eq!(self.num_bits, 0);
+        let depth = self.depth(id);
+        if depth != 0 {
+            self.scripts.push(script! {
+                { depth + 1 } OP_ROLL { depth + 1 } OP_ROLL
+            });
+            self.words.retain(|&w| w != id);
+            self.words.push(id);
+        }
+    }
+
+    fn drop_word(&mut self, id: usize) {
+        self.roll(id);
+        self.scripts.push(script! { OP_2DROP });
+        self.words.pop();
+    }
+
+    fn push_constant(&mut self, v: u32) -> usize {
+        self.scripts.push(script! {
+            { (v >> 16) as i64 } { (v & 0xffff) as i64 }
+        });
+        self.new_word()
+    }
+
+    /// Add the top two words modulo 2^32.
+    fn add(&mut self) -> usize {
+        self.scripts.push(script! {
+            OP_ROT OP_ADD
+            OP_DUP 65536 OP_GREATERTHANOREQUAL
+            OP_IF
+                65536 OP_SUB
+                OP_SWAP OP_1ADD OP_SWAP
+            OP_ENDIF
+            OP_TOALTSTACK
+            OP_ADD
+            OP_DUP 65536 OP_GREATERTHANOREQUAL
+            OP_IF
+                65536 OP_SUB
+            OP_ENDIF
+            OP_FROMALTSTACK
+        });
+        self.words.pop();
+        self.words.pop();
+        self.new_word()
+    }
+
+    /// Decompose the top word into bits, with the most significant bit at the bottom.
+    fn decompose(&mut self) {
+        self.scripts.push(decompose_word());
+        self.words.pop();
+        self.num_bits += 32;
+    }
+
+    fn drop_bits(&mut self, n: usize) {
+        self.scripts.push(script! {
+            for _ in 0..n / 2 {
+                OP_2DROP
+            }
+        });
+        self.num_bits -= n;
+    }
+
+    /// Compute the XOR of the rotations (and the right shift) of the top 32 bits into the
+    /// altstack.
+    fn xor_rotations(&mut self, rotations: &[usize], shift: usize) {
+        let mut script_bytes = vec![];
+        for i in 0..32 {
+            let mut terms: Vec<usize> = rotations.iter().map(|r| (i + r) % 32).collect();
+            if i + shift < 32 {
+                terms.push(i + shift);
+            }
+            let script = script! {
+                { terms[0] } OP_PICK
+                for &term in terms[1..].iter() {
+                    { term + 1 } OP_PICK OP_NUMNOTEQUAL
+                }
+                OP_TOALTSTACK
+            };
+            script_bytes.extend_from_slice(script.as_bytes());
+        }
+        self.scripts.push(Script::from_bytes(script_bytes));
+    }
+
+    /// Compute Ch(e, f, g) of the top 96 bits into the altstack.
+    fn ch(&mut self) {
+        self.scripts.push(script! {
+            for i in 0..32 {
+                { 64 + i } OP_PICK
+                OP_IF
+                    { 32 + i } OP_PICK
+                OP_ELSE
+                    { i } OP_PICK
+                OP_ENDIF
+                OP_TOALTSTACK
+            }
+        });
+    }
+
+    /// Compute Maj(a, b, c) of the top 96 bits into the altstack.
+    fn maj(&mut self) {
+        self.scripts.push(script! {
+            for i in 0..32 {
+                { 64 + i } OP_PICK
+                { 33 + i } OP_PICK OP_ADD
+                { i + 1 } OP_PICK OP_ADD
+                2 OP_GREATERTHANOREQUAL
+                OP_TOALTSTACK
+            }
+        });
+    }
+
+    /// Recombine the 32 bits in the altstack into a word.
+    fn recombine(&mut self) -> usize {
+        assert_eq!(self.num_bits, 0);
+        self.scripts.push(script! {
+            for _ in 0..2 {
+                OP_FROMALTSTACK
+                for _ in 1..16 {
+                    OP_DUP OP_ADD OP_FROMALTSTACK OP_ADD
+                }
+            }
+        });
+        self.new_word()
+    }
+}
+
+/// Decompose the word on the stack into 32 bits, with the most significant bit at the bottom.
+fn decompose_word() -> Script {
+    script! {
+        OP_TOALTSTACK
+        { decompose_limb() }
+        OP_FROMALTSTACK
+        { decompose_limb() }
+    }
+}
+
+fn decompose_limb() -> Script {
+    script! {
+        for i in (1..16).rev() {
+            OP_DUP { 1 << i } OP_GREATERTHANOREQUAL
+            OP_SWAP OP_OVER
+            OP_IF
+                { 1 << i } OP_SUB
+            OP_ENDIF
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::treepp::*;
+    use crate::utils::analyzer::{check_within_limits, MAX_LEAF_SCRIPT_SIZE};
+    use crate::utils::sha256::{Sha256Gadget, Sha256StreamBuilder, Sha256StreamWitness};
+    use crate::utils::test_utils::push_hint;
+    use bitcoin::hashes::{sha256, Hash};
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_compress() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut data = vec![0u8; 128];
+        prng.fill_bytes(&mut data);
+
+        let block: [u8; 64] = data[64..].try_into().unwrap();
+        let midstate = Sha256Gadget::compute_midstate(&data[0..64]);
+        let expected = Sha256Gadget::compute_midstate(&data);
+
+        let mut hints = Sha256Gadget::get_hints_for_midstate(&midstate);
+        hints.extend(Sha256Gadget::get_hints_for_compress(&block));
+
+        let script = script! {
+            for hint in hints.iter() {
+                { push_hint(hint) }
+            }
+            { midstate.to_vec() }
+            { Sha256Gadget::state_from_midstate() }
+            { block.to_vec() }
+            { Sha256Gadget::compress() }
+            { Sha256Gadget::state_to_digest() }
+            { expected.to_vec() }
+            OP_EQUAL
+        };
+
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..3 {
+            let num_elements = prng.gen_range(1..5);
+            let elements: Vec<Vec<u8>> = (0..num_elements)
+                .map(|_| {
+                    let mut element = vec![0u8; prng.gen_range(2..=520)];
+                    prng.fill_bytes(&mut element);
+                    element
+                })
+                .collect();
+
+            let mut builder = Sha256StreamBuilder::new();
+            let mut witness = Sha256StreamWitness::new();
+            let mut scripts = vec![builder.init()];
+            for element in elements.iter() {
+                scripts.push(script! {
+                    { element.clone() }
+                    { builder.absorb(element.len()) }
+                });
+                witness.absorb(element);
+            }
+            scripts.push(builder.finalize());
+            let hints = witness.finalize();
+
+            let expected = sha256::Hash::hash(&elements.concat());
+
+            let script = script! {
+                for hint in hints.iter() {
+                    { push_hint(hint) }
+                }
+                for script in scripts.into_iter() {
+                    { script }
+                }
+                { expected.to_byte_array().to_vec() }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_stream_with_midstate() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut prefix = vec![0u8; 128];
+        prng.fill_bytes(&mut prefix);
+        let mut element = vec![0u8; 100];
+        prng.fill_bytes(&mut element);
+
+        let mut builder = Sha256StreamBuilder::new_with_midstate(prefix.len());
+        let mut witness = Sha256StreamWitness::new_with_midstate(&prefix);
+        witness.absorb(&element);
+        let hints = witness.finalize();
+
+        let expected = sha256::Hash::hash(&[prefix, element.clone()].concat());
+
+        let script = script! {
+            for hint in hints.iter() {
+                { push_hint(hint) }
+            }
+            { builder.init() }
+            { element }
+            { builder.absorb(100) }
+            { builder.finalize() }
+            { expected.to_byte_array().to_vec() }
+            OP_EQUAL
+        };
+
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_leaf_limits() {
+        // A message of 55 bytes takes one block, which fits in a standard leaf.
+        let mut builder = Sha256StreamBuilder::new();
+        let script = script! {
+            { builder.init() }
+            { builder.absorb(55) }
+            { builder.finalize() }
+        };
+        assert!(check_within_limits(&script, "one block").is_ok());
+
+        // A message of 56 bytes takes two blocks, which do not.
+        let mut builder = Sha256StreamBuilder::new();
+        let script = script! {
+            { builder.init() }
+            { builder.absorb(56) }
+            { builder.finalize() }
+        };
+        assert!(script.len() > MAX_LEAF_SCRIPT_SIZE);
+        assert!(check_within_limits(&script, "two blocks").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::treepp::*;
+use crate::utils::pseudo::OP_HINT;
+
+/// The length of a piece in [`SplitGadget`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitLength {
+    /// The piece has exactly this number of bytes.
+    Fixed(usize),
+    /// The piece has a number of bytes that is only known from the hint, within `min..=max`.
+    Hinted {
+        /// The minimum number of bytes.
+        min: usize,
+        /// The maximum number of bytes.
+        max: usize,
+    },
+}
+
+/// A piece of the byte string to be split.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SplitPart {
+    /// The length of the piece.
+    pub len: SplitLength,
+    /// Whether the piece is returned on the stack.
+    pub keep: bool,
+}
+
+/// Gadget for splitting a byte string on the stack into pieces, which emulates the disabled
+/// `OP_SUBSTR`, `OP_LEFT`, and `OP_RIGHT`.
+///
+/// The pieces are obtained through `OP_HINT`, checked against the layout, and checked to
+/// concatenate to the byte string. Note that the split is only unique if at most one piece has a
+/// hinted length, or if the hinted lengths are otherwise determined by the content.
+#[derive(Clone, Debug)]
+pub struct SplitGadget {
+    /// The layout of the pieces, in order.
+    pub parts: Vec<SplitPart>,
+}
+
+impl SplitGadget {
+    /// Create the gadget with the given layout.
+    pub fn new(parts: Vec<SplitPart>) -> Self {
+        assert!(!parts.is_empty());
+        for part in parts.iter() {
+            if let SplitLength::Hinted { min, max } = part.len {
+                assert!(min <= max);
+            }
+        }
+        Self { parts }
+    }
+
+    /// Construct the script that splits the byte string.
+    ///
+    /// Input:
+    /// - the byte string
+    ///
+    /// Output:
+    /// - the pieces with `keep` set, with the first one at the bottom
+    pub fn to_script(&self) -> Script {
+        let num_kept = self.parts.iter().filter(|part| part.keep).count();
+
+        script! {
+            for (i, part) in self.parts.iter().enumerate() {
+                OP_HINT
+                { length_check(part.len) }
+                if part.keep {
+                    OP_DUP OP_TOALTSTACK
+                }
+                if i > 0 {
+                    OP_CAT
+                }
+            }
+            OP_EQUALVERIFY
+
+            for _ in 0..num_kept {
+                OP_FROMALTSTACK
+            }
+            // the altstack returns them in the reverse order
+            for i in 1..num_kept {
+                { i } OP_ROLL
+            }
+        }
+    }
+
+    /// Generate the hints for the script, given the byte string and the length of each piece
+    /// with [`SplitLength::Hinted`], in order.
+    pub fn get_hints(&self, target: &[u8], hinted_lens: &[usize]) -> Vec<Vec<u8>> {
+        let mut hinted_lens = hinted_lens.iter();
+
+        let mut hints = vec![];
+        let mut start = 0;
+        for part in self.parts.iter() {
+            let len = match part.len {
+                SplitLength::Fixed(len) => len,
+                SplitLength::Hinted { min, max } => {
+                    let len = *hinted_lens.next().unwrap();
+                    assert!(len >= min && len <= max);
+                    len
+                }
+            };
+            hints.push(target[start..start + len].to_vec());
+            start += len;
+        }
+        assert!(hinted_lens.next().is_none());
+        assert_eq!(start, target.len());
+
+        hints
+    }
+}
+
+fn length_check(len: SplitLength) -> Script {
+    match len {
+        SplitLength::Fixed(len) => script! {
+            OP_SIZE { len } OP_EQUALVERIFY
+        },
+        SplitLength::Hinted { min, max } => script! {
+            OP_SIZE { min } { max + 1 } OP_WITHIN OP_VERIFY
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::treepp::*;
+    use crate::utils::split::{SplitGadget, SplitLength, SplitPart};
+    use crate::utils::test_utils::push_hint;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_split() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..50 {
+            let num_parts = prng.gen_range(1..6);
+            let hinted_idx = prng.gen_range(0..num_parts);
+
+            let mut parts = vec![];
+            let mut hinted_lens = vec![];
+            let mut len = 0;
+            for i in 0..num_parts {
+                let keep = prng.gen();
+                if i == hinted_idx {
+                    let hinted_len = prng.gen_range(0..40);
+                    hinted_lens.push(hinted_len);
+                    len += hinted_len;
+                    parts.push(SplitPart {
+                        len: SplitLength::Hinted { min: 0, max: 40 },
+                        keep,
+                    });
+                } else {
+                    let fixed_len = prng.gen_range(0..40);
+                    len += fixed_len;
+                    parts.push(SplitPart {
+                        len: SplitLength::Fixed(fixed_len),
+                        keep,
+                    });
+                }
+            }
+
+            let mut target = vec![0u8; len];
+            prng.fill_bytes(&mut target);
+
+            let gadget = SplitGadget::new(parts.clone());
+            let hints = gadget.get_hints(&target, &hinted_lens);
+
+            let script = script! {
+                for hint in hints.iter() {
+                    { push_hint(hint) }
+                }
+                { target.clone() }
+                { gadget.to_script() }
+                for (part, hint) in parts.iter().zip(hints.iter()).rev() {
+                    if part.keep {
+                        { push_hint(hint) }
+                        OP_EQUALVERIFY
+                    }
+                }
+                OP_TRUE
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_split_mismatch() {
+        let gadget = SplitGadget::new(vec![
+            SplitPart {
+                len: SplitLength::Fixed(8),
+                keep: true,
+            },
+            SplitPart {
+                len: SplitLength::Hinted { min: 0, max: 10 },
+                keep: false,
+            },
+        ]);
+
+        let target = vec![0x42u8; 16];
+
+        // pieces with a wrong length
+        let script = script! {
+            { vec![0x42u8; 7] }
+            { vec![0x42u8; 9] }
+            { target.clone() }
+            { gadget.to_script() }
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+
+        // pieces that do not concatenate to the byte string
+        let script = script! {
+            { vec![0x42u8; 8] }
+            { vec![0x43u8; 8] }
+            { target.clone() }
+            { gadget.to_script() }
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+}
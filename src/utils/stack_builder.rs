@@ -0,0 +1,309 @@
+use crate::treepp::*;
+use crate::utils::hint_schema::{HintSchema, HintSize};
+use crate::utils::pseudo::OP_HINT;
+use std::fmt::Debug;
+
+/// A named variable on the stack, tracked by the [`StackBuilder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackVariable(usize);
+
+/// Where a variable currently is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Location {
+    Stack,
+    Altstack,
+    Consumed,
+}
+
+/// Builder that tracks the stack by variable names and emits the stack moves.
+///
+/// A gadget is applied to a list of variables, which are consumed. The builder moves them to the
+/// top of the stack (in order, with the last one on top) using OP_SWAP, OP_ROT, or OP_ROLL, and
+/// the outputs of the gadget become new variables. A variable that is still needed later should
+/// be copied with [`StackBuilder::copy`] (which emits OP_DUP, OP_OVER, or OP_PICK) before being
+/// consumed.
+///
+/// The builder panics when the script is being built if a variable is used after being consumed,
+/// used while it is on the altstack, or left on the stack at the end.
+///
+/// The elements below the variables that the builder tracks are not touched, except that hints
+/// are taken from the bottom of the stack.
+#[derive(Clone, Debug, Default)]
+pub struct StackBuilder {
+    names: Vec<String>,
+    locations: Vec<Location>,
+    stack: Vec<StackVariable>,
+    altstack: Vec<StackVariable>,
+    script: Vec<u8>,
+}
+
+impl StackBuilder {
+    /// Create an empty builder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn new_variable(&mut self, name: &str) -> StackVariable {
+        let var = StackVariable(self.names.len());
+        self.names.push(name.to_string());
+        self.locations.push(Location::Stack);
+        self.stack.push(var);
+        var
+    }
+
+    fn emit(&mut self, script: Script) {
+        self.script.extend_from_slice(script.as_bytes());
+    }
+
+    /// The name of a variable.
+    pub fn name(&self, var: StackVariable) -> &str {
+        &self.names[var.0]
+    }
+
+    /// The variables on the stack, with the last one on top.
+    pub fn stack(&self) -> &[StackVariable] {
+        &self.stack
+    }
+
+    /// The depth of a variable on the stack, where the top of the stack has depth 0.
+    pub fn depth(&self, var: StackVariable) -> usize {
+        match self.locations[var.0] {
+            Location::Stack => {}
+            Location::Altstack => panic!("variable `{}` is on the altstack", self.name(var)),
+            Location::Consumed => {
+                panic!("variable `{}` is used after being consumed", self.name(var))
+            }
+        }
+        let idx = self.stack.iter().rposition(|v| *v == var).unwrap();
+        self.stack.len() - 1 - idx
+    }
+
+    /// Declare an element that is already on the stack (above the previously declared ones).
+    ///
+    /// This should be done before anything else is pushed.
+    pub fn input(&mut self, name: &str) -> StackVariable {
+        assert!(
+            self.script.is_empty(),
+            "inputs must be declared before the script starts"
+        );
+        self.new_variable(name)
+    }
+
+    /// Take a hint, which becomes a new variable.
+    pub fn hint(&mut self, name: &str) -> StackVariable {
+        self.emit(script! { OP_HINT });
+        self.new_variable(name)
+    }
+
+    /// Take a hint and check that it has the given size.
+    pub fn hint_with_size(&mut self, name: &str, size: usize) -> StackVariable {
+        self.emit(script! {
+            OP_HINT
+            OP_SIZE { size } OP_EQUALVERIFY
+        });
+        self.new_variable(name)
+    }
+
+    /// Take a hint declared in the schema, which becomes a new variable named after the key.
+    pub fn hint_from_schema<K: Copy + Eq + Debug>(
+        &mut self,
+        schema: &mut HintSchema<K>,
+        key: K,
+        size: HintSize,
+    ) -> StackVariable {
+        self.emit(schema.hint(key, size));
+        self.new_variable(&format!("{:?}", key))
+    }
+
+    /// Run a script that pushes one element without consuming anything.
+    pub fn push(&mut self, name: &str, script: Script) -> StackVariable {
+        let [var] = self.apply(script, &[], [name]);
+        var
+    }
+
+    /// Copy a variable to the top of the stack.
+    pub fn copy(&mut self, var: StackVariable) -> StackVariable {
+        let depth = self.depth(var);
+        self.emit(match depth {
+            0 => script! { OP_DUP },
+            1 => script! { OP_OVER },
+            _ => script! { { depth } OP_PICK },
+        });
+        let name = self.name(var).to_string();
+        self.new_variable(&name)
+    }
+
+    /// Drop a variable.
+    pub fn drop(&mut self, var: StackVariable) {
+        let depth = self.depth(var);
+        if depth == 1 {
+            self.emit(script! { OP_NIP });
+            self.stack.remove(self.stack.len() - 2);
+            self.locations[var.0] = Location::Consumed;
+        } else {
+            self.apply(script! { OP_DROP }, &[var], []);
+        }
+    }
+
+    /// Move the variables to the top of the stack, in order, with the last one on top.
+    fn move_to_top(&mut self, vars: &[StackVariable]) {
+        for (i, var) in vars.iter().enumerate() {
+            if vars[..i].contains(var) {
+                panic!(
+                    "variable `{}` is used twice, which needs a copy",
+                    self.name(*var)
+                );
+            }
+            // make sure that all the variables can be used before emitting anything
+            self.depth(*var);
+        }
+
+        // skip the variables that are already in place
+        let n = vars.len();
+        if self.stack.len() >= n && self.stack[self.stack.len() - n..] == *vars {
+            return;
+        }
+
+        for var in vars.iter() {
+            let depth = self.depth(*var);
+            self.emit(match depth {
+                0 => script! {},
+                1 => script! { OP_SWAP },
+                2 => script! { OP_ROT },
+                _ => script! { { depth } OP_ROLL },
+            });
+            let idx = self.stack.len() - 1 - depth;
+            let var = self.stack.remove(idx);
+            self.stack.push(var);
+        }
+    }
+
+    /// Apply a script to the variables, which are consumed, and return the outputs of the
+    /// script as new variables.
+    ///
+    /// The script expects the inputs on the top of the stack with the last one on top, and
+    /// leaves the outputs on the stack with the last one on top.
+    pub fn apply<const N: usize>(
+        &mut self,
+        script: Script,
+        inputs: &[StackVariable],
+        outputs: [&str; N],
+    ) -> [StackVariable; N] {
+        self.move_to_top(inputs);
+        for var in inputs.iter() {
+            self.locations[var.0] = Location::Consumed;
+        }
+        self.stack.truncate(self.stack.len() - inputs.len());
+
+        self.emit(script);
+        outputs.map(|name| self.new_variable(name))
+    }
+
+    /// Move a variable to the altstack.
+    pub fn move_to_altstack(&mut self, var: StackVariable) {
+        self.move_to_top(&[var]);
+        self.emit(script! { OP_TOALTSTACK });
+        self.stack.pop();
+        self.altstack.push(var);
+        self.locations[var.0] = Location::Altstack;
+    }
+
+    /// Move the variable on the top of the altstack back to the stack.
+    pub fn move_from_altstack(&mut self) -> StackVariable {
+        let var = self.altstack.pop().expect("the altstack is empty");
+        self.emit(script! { OP_FROMALTSTACK });
+        self.stack.push(var);
+        self.locations[var.0] = Location::Stack;
+        var
+    }
+
+    /// Arrange the outputs on the top of the stack, in order, and return the script.
+    ///
+    /// All the other variables must have been consumed.
+    pub fn build(mut self, outputs: &[StackVariable]) -> Script {
+        self.move_to_top(outputs);
+        if let Some(var) = self.stack.iter().find(|v| !outputs.contains(v)) {
+            panic!("variable `{}` is left on the stack", self.name(*var));
+        }
+        if let Some(var) = self.altstack.last() {
+            panic!("variable `{}` is left on the altstack", self.name(*var));
+        }
+        Script::from_bytes(self.script)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::treepp::*;
+    use crate::utils::stack_builder::StackBuilder;
+    use bitcoin::hashes::{sha256, Hash};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_stack_builder() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let a = (0..20).map(|_| prng.gen()).collect::<Vec<u8>>();
+        let b = (0..30).map(|_| prng.gen()).collect::<Vec<u8>>();
+        let c = (0..4).map(|_| prng.gen()).collect::<Vec<u8>>();
+
+        // compute sha256(c | a | b) and (a | c), and keep b
+        let mut builder = StackBuilder::new();
+        let var_a = builder.input("a");
+        let var_b = builder.input("b");
+        let var_c = builder.hint_with_size("c", 4);
+        builder.move_to_altstack(var_c);
+        let copy_a = builder.copy(var_a);
+        let copy_b = builder.copy(var_b);
+        let var_c = builder.move_from_altstack();
+        let copy_c = builder.copy(var_c);
+        let [hash] = builder.apply(
+            script! { OP_CAT OP_CAT OP_SHA256 },
+            &[copy_c, copy_a, copy_b],
+            ["hash"],
+        );
+        let [cat] = builder.apply(script! { OP_CAT }, &[var_a, var_c], ["a | c"]);
+        let script = builder.build(&[hash, cat, var_b]);
+
+        let mut ca = c.clone();
+        ca.extend_from_slice(&a);
+        ca.extend_from_slice(&b);
+        let expected_hash = sha256::Hash::hash(&ca).to_byte_array().to_vec();
+
+        let mut ac = a.clone();
+        ac.extend_from_slice(&c);
+
+        let script = script! {
+            { c.clone() }
+            { a.clone() }
+            { b.clone() }
+            { script }
+            { b.clone() } OP_EQUALVERIFY
+            { ac.clone() } OP_EQUALVERIFY
+            { expected_hash.clone() } OP_EQUAL
+        };
+
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    #[should_panic(expected = "variable `a` is used after being consumed")]
+    fn test_use_after_consumed() {
+        let mut builder = StackBuilder::new();
+        let var_a = builder.input("a");
+        let var_b = builder.input("b");
+        builder.apply(script! { OP_CAT }, &[var_a, var_b], ["a | b"]);
+        builder.copy(var_a);
+    }
+
+    #[test]
+    #[should_panic(expected = "variable `b` is left on the stack")]
+    fn test_left_on_stack() {
+        let mut builder = StackBuilder::new();
+        let var_a = builder.input("a");
+        builder.input("b");
+        builder.build(&[var_a]);
+    }
+}
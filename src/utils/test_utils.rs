@@ -0,0 +1,58 @@
+use crate::treepp::*;
+use crate::wizards::tap_csv_preimage_builder::FieldSource;
+use bitcoin::hashes::Hash;
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::opcodes::all::{OP_PUSHBYTES_36, OP_RETURN};
+use bitcoin::{PubkeyHash, ScriptBuf, ScriptHash, WPubkeyHash, WScriptHash, XOnlyPublicKey};
+use rand::{Rng, RngCore};
+use rand_chacha::ChaCha20Rng;
+
+/// Push a hint in the test script, which, unlike the witness, requires minimal pushes.
+pub(crate) fn push_hint(hint: &[u8]) -> Script {
+    match hint {
+        [] => script! { OP_PUSHBYTES_0 },
+        [0x81] => script! { -1 },
+        [v] if (1..=16).contains(v) => script! { { *v as i64 } },
+        _ => script! { { hint.to_vec() } },
+    }
+}
+
+/// Pick a random source for a field, which can be `FieldSource::FromStack` only if allowed.
+pub(crate) fn get_rand_source<T>(
+    prng: &mut ChaCha20Rng,
+    value: T,
+    allow_from_stack: bool,
+) -> FieldSource<T> {
+    let num_kinds = if allow_from_stack { 3 } else { 2 };
+    match prng.gen_range(0..num_kinds) {
+        0 => FieldSource::Constant(value),
+        1 => FieldSource::Hint,
+        _ => FieldSource::FromStack,
+    }
+}
+
+/// Generate a random script pub key of any supported type.
+pub(crate) fn get_rand_script_pub_key(prng: &mut ChaCha20Rng) -> ScriptBuf {
+    let mut bytes = [0u8; 32];
+    prng.fill_bytes(&mut bytes);
+    match prng.gen_range(0..7) {
+        0 => ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&bytes)),
+        1 => ScriptBuf::new_p2wsh(&WScriptHash::hash(&bytes)),
+        2 => {
+            // the caboose of the covenant
+            let mut script_bytes = vec![OP_RETURN.to_u8(), OP_PUSHBYTES_36.to_u8()];
+            script_bytes.extend_from_slice(&bytes);
+            script_bytes.extend_from_slice(&prng.gen::<u32>().to_le_bytes());
+            ScriptBuf::new_p2wsh(&ScriptBuf::from_bytes(script_bytes).wscript_hash())
+        }
+        3 => ScriptBuf::new_p2pkh(&PubkeyHash::hash(&bytes)),
+        4 => ScriptBuf::new_p2sh(&ScriptHash::hash(&bytes)),
+        5 => ScriptBuf::new_op_return(bytes),
+        _ => loop {
+            prng.fill_bytes(&mut bytes);
+            if let Ok(key) = XOnlyPublicKey::from_slice(&bytes) {
+                break ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(key));
+            }
+        },
+    }
+}
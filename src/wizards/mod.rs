@@ -7,6 +7,10 @@ pub mod outpoint;
 /// Module for the preimage for taproot CheckSigVerify.
 pub mod tap_csv_preimage;
 
+/// Module for building the preimage for taproot CheckSigVerify from constants, hints, and stack
+/// elements.
+pub mod tap_csv_preimage_builder;
+
 /// Module for the transaction.
 pub mod tx;
 
@@ -15,3 +19,6 @@ pub mod tx_in;
 
 /// Module for the transaction output.
 pub mod tx_out;
+
+/// Module for reflecting a previous transaction from constants, hints, and stack elements.
+pub mod tx_reflection_builder;
@@ -138,6 +138,7 @@ pub mod data_input_if_anyonecanpay {
 
 use crate::treepp::*;
 use crate::utils::pseudo::{OP_CAT2, OP_CAT4};
+use bitcoin::sighash::Annex;
 use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, TapLeafHash, TapSighashType, Transaction};
 
 pub use crate::structures::epoch::EpochGadget as Step1EpochGadget;
@@ -182,6 +183,71 @@ impl TapCSVPreImageGadget {
         tap_leaf_hash: &TapLeafHash,
         code_sep_pos: Option<u32>,
         hash_type: &TapSighashType,
+    ) -> Script {
+        Self::from_constant_with_annex(
+            tx,
+            input_amounts,
+            input_script_pub_keys,
+            this_input_idx,
+            tap_leaf_hash,
+            code_sep_pos,
+            hash_type,
+            None,
+        )
+    }
+
+    /// Construct the preimage from constant data, where the input may have an annex.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_constant_with_annex(
+        tx: &Transaction,
+        input_amounts: &[Amount],
+        input_script_pub_keys: &[ScriptBuf],
+        this_input_idx: usize,
+        tap_leaf_hash: &TapLeafHash,
+        code_sep_pos: Option<u32>,
+        hash_type: &TapSighashType,
+        annex: Option<&Annex>,
+    ) -> Script {
+        script! {
+            { Self::common_from_constant(tx, input_amounts, input_script_pub_keys, this_input_idx, hash_type, 1, annex) }
+            { Step12ExtGadget::from_constant(tap_leaf_hash, code_sep_pos) }
+            OP_CAT2
+        }
+    }
+
+    /// Construct the preimage of a key-path spend (with the extension flag being 0) from constant
+    /// data, which has no tapscript extension.
+    ///
+    /// It can be used to check, through the Schnorr trick, that another input of the same
+    /// transaction is a key-path spend under the given hash type.
+    pub fn from_constant_key_path(
+        tx: &Transaction,
+        input_amounts: &[Amount],
+        input_script_pub_keys: &[ScriptBuf],
+        this_input_idx: usize,
+        hash_type: &TapSighashType,
+        annex: Option<&Annex>,
+    ) -> Script {
+        Self::common_from_constant(
+            tx,
+            input_amounts,
+            input_script_pub_keys,
+            this_input_idx,
+            hash_type,
+            0,
+            annex,
+        )
+    }
+
+    /// Construct the part of the preimage that is shared by the key path and the script path.
+    fn common_from_constant(
+        tx: &Transaction,
+        input_amounts: &[Amount],
+        input_script_pub_keys: &[ScriptBuf],
+        this_input_idx: usize,
+        hash_type: &TapSighashType,
+        ext_flag: u8,
+        annex: Option<&Annex>,
     ) -> Script {
         assert_eq!(tx.input.len(), input_amounts.len());
 
@@ -204,7 +270,7 @@ impl TapCSVPreImageGadget {
                 { Step6TxPart2GadgetIfNotNoneOrSingle::from_constant(&tx.output) }
                 OP_CAT2
             }
-            { Step7SpendTypeGadget::from_constant(1, false) }
+            { Step7SpendTypeGadget::from_constant(ext_flag, annex.is_some()) }
             if [TapSighashType::AllPlusAnyoneCanPay, TapSighashType::NonePlusAnyoneCanPay, TapSighashType::SinglePlusAnyoneCanPay].contains(hash_type) {
                 { Step8DataInputPart1GadgetIfAnyOneCanPay::from_constant(
                     &tx.input[this_input_idx].previous_output,
@@ -215,14 +281,17 @@ impl TapCSVPreImageGadget {
             } else {
                 { Step9InputIndexGadgetIfNotAnyOneCanPay::from_constant(this_input_idx as u32) }
             }
-            { Step10AnnexGadgetIfPresent::none() }
+            if annex.is_some() {
+                { Step10AnnexGadgetIfPresent::sha_from_constant(annex.unwrap()) }
+            } else {
+                { Step10AnnexGadgetIfPresent::none() }
+            }
             OP_CAT4
             if [TapSighashType::Single, TapSighashType::SinglePlusAnyoneCanPay].contains(hash_type) {
                 { Step11ThisOutputGadgetIfSingle::from_constant(&tx.output[this_input_idx]) }
+                OP_SHA256
                 OP_CAT2
             }
-            { Step12ExtGadget::from_constant(tap_leaf_hash, code_sep_pos) }
-            OP_CAT2
         }
     }
 }
@@ -240,7 +309,7 @@ mod test {
     };
     use bitcoin::consensus::Decodable;
     use bitcoin::hashes::Hash;
-    use bitcoin::sighash::{Prevouts, SighashCache};
+    use bitcoin::sighash::{Annex, Prevouts, SighashCache};
     use bitcoin::{Amount, ScriptBuf, TapLeafHash, TapSighashType, Transaction, TxOut};
     use rand::{RngCore, SeedableRng};
     use rand_chacha::ChaCha20Rng;
@@ -364,6 +433,8 @@ mod test {
                 { step11_this_output_if_single::Step1AmountGadget::from_constant(&tx.output[0].value) }
                 { step11_this_output_if_single::Step2ScriptPubKeyGadget::from_constant(&tx.output[0].script_pubkey) }
                 OP_CAT2
+                OP_SHA256
+                OP_CAT2
             }
             { step12_ext::Step1TapLeafHashGadget::from_constant(&tap_leaf_hash) }
             { step12_ext::Step2KeyVersionGadget::from_constant(0) }
@@ -381,5 +452,88 @@ mod test {
 
         let exec_result = execute_script(script);
         assert!(exec_result.success);
+
+        // The same transaction, but the input has an annex.
+        let annex_bytes = [0x50, 0x11, 0x22, 0x33];
+        let annex = Annex::new(&annex_bytes).unwrap();
+
+        let tx_preimage_expected = {
+            let mut bytes = vec![];
+            let mut sighashcache = SighashCache::new(tx.clone());
+            sighashcache
+                .taproot_encode_signing_data_to(
+                    &mut bytes,
+                    0,
+                    &Prevouts::All(&[
+                        TxOut {
+                            value: input_amounts[0].clone(),
+                            script_pubkey: input_script_pub_keys[0].clone(),
+                        },
+                        TxOut {
+                            value: input_amounts[1].clone(),
+                            script_pubkey: input_script_pub_keys[1].clone(),
+                        },
+                    ]),
+                    Some(annex.clone()),
+                    Some((tap_leaf_hash, 0xffffffffu32)),
+                    TapSighashType::All,
+                )
+                .unwrap();
+
+            bytes
+        };
+
+        let script = script! {
+            { TapCSVPreImageGadget::from_constant_with_annex(&tx, &input_amounts, &input_script_pub_keys, 0, &tap_leaf_hash, None, &TapSighashType::All, Some(&annex)) }
+            { tx_preimage_expected }
+            OP_EQUAL
+        };
+
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+
+        // The original transaction uses the key path for both inputs.
+        let prevouts = [
+            TxOut {
+                value: input_amounts[0],
+                script_pubkey: input_script_pub_keys[0].clone(),
+            },
+            TxOut {
+                value: input_amounts[1],
+                script_pubkey: input_script_pub_keys[1].clone(),
+            },
+        ];
+        for hash_type in [
+            TapSighashType::Default,
+            TapSighashType::All,
+            TapSighashType::None,
+            TapSighashType::Single,
+            TapSighashType::AllPlusAnyoneCanPay,
+            TapSighashType::NonePlusAnyoneCanPay,
+            TapSighashType::SinglePlusAnyoneCanPay,
+        ] {
+            for (input_idx, annex) in [(0, None), (1, None), (1, Some(&annex))] {
+                let mut tx_preimage_expected = vec![];
+                SighashCache::new(tx.clone())
+                    .taproot_encode_signing_data_to(
+                        &mut tx_preimage_expected,
+                        input_idx,
+                        &Prevouts::All(&prevouts),
+                        annex.cloned(),
+                        None,
+                        hash_type,
+                    )
+                    .unwrap();
+
+                let script = script! {
+                    { TapCSVPreImageGadget::from_constant_key_path(&tx, &input_amounts, &input_script_pub_keys, input_idx, &hash_type, annex) }
+                    { tx_preimage_expected }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
     }
 }
@@ -0,0 +1,991 @@
+use crate::structures::script_pub_key::ScriptPubKeyGadget;
+use crate::treepp::*;
+use crate::utils::pseudo::{OP_CAT2, OP_HINT};
+use crate::wizards::tap_csv_preimage::{
+    step12_ext, step8_data_input_part_if_anyonecanpay, Step1EpochGadget, Step2HashTypeGadget,
+    Step3VersionGadget, Step4LockTimeGadget, Step7SpendTypeGadget,
+    Step9InputIndexGadgetIfNotAnyOneCanPay,
+};
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, OutPoint, ScriptBuf, Sequence, TapLeafHash, TapSighashType, Transaction, TxOut,
+};
+
+/// Where the value of a field in the preimage comes from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum FieldSource<T> {
+    /// The value is known when the script is constructed, and it is hardcoded in the script.
+    Constant(T),
+    /// The value is provided as a hint in the witness, obtained through `OP_HINT`.
+    #[default]
+    Hint,
+    /// The value has been placed on the stack before the script runs.
+    ///
+    /// The values from the stack are consumed in the order of the fields in the preimage, and the
+    /// one of the last such field is expected at the top of the stack.
+    FromStack,
+}
+
+/// The elements that the witness needs to provide for a script built from field sources.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FieldWitness {
+    /// The hints, in the order that they are consumed by `OP_HINT`, which is the order that they
+    /// should be pushed into the witness.
+    pub hints: Vec<Vec<u8>>,
+    /// The elements expected on the stack, from the bottom to the top.
+    pub stack: Vec<Vec<u8>>,
+}
+
+/// Builder for the preimage for taproot CheckSigVerify, where each field in BIP-341 can be a
+/// constant, a hint, or an element on the stack.
+///
+/// The epoch, the hash type, the spend type (without annex), and the key version are always
+/// constants, since the layout of the preimage depends on them. By default, the preimage is for
+/// the script path; [`TapCSVPreImageBuilder::new_key_path`] creates one for the key path, which
+/// has no tapscript extension. The other fields can be set with
+/// the struct update syntax on top of [`TapCSVPreImageBuilder::new`].
+#[derive(Clone, Debug)]
+pub struct TapCSVPreImageBuilder {
+    /// The hash type, which determines the layout of the preimage.
+    pub hash_type: TapSighashType,
+    /// Whether the preimage is for the key path (with the extension flag being 0), in which case
+    /// the tap leaf hash and the code separator position are absent.
+    pub key_path: bool,
+    /// The transaction version.
+    pub version: FieldSource<Version>,
+    /// The locktime.
+    pub lock_time: FieldSource<LockTime>,
+    /// The hash of all the outpoints (only if not anyonecanpay).
+    pub sha_prevouts: FieldSource<[u8; 32]>,
+    /// The hash of all the input amounts (only if not anyonecanpay).
+    pub sha_amounts: FieldSource<[u8; 32]>,
+    /// The hash of all the input script pub keys (only if not anyonecanpay).
+    pub sha_script_pub_keys: FieldSource<[u8; 32]>,
+    /// The hash of all the input sequences (only if not anyonecanpay).
+    pub sha_sequences: FieldSource<[u8; 32]>,
+    /// The hash of all the outputs (only if not none or single).
+    pub sha_outputs: FieldSource<[u8; 32]>,
+    /// This input's outpoint (only if anyonecanpay).
+    pub outpoint: FieldSource<OutPoint>,
+    /// This input's amount (only if anyonecanpay).
+    pub amount: FieldSource<Amount>,
+    /// This input's script pub key (only if anyonecanpay).
+    ///
+    /// If provided, it can be any script with at most 517 bytes.
+    pub script_pub_key: FieldSource<ScriptBuf>,
+    /// This input's sequence (only if anyonecanpay).
+    pub sequence: FieldSource<Sequence>,
+    /// This input's index (only if not anyonecanpay).
+    pub input_index: FieldSource<u32>,
+    /// The hash of the output with the same index as this input (only if single).
+    pub sha_single_output: FieldSource<[u8; 32]>,
+    /// The tap leaf hash (only if not key path).
+    pub tap_leaf_hash: FieldSource<TapLeafHash>,
+    /// The code separator position, 0xffffffff if no OP_CODESEPARATOR has been executed (only if
+    /// not key path).
+    pub code_sep_pos: FieldSource<u32>,
+}
+
+/// The fields after the epoch and the hash type, in the order of the preimage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Version,
+    LockTime,
+    ShaPrevouts,
+    ShaAmounts,
+    ShaScriptPubKeys,
+    ShaSequences,
+    ShaOutputs,
+    SpendType,
+    OutPoint,
+    Amount,
+    ScriptPubKey,
+    Sequence,
+    InputIndex,
+    ShaSingleOutput,
+    TapLeafHash,
+    KeyVersion,
+    CodeSepPos,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SourceKind {
+    Constant,
+    Hint,
+    FromStack,
+}
+
+impl<T> FieldSource<T> {
+    pub(crate) fn kind(&self) -> SourceKind {
+        match self {
+            FieldSource::Constant(_) => SourceKind::Constant,
+            FieldSource::Hint => SourceKind::Hint,
+            FieldSource::FromStack => SourceKind::FromStack,
+        }
+    }
+
+    fn constant(&self) -> &T {
+        match self {
+            FieldSource::Constant(v) => v,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl TapCSVPreImageBuilder {
+    /// Create a builder for the given hash type, where all the fields are hints by default.
+    pub fn new(hash_type: TapSighashType) -> Self {
+        Self {
+            hash_type,
+            key_path: false,
+            version: FieldSource::Hint,
+            lock_time: FieldSource::Hint,
+            sha_prevouts: FieldSource::Hint,
+            sha_amounts: FieldSource::Hint,
+            sha_script_pub_keys: FieldSource::Hint,
+            sha_sequences: FieldSource::Hint,
+            sha_outputs: FieldSource::Hint,
+            outpoint: FieldSource::Hint,
+            amount: FieldSource::Hint,
+            script_pub_key: FieldSource::Hint,
+            sequence: FieldSource::Hint,
+            input_index: FieldSource::Hint,
+            sha_single_output: FieldSource::Hint,
+            tap_leaf_hash: FieldSource::Hint,
+            code_sep_pos: FieldSource::Hint,
+        }
+    }
+
+    /// Create a builder for the key path under the given hash type, where all the fields are hints
+    /// by default.
+    pub fn new_key_path(hash_type: TapSighashType) -> Self {
+        Self {
+            key_path: true,
+            ..Self::new(hash_type)
+        }
+    }
+
+    fn is_anyonecanpay(&self) -> bool {
+        [
+            TapSighashType::AllPlusAnyoneCanPay,
+            TapSighashType::NonePlusAnyoneCanPay,
+            TapSighashType::SinglePlusAnyoneCanPay,
+        ]
+        .contains(&self.hash_type)
+    }
+
+    fn is_all(&self) -> bool {
+        [
+            TapSighashType::All,
+            TapSighashType::Default,
+            TapSighashType::AllPlusAnyoneCanPay,
+        ]
+        .contains(&self.hash_type)
+    }
+
+    fn is_single(&self) -> bool {
+        [
+            TapSighashType::Single,
+            TapSighashType::SinglePlusAnyoneCanPay,
+        ]
+        .contains(&self.hash_type)
+    }
+
+    /// The fields present in the preimage under this hash type, in order.
+    fn fields(&self) -> Vec<Field> {
+        let mut fields = vec![Field::Version, Field::LockTime];
+        if !self.is_anyonecanpay() {
+            fields.extend_from_slice(&[
+                Field::ShaPrevouts,
+                Field::ShaAmounts,
+                Field::ShaScriptPubKeys,
+                Field::ShaSequences,
+            ]);
+        }
+        if self.is_all() {
+            fields.push(Field::ShaOutputs);
+        }
+        fields.push(Field::SpendType);
+        if self.is_anyonecanpay() {
+            fields.extend_from_slice(&[
+                Field::OutPoint,
+                Field::Amount,
+                Field::ScriptPubKey,
+                Field::Sequence,
+            ]);
+        } else {
+            fields.push(Field::InputIndex);
+        }
+        if self.is_single() {
+            fields.push(Field::ShaSingleOutput);
+        }
+        if !self.key_path {
+            fields.extend_from_slice(&[Field::TapLeafHash, Field::KeyVersion, Field::CodeSepPos]);
+        }
+        fields
+    }
+
+    fn kind(&self, field: Field) -> SourceKind {
+        match field {
+            Field::Version => self.version.kind(),
+            Field::LockTime => self.lock_time.kind(),
+            Field::ShaPrevouts => self.sha_prevouts.kind(),
+            Field::ShaAmounts => self.sha_amounts.kind(),
+            Field::ShaScriptPubKeys => self.sha_script_pub_keys.kind(),
+            Field::ShaSequences => self.sha_sequences.kind(),
+            Field::ShaOutputs => self.sha_outputs.kind(),
+            Field::SpendType | Field::KeyVersion => SourceKind::Constant,
+            Field::OutPoint => self.outpoint.kind(),
+            Field::Amount => self.amount.kind(),
+            Field::ScriptPubKey => self.script_pub_key.kind(),
+            Field::Sequence => self.sequence.kind(),
+            Field::InputIndex => self.input_index.kind(),
+            Field::ShaSingleOutput => self.sha_single_output.kind(),
+            Field::TapLeafHash => self.tap_leaf_hash.kind(),
+            Field::CodeSepPos => self.code_sep_pos.kind(),
+        }
+    }
+
+    /// Construct the serialized field from the constant.
+    fn constant_script(&self, field: Field) -> Script {
+        match field {
+            Field::Version => Step3VersionGadget::from_constant(self.version.constant()),
+            Field::LockTime => {
+                Step4LockTimeGadget::from_constant_absolute(self.lock_time.constant())
+            }
+            Field::ShaPrevouts => script! { { self.sha_prevouts.constant().to_vec() } },
+            Field::ShaAmounts => script! { { self.sha_amounts.constant().to_vec() } },
+            Field::ShaScriptPubKeys => script! { { self.sha_script_pub_keys.constant().to_vec() } },
+            Field::ShaSequences => script! { { self.sha_sequences.constant().to_vec() } },
+            Field::ShaOutputs => script! { { self.sha_outputs.constant().to_vec() } },
+            Field::SpendType => Step7SpendTypeGadget::from_constant(!self.key_path as u8, false),
+            Field::OutPoint => {
+                step8_data_input_part_if_anyonecanpay::Step1OutPointGadget::from_constant(
+                    self.outpoint.constant(),
+                )
+            }
+            Field::Amount => {
+                step8_data_input_part_if_anyonecanpay::Step2AmountGadget::from_constant(
+                    self.amount.constant(),
+                )
+            }
+            Field::ScriptPubKey => {
+                step8_data_input_part_if_anyonecanpay::Step3ScriptPubKeyGadget::from_constant(
+                    self.script_pub_key.constant(),
+                )
+            }
+            Field::Sequence => {
+                step8_data_input_part_if_anyonecanpay::Step4SequenceGadget::from_constant(
+                    self.sequence.constant(),
+                )
+            }
+            Field::InputIndex => {
+                Step9InputIndexGadgetIfNotAnyOneCanPay::from_constant(*self.input_index.constant())
+            }
+            Field::ShaSingleOutput => script! { { self.sha_single_output.constant().to_vec() } },
+            Field::TapLeafHash => {
+                step12_ext::Step1TapLeafHashGadget::from_constant(self.tap_leaf_hash.constant())
+            }
+            Field::KeyVersion => step12_ext::Step2KeyVersionGadget::from_constant(0),
+            Field::CodeSepPos => {
+                step12_ext::Step3CodeSepPosGadget::from_constant(*self.code_sep_pos.constant())
+            }
+        }
+    }
+
+    /// Check the size of a provided field and serialize it if needed.
+    fn provided_script(field: Field) -> Script {
+        match field {
+            Field::Version => script! { OP_SIZE 4 OP_EQUALVERIFY },
+            Field::LockTime => Step4LockTimeGadget::from_provided(),
+            Field::ShaPrevouts
+            | Field::ShaAmounts
+            | Field::ShaScriptPubKeys
+            | Field::ShaSequences
+            | Field::ShaOutputs
+            | Field::ShaSingleOutput
+            | Field::TapLeafHash => script! { OP_SIZE 32 OP_EQUALVERIFY },
+            Field::OutPoint => {
+                step8_data_input_part_if_anyonecanpay::Step1OutPointGadget::from_provided()
+            }
+            Field::Amount => {
+                step8_data_input_part_if_anyonecanpay::Step2AmountGadget::from_provided()
+            }
+            Field::ScriptPubKey => ScriptPubKeyGadget::from_provided_arbitrary(),
+            Field::Sequence => {
+                step8_data_input_part_if_anyonecanpay::Step4SequenceGadget::from_provided()
+            }
+            Field::InputIndex | Field::CodeSepPos => {
+                Step9InputIndexGadgetIfNotAnyOneCanPay::from_provided()
+            }
+            Field::SpendType | Field::KeyVersion => unreachable!(),
+        }
+    }
+
+    /// Construct the script for a field, where `extra_depth` counts the elements between the
+    /// stack elements for the fields and the partial preimage.
+    fn field_script(&self, field: Field, remaining: &mut usize, extra_depth: usize) -> Script {
+        match self.kind(field) {
+            SourceKind::Constant => self.constant_script(field),
+            SourceKind::Hint => script! {
+                OP_HINT
+                { Self::provided_script(field) }
+            },
+            SourceKind::FromStack => {
+                // Skip the remaining elements for later fields as well as the partial preimage.
+                let depth = *remaining + extra_depth;
+                *remaining -= 1;
+                script! {
+                    { depth } OP_ROLL
+                    { Self::provided_script(field) }
+                }
+            }
+        }
+    }
+
+    /// Construct the script that computes the preimage.
+    ///
+    /// Input:
+    /// - the elements of the fields with [`FieldSource::FromStack`], in order
+    ///
+    /// Output:
+    /// - the preimage
+    ///
+    /// The hints are obtained through `OP_HINT`.
+    pub fn to_script(&self) -> Script {
+        let fields = self.fields();
+        let mut remaining = fields
+            .iter()
+            .filter(|field| self.kind(**field) == SourceKind::FromStack)
+            .count();
+
+        let mut field_scripts = vec![];
+        for field in fields.iter() {
+            field_scripts.push(self.field_script(*field, &mut remaining, 0));
+        }
+
+        script! {
+            { Step1EpochGadget::default() }
+            { Step2HashTypeGadget::from_constant(&self.hash_type) }
+            OP_CAT2
+            for field_script in field_scripts.into_iter() {
+                { field_script }
+                OP_CAT2
+            }
+        }
+    }
+
+    /// Construct the script that computes the preimage, where the hash type is provided as the
+    /// first hint, and the layout of the preimage is selected in the script.
+    ///
+    /// The `hash_type` of the builder is ignored. Only the version, the locktime, the tap leaf
+    /// hash, and the code separator position, which are present under all the hash types, can be
+    /// taken from the stack.
+    ///
+    /// Input:
+    /// - the elements of the fields with [`FieldSource::FromStack`], in order
+    ///
+    /// Output:
+    /// - the preimage
+    ///
+    /// The hints are obtained through `OP_HINT`.
+    pub fn to_script_with_hinted_hash_type(&self) -> Script {
+        let mut remaining = 0;
+        for field in [
+            Field::ShaPrevouts,
+            Field::ShaAmounts,
+            Field::ShaScriptPubKeys,
+            Field::ShaSequences,
+            Field::ShaOutputs,
+            Field::OutPoint,
+            Field::Amount,
+            Field::ScriptPubKey,
+            Field::Sequence,
+            Field::InputIndex,
+            Field::ShaSingleOutput,
+        ] {
+            assert_ne!(
+                self.kind(field),
+                SourceKind::FromStack,
+                "fields that depend on the hash type cannot be taken from the stack"
+            );
+        }
+        for field in [
+            Field::Version,
+            Field::LockTime,
+            Field::TapLeafHash,
+            Field::CodeSepPos,
+        ] {
+            if self.fields().contains(&field) && self.kind(field) == SourceKind::FromStack {
+                remaining += 1;
+            }
+        }
+
+        // The base type and the anyonecanpay flag sit below the partial preimage.
+        let mut field_script = |field: Field| {
+            script! {
+                { self.field_script(field, &mut remaining, 2) }
+                OP_CAT2
+            }
+        };
+
+        let version = field_script(Field::Version);
+        let lock_time = field_script(Field::LockTime);
+        let sha_prevouts = field_script(Field::ShaPrevouts);
+        let sha_amounts = field_script(Field::ShaAmounts);
+        let sha_script_pub_keys = field_script(Field::ShaScriptPubKeys);
+        let sha_sequences = field_script(Field::ShaSequences);
+        let sha_outputs = field_script(Field::ShaOutputs);
+        let spend_type = field_script(Field::SpendType);
+        let outpoint = field_script(Field::OutPoint);
+        let amount = field_script(Field::Amount);
+        let script_pub_key = field_script(Field::ScriptPubKey);
+        let sequence = field_script(Field::Sequence);
+        let input_index = field_script(Field::InputIndex);
+        let sha_single_output = field_script(Field::ShaSingleOutput);
+        let ext = if self.key_path {
+            script! {}
+        } else {
+            script! {
+                { field_script(Field::TapLeafHash) }
+                { field_script(Field::KeyVersion) }
+                { field_script(Field::CodeSepPos) }
+            }
+        };
+
+        script! {
+            OP_HINT
+            { Step2HashTypeGadget::from_provided_with_flags() }
+            OP_ROT
+
+            { Step1EpochGadget::default() }
+            OP_SWAP OP_CAT2
+
+            { version }
+            { lock_time }
+
+            OP_OVER OP_NOTIF
+                { sha_prevouts }
+                { sha_amounts }
+                { sha_script_pub_keys }
+                { sha_sequences }
+            OP_ENDIF
+
+            2 OP_PICK 1 OP_EQUAL OP_IF
+                { sha_outputs }
+            OP_ENDIF
+
+            { spend_type }
+
+            OP_OVER OP_IF
+                { outpoint }
+                { amount }
+                { script_pub_key }
+                { sequence }
+            OP_ELSE
+                { input_index }
+            OP_ENDIF
+
+            2 OP_PICK 3 OP_EQUAL OP_IF
+                { sha_single_output }
+            OP_ENDIF
+
+            { ext }
+
+            // Drop the base type and the anyonecanpay flag.
+            OP_NIP OP_NIP
+        }
+    }
+
+    /// Generate the hints and the stack elements for the script from
+    /// [`TapCSVPreImageBuilder::to_script_with_hinted_hash_type`], from the transaction being
+    /// signed under the given hash type.
+    pub fn witness_with_hinted_hash_type(
+        &self,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+        input_idx: usize,
+        tap_leaf_hash: &TapLeafHash,
+        code_sep_pos: Option<u32>,
+        hash_type: TapSighashType,
+    ) -> FieldWitness {
+        let builder = TapCSVPreImageBuilder {
+            hash_type,
+            ..self.clone()
+        };
+        let mut witness = builder.witness(tx, prevouts, input_idx, tap_leaf_hash, code_sep_pos);
+        witness.hints.insert(0, vec![hash_type as u8]);
+        witness
+    }
+
+    /// Generate the hints and the stack elements for the script of a key-path builder, from the
+    /// transaction being signed.
+    pub fn witness_key_path(
+        &self,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+        input_idx: usize,
+    ) -> FieldWitness {
+        assert!(self.key_path);
+        self.witness(tx, prevouts, input_idx, &TapLeafHash::all_zeros(), None)
+    }
+
+    /// Generate the hints and the stack elements for the script, from the transaction being signed.
+    ///
+    /// The tap leaf hash and the code separator position are ignored for a key-path builder.
+    pub fn witness(
+        &self,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+        input_idx: usize,
+        tap_leaf_hash: &TapLeafHash,
+        code_sep_pos: Option<u32>,
+    ) -> FieldWitness {
+        assert_eq!(tx.input.len(), prevouts.len());
+
+        let serialize_all = |f: &dyn Fn(&mut Vec<u8>, usize)| {
+            let mut bytes = vec![];
+            for i in 0..tx.input.len() {
+                f(&mut bytes, i);
+            }
+            sha256::Hash::hash(&bytes).to_byte_array().to_vec()
+        };
+
+        let mut witness = FieldWitness::default();
+        for field in self.fields() {
+            let element = match field {
+                Field::Version => tx.version.0.to_le_bytes().to_vec(),
+                Field::LockTime => tx.lock_time.to_consensus_u32().to_le_bytes().to_vec(),
+                Field::ShaPrevouts => serialize_all(&|bytes, i| {
+                    tx.input[i].previous_output.consensus_encode(bytes).unwrap();
+                }),
+                Field::ShaAmounts => serialize_all(&|bytes, i| {
+                    prevouts[i].value.consensus_encode(bytes).unwrap();
+                }),
+                Field::ShaScriptPubKeys => serialize_all(&|bytes, i| {
+                    prevouts[i].script_pubkey.consensus_encode(bytes).unwrap();
+                }),
+                Field::ShaSequences => serialize_all(&|bytes, i| {
+                    tx.input[i].sequence.consensus_encode(bytes).unwrap();
+                }),
+                Field::ShaOutputs => {
+                    let mut bytes = vec![];
+                    for output in tx.output.iter() {
+                        output.consensus_encode(&mut bytes).unwrap();
+                    }
+                    sha256::Hash::hash(&bytes).to_byte_array().to_vec()
+                }
+                Field::OutPoint => {
+                    let mut bytes = vec![];
+                    tx.input[input_idx]
+                        .previous_output
+                        .consensus_encode(&mut bytes)
+                        .unwrap();
+                    bytes
+                }
+                Field::Amount => prevouts[input_idx].value.to_sat().to_le_bytes().to_vec(),
+                Field::ScriptPubKey => prevouts[input_idx].script_pubkey.to_bytes(),
+                Field::Sequence => tx.input[input_idx]
+                    .sequence
+                    .to_consensus_u32()
+                    .to_le_bytes()
+                    .to_vec(),
+                Field::InputIndex => (input_idx as u32).to_le_bytes().to_vec(),
+                Field::ShaSingleOutput => {
+                    let mut bytes = vec![];
+                    tx.output[input_idx].consensus_encode(&mut bytes).unwrap();
+                    sha256::Hash::hash(&bytes).to_byte_array().to_vec()
+                }
+                Field::TapLeafHash => AsRef::<[u8]>::as_ref(tap_leaf_hash).to_vec(),
+                Field::SpendType | Field::KeyVersion => continue,
+                Field::CodeSepPos => code_sep_pos.unwrap_or(0xffffffffu32).to_le_bytes().to_vec(),
+            };
+
+            match self.kind(field) {
+                SourceKind::Constant => {}
+                SourceKind::Hint => witness.hints.push(element),
+                SourceKind::FromStack => witness.stack.push(element),
+            }
+        }
+        witness
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::treepp::*;
+    use crate::utils::test_utils::{get_rand_script_pub_key, get_rand_source, push_hint};
+    use crate::wizards::tap_csv_preimage_builder::TapCSVPreImageBuilder;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::consensus::Encodable;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::sighash::{Prevouts, SighashCache};
+    use bitcoin::transaction::Version;
+    use bitcoin::{
+        Amount, OutPoint, ScriptBuf, Sequence, TapLeafHash, TapSighashType, Transaction, TxIn,
+        TxOut, Txid, Witness,
+    };
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    const HASH_TYPES: [TapSighashType; 7] = [
+        TapSighashType::Default,
+        TapSighashType::All,
+        TapSighashType::None,
+        TapSighashType::Single,
+        TapSighashType::AllPlusAnyoneCanPay,
+        TapSighashType::NonePlusAnyoneCanPay,
+        TapSighashType::SinglePlusAnyoneCanPay,
+    ];
+
+    struct TestCase {
+        tx: Transaction,
+        prevouts: Vec<TxOut>,
+        input_idx: usize,
+        tap_leaf_hash: TapLeafHash,
+        code_sep_pos: Option<u32>,
+    }
+
+    impl TestCase {
+        fn new(prng: &mut ChaCha20Rng) -> Self {
+            let num_inputs = prng.gen_range(1..4);
+            let num_outputs = prng.gen_range(num_inputs..5);
+
+            let mut tx = Transaction {
+                version: Version(prng.gen_range(1..4)),
+                lock_time: LockTime::from_consensus(prng.gen()),
+                input: vec![],
+                output: vec![],
+            };
+            let mut prevouts = vec![];
+            for _ in 0..num_inputs {
+                let mut bytes = [0u8; 20];
+                prng.fill_bytes(&mut bytes);
+                tx.input.push(TxIn {
+                    previous_output: OutPoint::new(Txid::hash(&bytes), prng.gen()),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence(prng.gen()),
+                    witness: Witness::new(),
+                });
+                prevouts.push(TxOut {
+                    value: Amount::from_sat(prng.gen_range(0..2_100_000_000_000_000)),
+                    script_pubkey: get_rand_script_pub_key(prng),
+                });
+            }
+            for _ in 0..num_outputs {
+                tx.output.push(TxOut {
+                    value: Amount::from_sat(prng.gen_range(0..2_100_000_000_000_000)),
+                    script_pubkey: get_rand_script_pub_key(prng),
+                });
+            }
+
+            let input_idx = prng.gen_range(0..num_inputs);
+            let tap_leaf_hash = {
+                let mut bytes = [0u8; 32];
+                prng.fill_bytes(&mut bytes);
+                TapLeafHash::hash(&bytes)
+            };
+            let code_sep_pos = if prng.gen::<bool>() {
+                Some(prng.gen_range(0..0x7fffffffu32))
+            } else {
+                None
+            };
+
+            Self {
+                tx,
+                prevouts,
+                input_idx,
+                tap_leaf_hash,
+                code_sep_pos,
+            }
+        }
+
+        /// Assign random sources to the fields. If `hash_type_dependent_from_stack` is false, the
+        /// fields that depend on the hash type are never taken from the stack.
+        fn get_rand_builder(
+            &self,
+            prng: &mut ChaCha20Rng,
+            hash_type: TapSighashType,
+            hash_type_dependent_from_stack: bool,
+        ) -> TapCSVPreImageBuilder {
+            let tx = &self.tx;
+            let prevouts = &self.prevouts;
+            let input_idx = self.input_idx;
+
+            TapCSVPreImageBuilder {
+                version: get_rand_source(prng, tx.version, true),
+                lock_time: get_rand_source(prng, tx.lock_time, true),
+                sha_prevouts: get_rand_source(
+                    prng,
+                    sha256_of(|bytes| {
+                        for input in tx.input.iter() {
+                            input.previous_output.consensus_encode(bytes).unwrap();
+                        }
+                    }),
+                    hash_type_dependent_from_stack,
+                ),
+                sha_amounts: get_rand_source(
+                    prng,
+                    sha256_of(|bytes| {
+                        for prevout in prevouts.iter() {
+                            prevout.value.consensus_encode(bytes).unwrap();
+                        }
+                    }),
+                    hash_type_dependent_from_stack,
+                ),
+                sha_script_pub_keys: get_rand_source(
+                    prng,
+                    sha256_of(|bytes| {
+                        for prevout in prevouts.iter() {
+                            prevout.script_pubkey.consensus_encode(bytes).unwrap();
+                        }
+                    }),
+                    hash_type_dependent_from_stack,
+                ),
+                sha_sequences: get_rand_source(
+                    prng,
+                    sha256_of(|bytes| {
+                        for input in tx.input.iter() {
+                            input.sequence.consensus_encode(bytes).unwrap();
+                        }
+                    }),
+                    hash_type_dependent_from_stack,
+                ),
+                sha_outputs: get_rand_source(
+                    prng,
+                    sha256_of(|bytes| {
+                        for output in tx.output.iter() {
+                            output.consensus_encode(bytes).unwrap();
+                        }
+                    }),
+                    hash_type_dependent_from_stack,
+                ),
+                outpoint: get_rand_source(
+                    prng,
+                    tx.input[input_idx].previous_output,
+                    hash_type_dependent_from_stack,
+                ),
+                amount: get_rand_source(
+                    prng,
+                    prevouts[input_idx].value,
+                    hash_type_dependent_from_stack,
+                ),
+                script_pub_key: get_rand_source(
+                    prng,
+                    prevouts[input_idx].script_pubkey.clone(),
+                    hash_type_dependent_from_stack,
+                ),
+                sequence: get_rand_source(
+                    prng,
+                    tx.input[input_idx].sequence,
+                    hash_type_dependent_from_stack,
+                ),
+                input_index: get_rand_source(
+                    prng,
+                    input_idx as u32,
+                    hash_type_dependent_from_stack,
+                ),
+                sha_single_output: get_rand_source(
+                    prng,
+                    sha256_of(|bytes| {
+                        tx.output[input_idx].consensus_encode(bytes).unwrap();
+                    }),
+                    hash_type_dependent_from_stack,
+                ),
+                tap_leaf_hash: get_rand_source(prng, self.tap_leaf_hash, true),
+                code_sep_pos: get_rand_source(
+                    prng,
+                    self.code_sep_pos.unwrap_or(0xffffffffu32),
+                    true,
+                ),
+                ..TapCSVPreImageBuilder::new(hash_type)
+            }
+        }
+
+        fn get_expected(&self, hash_type: TapSighashType) -> Vec<u8> {
+            let mut bytes = vec![];
+            SighashCache::new(self.tx.clone())
+                .taproot_encode_signing_data_to(
+                    &mut bytes,
+                    self.input_idx,
+                    &Prevouts::All(&self.prevouts),
+                    None,
+                    Some((
+                        self.tap_leaf_hash,
+                        self.code_sep_pos.unwrap_or(0xffffffffu32),
+                    )),
+                    hash_type,
+                )
+                .unwrap();
+            bytes
+        }
+
+        fn get_expected_key_path(&self, hash_type: TapSighashType) -> Vec<u8> {
+            let mut bytes = vec![];
+            SighashCache::new(self.tx.clone())
+                .taproot_encode_signing_data_to(
+                    &mut bytes,
+                    self.input_idx,
+                    &Prevouts::All(&self.prevouts),
+                    None,
+                    None,
+                    hash_type,
+                )
+                .unwrap();
+            bytes
+        }
+    }
+
+    fn sha256_of(f: impl Fn(&mut Vec<u8>)) -> [u8; 32] {
+        let mut bytes = vec![];
+        f(&mut bytes);
+        sha256::Hash::hash(&bytes).to_byte_array()
+    }
+
+    #[test]
+    fn test_tap_csv_preimage_builder() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..20 {
+            let test_case = TestCase::new(&mut prng);
+
+            for hash_type in HASH_TYPES {
+                let builder = test_case.get_rand_builder(&mut prng, hash_type, true);
+
+                let witness = builder.witness(
+                    &test_case.tx,
+                    &test_case.prevouts,
+                    test_case.input_idx,
+                    &test_case.tap_leaf_hash,
+                    test_case.code_sep_pos,
+                );
+
+                let script = script! {
+                    for hint in witness.hints.iter() {
+                        { hint.clone() }
+                    }
+                    for element in witness.stack.iter() {
+                        { element.clone() }
+                    }
+                    { builder.to_script() }
+                    { test_case.get_expected(hash_type) }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tap_csv_preimage_builder_with_hinted_hash_type() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..10 {
+            let test_case = TestCase::new(&mut prng);
+
+            // The same script accepts all the hash types.
+            let builder = test_case.get_rand_builder(&mut prng, TapSighashType::Default, false);
+            let preimage_script = builder.to_script_with_hinted_hash_type();
+
+            for hash_type in HASH_TYPES {
+                let witness = builder.witness_with_hinted_hash_type(
+                    &test_case.tx,
+                    &test_case.prevouts,
+                    test_case.input_idx,
+                    &test_case.tap_leaf_hash,
+                    test_case.code_sep_pos,
+                    hash_type,
+                );
+
+                let script = script! {
+                    for hint in witness.hints.iter() {
+                        { push_hint(hint) }
+                    }
+                    for element in witness.stack.iter() {
+                        { element.clone() }
+                    }
+                    { preimage_script.clone() }
+                    { test_case.get_expected(hash_type) }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tap_csv_preimage_builder_key_path() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..10 {
+            let test_case = TestCase::new(&mut prng);
+
+            for hash_type in HASH_TYPES {
+                let builder = TapCSVPreImageBuilder {
+                    key_path: true,
+                    ..test_case.get_rand_builder(&mut prng, hash_type, true)
+                };
+
+                let witness = builder.witness_key_path(
+                    &test_case.tx,
+                    &test_case.prevouts,
+                    test_case.input_idx,
+                );
+
+                let script = script! {
+                    for hint in witness.hints.iter() {
+                        { hint.clone() }
+                    }
+                    for element in witness.stack.iter() {
+                        { element.clone() }
+                    }
+                    { builder.to_script() }
+                    { test_case.get_expected_key_path(hash_type) }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+
+            let builder = TapCSVPreImageBuilder {
+                key_path: true,
+                ..test_case.get_rand_builder(&mut prng, TapSighashType::Default, false)
+            };
+            let preimage_script = builder.to_script_with_hinted_hash_type();
+
+            for hash_type in HASH_TYPES {
+                let witness = builder.witness_with_hinted_hash_type(
+                    &test_case.tx,
+                    &test_case.prevouts,
+                    test_case.input_idx,
+                    &test_case.tap_leaf_hash,
+                    test_case.code_sep_pos,
+                    hash_type,
+                );
+
+                let script = script! {
+                    for hint in witness.hints.iter() {
+                        { push_hint(hint) }
+                    }
+                    for element in witness.stack.iter() {
+                        { element.clone() }
+                    }
+                    { preimage_script.clone() }
+                    { test_case.get_expected_key_path(hash_type) }
+                    OP_EQUAL
+                };
+
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+    }
+}
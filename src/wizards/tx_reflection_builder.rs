@@ -0,0 +1,571 @@
+use crate::structures::script_pub_key::ScriptPubKeyGadget;
+use crate::treepp::*;
+use crate::utils::pseudo::{OP_CAT2, OP_HINT};
+use crate::utils::sha256::{Sha256StreamBuilder, Sha256StreamWitness};
+use crate::wizards::tap_csv_preimage_builder::{FieldSource, FieldWitness, SourceKind};
+use crate::wizards::tx::{
+    step3_input, step5_output, Step1VersionGadget, Step2InCounterGadget, Step4OutCounterGadget,
+    Step6LockTimeGadget,
+};
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::Encodable;
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, VarInt};
+
+/// The sources of the fields of a transaction input.
+#[derive(Clone, Debug, Default)]
+pub struct TxInReflection {
+    /// The outpoint that the input spends.
+    pub outpoint: FieldSource<OutPoint>,
+    /// The sequence number.
+    pub sequence: FieldSource<Sequence>,
+}
+
+/// The sources of the fields of a transaction output.
+#[derive(Clone, Debug, Default)]
+pub struct TxOutReflection {
+    /// The amount.
+    pub amount: FieldSource<Amount>,
+    /// The script pub key.
+    ///
+    /// If provided, it can be any script with at most 517 bytes.
+    pub script_pub_key: FieldSource<ScriptBuf>,
+    /// The length of the script pub key if it is provided, which is needed for hashing
+    /// transaction data that may exceed 520 bytes (see [`TxReflectionBuilder::to_txid_script`]).
+    pub script_pub_key_len: Option<usize>,
+}
+
+/// Builder for reflecting a previous transaction, i.e., reconstructing the preimage of its txid,
+/// where each field can be a constant, a hint, or an element on the stack.
+///
+/// The numbers of inputs and outputs are fixed, and the inputs must be segwit inputs (with an
+/// empty script sig).
+#[derive(Clone, Debug)]
+pub struct TxReflectionBuilder {
+    /// The transaction version.
+    pub version: FieldSource<Version>,
+    /// The inputs.
+    pub inputs: Vec<TxInReflection>,
+    /// The outputs.
+    pub outputs: Vec<TxOutReflection>,
+    /// The locktime.
+    pub lock_time: FieldSource<LockTime>,
+}
+
+impl TxReflectionBuilder {
+    /// Create a builder with the given numbers of inputs and outputs, where all the fields are
+    /// hints by default.
+    pub fn new(num_inputs: usize, num_outputs: usize) -> Self {
+        Self {
+            version: FieldSource::Hint,
+            inputs: vec![TxInReflection::default(); num_inputs],
+            outputs: vec![TxOutReflection::default(); num_outputs],
+            lock_time: FieldSource::Hint,
+        }
+    }
+
+    fn num_from_stack(&self) -> usize {
+        let mut count = matches!(self.version, FieldSource::FromStack) as usize
+            + matches!(self.lock_time, FieldSource::FromStack) as usize;
+        for input in self.inputs.iter() {
+            count += matches!(input.outpoint, FieldSource::FromStack) as usize;
+            count += matches!(input.sequence, FieldSource::FromStack) as usize;
+        }
+        for output in self.outputs.iter() {
+            count += matches!(output.amount, FieldSource::FromStack) as usize;
+            count += matches!(output.script_pub_key, FieldSource::FromStack) as usize;
+        }
+        count
+    }
+
+    /// Construct the scripts of the fields, each with the length of the serialized field if it
+    /// is known, where `above` is the number of elements that sit above the elements of the
+    /// fields with [`FieldSource::FromStack`].
+    fn field_scripts(&self, above: usize) -> Vec<(Script, Option<usize>)> {
+        let mut remaining = self.num_from_stack();
+
+        let mut field_scripts = vec![];
+        field_scripts.push((
+            field_script(
+                &self.version,
+                Step1VersionGadget::from_constant,
+                script! { OP_SIZE 4 OP_EQUALVERIFY },
+                &mut remaining,
+                above,
+            ),
+            Some(4),
+        ));
+        field_scripts.push((
+            Step2InCounterGadget::from_constant(self.inputs.len()),
+            Some(VarInt::from(self.inputs.len()).size()),
+        ));
+        for input in self.inputs.iter() {
+            field_scripts.push((
+                field_script(
+                    &input.outpoint,
+                    step3_input::Step1OutPointGadget::from_constant,
+                    step3_input::Step1OutPointGadget::from_provided(),
+                    &mut remaining,
+                    above,
+                ),
+                Some(36),
+            ));
+            field_scripts.push((
+                step3_input::Step2ScriptSigGadget::segregated_witness(),
+                Some(1),
+            ));
+            field_scripts.push((
+                field_script(
+                    &input.sequence,
+                    step3_input::Step3SequenceGadget::from_constant,
+                    step3_input::Step3SequenceGadget::from_provided(),
+                    &mut remaining,
+                    above,
+                ),
+                Some(4),
+            ));
+        }
+        field_scripts.push((
+            Step4OutCounterGadget::from_constant(self.outputs.len()),
+            Some(VarInt::from(self.outputs.len()).size()),
+        ));
+        for output in self.outputs.iter() {
+            field_scripts.push((
+                field_script(
+                    &output.amount,
+                    step5_output::Step1AmountGadget::from_constant,
+                    step5_output::Step1AmountGadget::from_provided(),
+                    &mut remaining,
+                    above,
+                ),
+                Some(8),
+            ));
+
+            let script_pub_key_len = match &output.script_pub_key {
+                FieldSource::Constant(script_pub_key) => Some(script_pub_key.len()),
+                _ => output.script_pub_key_len,
+            };
+            field_scripts.push((
+                field_script(
+                    &output.script_pub_key,
+                    step5_output::Step2ScriptPubKeyGadget::from_constant,
+                    ScriptPubKeyGadget::from_provided_arbitrary(),
+                    &mut remaining,
+                    above,
+                ),
+                script_pub_key_len.map(|len| VarInt::from(len).size() + len),
+            ));
+        }
+        field_scripts.push((
+            field_script(
+                &self.lock_time,
+                Step6LockTimeGadget::from_constant_absolute,
+                Step6LockTimeGadget::from_provided(),
+                &mut remaining,
+                above,
+            ),
+            Some(4),
+        ));
+        field_scripts
+    }
+
+    /// Construct the script that computes the transaction data for the txid.
+    ///
+    /// Input:
+    /// - the elements of the fields with [`FieldSource::FromStack`], in order
+    ///
+    /// Output:
+    /// - the transaction data (whose double SHA256 hash is the txid)
+    ///
+    /// The hints are obtained through `OP_HINT`.
+    pub fn to_script(&self) -> Script {
+        script! {
+            // Initialize an empty string to be appended below.
+            OP_PUSHBYTES_0
+            for (field_script, _) in self.field_scripts(1).into_iter() {
+                { field_script }
+                OP_CAT2
+            }
+        }
+    }
+
+    /// The length of the transaction data, if the lengths of all the script pub keys are known.
+    pub fn data_len(&self) -> Option<usize> {
+        self.field_scripts(1).iter().map(|(_, len)| *len).sum()
+    }
+
+    /// Whether the txid needs to be computed with [`Sha256StreamBuilder`], i.e., the transaction
+    /// data is known to exceed 520 bytes.
+    ///
+    /// If the length of a provided script pub key is unknown, the single `OP_SHA256` is used, and
+    /// the script fails if the transaction data exceeds 520 bytes, since `OP_CAT` cannot produce
+    /// a longer element.
+    fn needs_stream(&self) -> bool {
+        self.data_len().is_some_and(|len| len > 520)
+    }
+
+    /// Construct the script that computes the txid, in the same way as
+    /// [`TxReflectionBuilder::to_script`].
+    ///
+    /// If the transaction data is known to exceed 520 bytes, which is the limit of `OP_SHA256`,
+    /// the first hash is computed with [`Sha256StreamBuilder`] field by field, which requires the
+    /// lengths of all the script pub keys to be set. The hints are then generated by
+    /// [`TxReflectionBuilder::witness_txid`].
+    ///
+    /// Note: hashing in chunks is not available on-chain. Such a script exceeds the size of a
+    /// standard leaf (see [`Sha256StreamBuilder`]), so it is only usable off-chain, and a leaf
+    /// can only reflect a transaction whose data fits in 520 bytes.
+    ///
+    /// If the length of a provided script pub key is not set, the single `OP_SHA256` is used, and
+    /// the script fails if the transaction data exceeds 520 bytes.
+    pub fn to_txid_script(&self) -> Script {
+        if self.needs_stream() {
+            let mut stream = Sha256StreamBuilder::new();
+
+            let mut scripts = vec![stream.init()];
+            for (field_script, len) in self.field_scripts(Sha256StreamBuilder::STACK_SIZE) {
+                scripts.push(field_script);
+                scripts.push(stream.absorb(len.unwrap()));
+            }
+            scripts.push(stream.finalize());
+
+            script! {
+                for script in scripts.into_iter() {
+                    { script }
+                }
+                OP_SHA256
+            }
+        } else {
+            script! {
+                { self.to_script() }
+                OP_SHA256 OP_SHA256
+            }
+        }
+    }
+
+    /// Generate the hints and the stack elements for the script, from the previous transaction.
+    pub fn witness(&self, tx: &Transaction) -> FieldWitness {
+        let mut witness = FieldWitness::default();
+        for (kind, element, _) in self.field_data(tx) {
+            match kind {
+                SourceKind::Constant => {}
+                SourceKind::Hint => witness.hints.push(element),
+                SourceKind::FromStack => witness.stack.push(element),
+            }
+        }
+        witness
+    }
+
+    /// Generate the hints and the stack elements for [`TxReflectionBuilder::to_txid_script`],
+    /// from the previous transaction.
+    pub fn witness_txid(&self, tx: &Transaction) -> FieldWitness {
+        if self.needs_stream() {
+            let mut stream = Sha256StreamWitness::new();
+
+            let mut witness = FieldWitness::default();
+            for (kind, element, data) in self.field_data(tx) {
+                match kind {
+                    SourceKind::Constant => {}
+                    SourceKind::Hint => witness.hints.push(element),
+                    SourceKind::FromStack => witness.stack.push(element),
+                }
+                stream.absorb(&data);
+                witness.hints.append(&mut stream.hints);
+            }
+            witness.hints.extend(stream.finalize());
+            witness
+        } else {
+            self.witness(tx)
+        }
+    }
+
+    /// Collect the fields of the transaction, each with its source, the element as a hint or on
+    /// the stack, and the serialized field in the transaction data.
+    fn field_data(&self, tx: &Transaction) -> Vec<(SourceKind, Vec<u8>, Vec<u8>)> {
+        assert_eq!(tx.input.len(), self.inputs.len());
+        assert_eq!(tx.output.len(), self.outputs.len());
+
+        let mut fields = vec![];
+        fields.push(same_field(
+            self.version.kind(),
+            tx.version.0.to_le_bytes().to_vec(),
+        ));
+
+        let mut counter = vec![];
+        VarInt::from(tx.input.len())
+            .consensus_encode(&mut counter)
+            .unwrap();
+        fields.push(same_field(SourceKind::Constant, counter));
+
+        for (input, entry) in self.inputs.iter().zip(tx.input.iter()) {
+            assert!(entry.script_sig.is_empty());
+
+            let mut outpoint = vec![];
+            entry
+                .previous_output
+                .consensus_encode(&mut outpoint)
+                .unwrap();
+            fields.push(same_field(input.outpoint.kind(), outpoint));
+            fields.push(same_field(SourceKind::Constant, vec![0x00]));
+            fields.push(same_field(
+                input.sequence.kind(),
+                entry.sequence.to_consensus_u32().to_le_bytes().to_vec(),
+            ));
+        }
+
+        let mut counter = vec![];
+        VarInt::from(tx.output.len())
+            .consensus_encode(&mut counter)
+            .unwrap();
+        fields.push(same_field(SourceKind::Constant, counter));
+
+        for (output, entry) in self.outputs.iter().zip(tx.output.iter()) {
+            fields.push(same_field(
+                output.amount.kind(),
+                entry.value.to_sat().to_le_bytes().to_vec(),
+            ));
+
+            let mut script_pub_key = vec![];
+            entry
+                .script_pubkey
+                .consensus_encode(&mut script_pub_key)
+                .unwrap();
+            fields.push((
+                output.script_pub_key.kind(),
+                entry.script_pubkey.to_bytes(),
+                script_pub_key,
+            ));
+        }
+
+        fields.push(same_field(
+            self.lock_time.kind(),
+            tx.lock_time.to_consensus_u32().to_le_bytes().to_vec(),
+        ));
+        fields
+    }
+}
+
+/// A field whose element is the same as its serialization in the transaction data.
+fn same_field(kind: SourceKind, element: Vec<u8>) -> (SourceKind, Vec<u8>, Vec<u8>) {
+    (kind, element.clone(), element)
+}
+
+fn field_script<T>(
+    source: &FieldSource<T>,
+    from_constant: impl FnOnce(&T) -> Script,
+    from_provided: Script,
+    remaining: &mut usize,
+    above: usize,
+) -> Script {
+    match source {
+        FieldSource::Constant(v) => from_constant(v),
+        FieldSource::Hint => script! {
+            OP_HINT
+            { from_provided }
+        },
+        FieldSource::FromStack => {
+            // Skip the remaining elements for later fields as well as the elements above them, such
+            // as the partial transaction data.
+            let depth = *remaining - 1 + above;
+            *remaining -= 1;
+            script! {
+                { depth } OP_ROLL
+                { from_provided }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::treepp::*;
+    use crate::utils::test_utils::{get_rand_script_pub_key, get_rand_source, push_hint};
+    use crate::wizards::tx_reflection_builder::TxReflectionBuilder;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{
+        Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, WScriptHash, Witness,
+    };
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_tx_reflection_builder() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for _ in 0..50 {
+            let num_inputs = prng.gen_range(1..4);
+            let num_outputs = prng.gen_range(1..5);
+
+            let mut tx = Transaction {
+                version: Version(prng.gen_range(1..4)),
+                lock_time: LockTime::from_consensus(prng.gen()),
+                input: vec![],
+                output: vec![],
+            };
+            for _ in 0..num_inputs {
+                let mut bytes = [0u8; 20];
+                prng.fill_bytes(&mut bytes);
+                tx.input.push(TxIn {
+                    previous_output: OutPoint::new(Txid::hash(&bytes), prng.gen()),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence(prng.gen()),
+                    witness: Witness::new(),
+                });
+            }
+            for _ in 0..num_outputs {
+                tx.output.push(TxOut {
+                    value: Amount::from_sat(prng.gen_range(0..2_100_000_000_000_000)),
+                    script_pubkey: get_rand_script_pub_key(&mut prng),
+                });
+            }
+
+            let mut builder = TxReflectionBuilder::new(num_inputs, num_outputs);
+            builder.version = get_rand_source(&mut prng, tx.version, true);
+            for (input, entry) in builder.inputs.iter_mut().zip(tx.input.iter()) {
+                input.outpoint = get_rand_source(&mut prng, entry.previous_output, true);
+                input.sequence = get_rand_source(&mut prng, entry.sequence, true);
+            }
+            for (output, entry) in builder.outputs.iter_mut().zip(tx.output.iter()) {
+                output.amount = get_rand_source(&mut prng, entry.value, true);
+                output.script_pub_key =
+                    get_rand_source(&mut prng, entry.script_pubkey.clone(), true);
+            }
+            builder.lock_time = get_rand_source(&mut prng, tx.lock_time, true);
+
+            let witness = builder.witness(&tx);
+
+            let script = script! {
+                for hint in witness.hints.iter() {
+                    { hint.clone() }
+                }
+                for element in witness.stack.iter() {
+                    { element.clone() }
+                }
+                { builder.to_script() }
+                OP_SHA256 OP_SHA256
+                { AsRef::<[u8]>::as_ref(&tx.compute_txid()).to_vec() }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_tx_reflection_builder_txid() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for num_outputs in [1, 12] {
+            let num_inputs = 3;
+
+            let mut tx = Transaction {
+                version: Version(prng.gen_range(1..4)),
+                lock_time: LockTime::from_consensus(prng.gen()),
+                input: vec![],
+                output: vec![],
+            };
+            for _ in 0..num_inputs {
+                let mut bytes = [0u8; 20];
+                prng.fill_bytes(&mut bytes);
+                tx.input.push(TxIn {
+                    previous_output: OutPoint::new(Txid::hash(&bytes), prng.gen()),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence(prng.gen()),
+                    witness: Witness::new(),
+                });
+            }
+            for _ in 0..num_outputs {
+                tx.output.push(TxOut {
+                    value: Amount::from_sat(prng.gen_range(0..2_100_000_000_000_000)),
+                    script_pubkey: get_rand_script_pub_key(&mut prng),
+                });
+            }
+
+            let mut builder = TxReflectionBuilder::new(num_inputs, num_outputs);
+            builder.version = get_rand_source(&mut prng, tx.version, true);
+            for (input, entry) in builder.inputs.iter_mut().zip(tx.input.iter()) {
+                input.outpoint = get_rand_source(&mut prng, entry.previous_output, true);
+                input.sequence = get_rand_source(&mut prng, entry.sequence, true);
+            }
+            for (output, entry) in builder.outputs.iter_mut().zip(tx.output.iter()) {
+                output.amount = get_rand_source(&mut prng, entry.value, true);
+                output.script_pub_key =
+                    get_rand_source(&mut prng, entry.script_pubkey.clone(), true);
+                output.script_pub_key_len = Some(entry.script_pubkey.len());
+            }
+            builder.lock_time = get_rand_source(&mut prng, tx.lock_time, true);
+
+            let data_len = builder.data_len().unwrap();
+            assert_eq!(data_len, bitcoin::consensus::serialize(&tx).len());
+            assert_eq!(data_len > 520, num_outputs == 12);
+
+            let witness = builder.witness_txid(&tx);
+
+            let script = script! {
+                for hint in witness.hints.iter() {
+                    { push_hint(hint) }
+                }
+                for element in witness.stack.iter() {
+                    { element.clone() }
+                }
+                { builder.to_txid_script() }
+                { AsRef::<[u8]>::as_ref(&tx.compute_txid()).to_vec() }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_tx_reflection_builder_txid_unknown_len() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        // The script pub keys are hints by default, and their lengths are not set, so the single
+        // OP_SHA256 is used, which fails once the transaction data exceeds 520 bytes.
+        for num_outputs in [1, 12] {
+            let mut bytes = [0u8; 20];
+            prng.fill_bytes(&mut bytes);
+            let mut tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(Txid::hash(&bytes), 0),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                }],
+                output: vec![],
+            };
+            for _ in 0..num_outputs {
+                let mut bytes = [0u8; 32];
+                prng.fill_bytes(&mut bytes);
+                tx.output.push(TxOut {
+                    value: Amount::from_sat(prng.gen_range(0..2_100_000_000_000_000)),
+                    script_pubkey: ScriptBuf::new_p2wsh(&WScriptHash::hash(&bytes)),
+                });
+            }
+
+            let builder = TxReflectionBuilder::new(1, num_outputs);
+            assert!(builder.data_len().is_none());
+
+            let witness = builder.witness_txid(&tx);
+
+            let script = script! {
+                for hint in witness.hints.iter() {
+                    { push_hint(hint) }
+                }
+                { builder.to_txid_script() }
+                { AsRef::<[u8]>::as_ref(&tx.compute_txid()).to_vec() }
+                OP_EQUAL
+            };
+
+            let exec_result = execute_script(script);
+            assert_eq!(exec_result.success, num_outputs == 1);
+        }
+    }
+}